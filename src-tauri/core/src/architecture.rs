@@ -0,0 +1,300 @@
+use candle_core::Tensor;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// The architecture of the bundled model.
+const DEFAULT_ARCHITECTURE: &str = include_str!("../assets/architecture.json");
+
+/// A compact architecture for students distilled from the bundled model.
+const STUDENT_ARCHITECTURE: &str = include_str!("../assets/architectures/student.json");
+
+/// Declarative description of a network, built into a `ConvNet` by `ConvNet::new`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchitectureSpec {
+    /// Identifies the architecture in saved model metadata.
+    pub id: String,
+    /// Shape of a single input as `[channels, height, width]`.
+    pub input_shape: [usize; 3],
+    pub layers: Vec<LayerSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LayerSpec {
+    Conv {
+        /// Prefix of the layer's weights in the safetensors file.
+        name: String,
+        out_channels: usize,
+        kernel: usize,
+        #[serde(default = "default_stride")]
+        stride: usize,
+        #[serde(default)]
+        padding: usize,
+    },
+    MaxPool {
+        size: usize,
+        /// Defaults to `size`.
+        #[serde(default)]
+        stride: Option<usize>,
+    },
+    Dropout {
+        p: f32,
+    },
+    /// Normalizes each channel (or feature, once flattened) over the batch. The running
+    /// statistics used outside of training are saved with the weights.
+    BatchNorm {
+        name: String,
+        #[serde(default = "default_eps")]
+        eps: f64,
+        #[serde(default = "default_momentum")]
+        momentum: f64,
+    },
+    Flatten,
+    /// Flattens its input first if it isn't flat already. Leave `out_features` out to get
+    /// one output per label.
+    Linear {
+        name: String,
+        #[serde(default)]
+        out_features: Option<usize>,
+    },
+    Activation {
+        kind: Activation,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    Relu,
+    /// ReLU with a slope of 0.01 for negative inputs.
+    LeakyRelu,
+    Gelu,
+    Silu,
+    Tanh,
+}
+
+impl Activation {
+    pub fn apply(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        match self {
+            Activation::Relu => xs.relu(),
+            Activation::LeakyRelu => candle_nn::ops::leaky_relu(xs, 0.01),
+            Activation::Gelu => xs.gelu(),
+            Activation::Silu => xs.silu(),
+            Activation::Tanh => xs.tanh(),
+        }
+    }
+}
+
+/// Shape of the features flowing between layers, excluding the batch dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum FeatureShape {
+    Spatial {
+        channels: usize,
+        height: usize,
+        width: usize,
+    },
+    Flat(usize),
+}
+
+impl FeatureShape {
+    /// Size of the dimension batch norm normalizes over.
+    pub fn channels(&self) -> usize {
+        match *self {
+            FeatureShape::Spatial { channels, .. } => channels,
+            FeatureShape::Flat(n) => n,
+        }
+    }
+
+    pub fn elem_count(&self) -> usize {
+        match *self {
+            FeatureShape::Spatial {
+                channels,
+                height,
+                width,
+            } => channels * height * width,
+            FeatureShape::Flat(n) => n,
+        }
+    }
+}
+
+fn default_stride() -> usize {
+    1
+}
+
+fn default_eps() -> f64 {
+    1e-5
+}
+
+fn default_momentum() -> f64 {
+    0.1
+}
+
+impl Default for ArchitectureSpec {
+    fn default() -> Self {
+        Self::from_json(DEFAULT_ARCHITECTURE).expect("Bundled architecture.json is invalid")
+    }
+}
+
+impl ArchitectureSpec {
+    /// The architecture `distill` trains by default, with about a fifth of the bundled
+    /// model's parameters.
+    pub fn student() -> Self {
+        Self::from_json(STUDENT_ARCHITECTURE).expect("Bundled student.json is invalid")
+    }
+
+    pub fn from_json(json: &str) -> candle_core::Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| candle_core::Error::Msg(format!("Invalid architecture spec: {}", e)))
+    }
+
+    pub fn from_json_file(path: &Path) -> candle_core::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Self::from_json(&json)
+            .map_err(|e| candle_core::Error::Msg(format!("Failed to load {:?}: {}", path, e)))
+    }
+
+    pub fn to_json(&self) -> candle_core::Result<String> {
+        serde_json::to_string(self).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to encode architecture spec: {}", e))
+        })
+    }
+
+    /// Names of the layers that own weights, in forward order.
+    pub fn layer_names(&self) -> Vec<&str> {
+        self.layers
+            .iter()
+            .filter_map(|layer| match layer {
+                LayerSpec::Conv { name, .. }
+                | LayerSpec::BatchNorm { name, .. }
+                | LayerSpec::Linear { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Names of the linear layers with one output per label.
+    pub fn label_sized_layers(&self) -> Vec<&str> {
+        self.layers
+            .iter()
+            .filter_map(|layer| match layer {
+                LayerSpec::Linear {
+                    name,
+                    out_features: None,
+                } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Infers the feature shape before every layer, with the network's output shape last.
+    /// `num_labels` is the size of linear layers without `out_features`.
+    pub fn shapes(&self, num_labels: usize) -> candle_core::Result<Vec<FeatureShape>> {
+        let names = self.layer_names();
+        let unique_names = names.iter().collect::<HashSet<_>>();
+        if unique_names.len() != names.len() {
+            return Err(candle_core::Error::Msg(format!(
+                "Architecture '{}' has duplicate layer names: {:?}",
+                self.id, names
+            )));
+        }
+
+        let [channels, height, width] = self.input_shape;
+        let mut shapes = vec![FeatureShape::Spatial {
+            channels,
+            height,
+            width,
+        }];
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let input = shapes[i];
+            let output = layer.output_shape(input, num_labels).map_err(|e| {
+                candle_core::Error::Msg(format!(
+                    "Layer {} of architecture '{}' ({:?}) can't take input {:?}: {}",
+                    i, self.id, layer, input, e
+                ))
+            })?;
+            shapes.push(output);
+        }
+
+        Ok(shapes)
+    }
+}
+
+impl LayerSpec {
+    fn output_shape(&self, input: FeatureShape, num_labels: usize) -> Result<FeatureShape, String> {
+        match (self, input) {
+            (
+                LayerSpec::Conv {
+                    out_channels,
+                    kernel,
+                    stride,
+                    padding,
+                    ..
+                },
+                FeatureShape::Spatial { height, width, .. },
+            ) => Ok(FeatureShape::Spatial {
+                channels: *out_channels,
+                height: window_output(height + 2 * padding, *kernel, *stride)?,
+                width: window_output(width + 2 * padding, *kernel, *stride)?,
+            }),
+            (
+                LayerSpec::MaxPool { size, stride },
+                FeatureShape::Spatial {
+                    channels,
+                    height,
+                    width,
+                },
+            ) => {
+                let stride = stride.unwrap_or(*size);
+                Ok(FeatureShape::Spatial {
+                    channels,
+                    height: window_output(height, *size, stride)?,
+                    width: window_output(width, *size, stride)?,
+                })
+            }
+            (LayerSpec::Conv { .. } | LayerSpec::MaxPool { .. }, FeatureShape::Flat(_)) => {
+                Err("expected spatial features but they were already flattened".to_string())
+            }
+            (LayerSpec::Flatten, input) => Ok(FeatureShape::Flat(input.elem_count())),
+            (LayerSpec::Linear { out_features, .. }, _) => {
+                Ok(FeatureShape::Flat(out_features.unwrap_or(num_labels)))
+            }
+            (LayerSpec::Dropout { p }, input) => {
+                if (0.0..1.0).contains(p) {
+                    Ok(input)
+                } else {
+                    Err(format!("dropout probability must be in [0, 1), got {}", p))
+                }
+            }
+            (LayerSpec::BatchNorm { eps, momentum, .. }, input) => {
+                if *eps < 0.0 {
+                    Err(format!("batch norm eps can't be negative, got {}", eps))
+                } else if !(0.0..=1.0).contains(momentum) {
+                    Err(format!(
+                        "batch norm momentum must be in [0, 1], got {}",
+                        momentum
+                    ))
+                } else {
+                    Ok(input)
+                }
+            }
+            (LayerSpec::Activation { .. }, input) => Ok(input),
+        }
+    }
+}
+
+/// Output size of a sliding window of `size` moved by `stride` over `input` elements.
+fn window_output(input: usize, size: usize, stride: usize) -> Result<usize, String> {
+    if size == 0 || stride == 0 {
+        return Err("window size and stride must be positive".to_string());
+    }
+    if size > input {
+        return Err(format!(
+            "window of {} is larger than input of {}",
+            size, input
+        ));
+    }
+    Ok((input - size) / stride + 1)
+}
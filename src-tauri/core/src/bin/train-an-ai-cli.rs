@@ -0,0 +1,675 @@
+//! Headless access to the model, so training runs can be scripted and reproduced without
+//! launching the app.
+
+use candle_core::Device;
+use candle_nn::VarMap;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::str::FromStr;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::distill::{self, DistillationSettings};
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::{ConvNet, TrainingArgs};
+use train_an_ai_core::quantize::{self, DEFAULT_CALIBRATION_SAMPLES};
+use train_an_ai_core::strokes::{self, RasterConfig};
+use train_an_ai_core::utils::{self, DatasetMode};
+use train_an_ai_core::{drawings, import, metadata, onnx, prune};
+
+const USAGE: &str = "\
+Usage: train-an-ai-cli <command> [options]
+
+Commands:
+  train    --drawings <dir> --out <model> [--model <model>] [--architecture <spec.json>]
+           [--labels <labels.json>] [--epochs <n>] [--learning-rate <f>] [--batch-size <n>]
+           [--mnist-ratio <f>] [--freeze <layer,...>] [--patience <n>] [--seed <n>]
+           [--tensorboard <run dir>] [--partial-drawings <true|false>]
+      Fine-tunes --model (or a fresh --architecture model) on the drawings in --drawings.
+      Passing --labels with --model expands the model to the new label set first.
+      Runs with the same --seed and inputs produce the same weights. --tensorboard writes
+      TensorBoard event files for the run. Unfinished versions of the drawings saved with
+      strokes are trained on too unless --partial-drawings is false, here and for prune and
+      distill.
+  eval     --model <model> [--drawings <dir>] [--batch-size <n>]
+      Reports accuracy on the MNIST test set, or on the drawings in --drawings.
+  predict  --model <model> <image>...
+      Prints the predicted label of every image.
+  export   --model <model> --out <path> [--format <safetensors|onnx>]
+      Writes the model with up to date metadata, or as an ONNX graph for use in other tools.
+  import   --weights <state dict> --out <model> [--architecture <spec.json>]
+           [--labels <labels.json>]
+      Converts a PyTorch state dict (.pt/.pth) or numpy .npz of an externally trained model
+      with the same architecture into a model file. Layer names are matched by name, or in
+      order when they differ, and TensorFlow style kernel layouts are converted.
+  quantize --model <model> --drawings <dir> [--samples <n>] [--batch-size <n>]
+      Calibrates int8 inference on a sample of the drawings in --drawings, saves the
+      calibration next to the model and compares accuracy on the MNIST test set and
+      prediction latency against the f32 model.
+  prune    --model <model> --drawings <dir> --out <model> [--amount <f>] [--epochs <n>]
+           [--partial-drawings <true|false>]
+      Removes the --amount share (default 0.5) of the weakest conv channels and fc neurons,
+      fine-tunes on the drawings in --drawings and reports parameter count, file size and
+      MNIST test accuracy before and after.
+  distill  --teacher <model> --drawings <dir> --out <model> [--architecture <spec.json>]
+           [--temperature <f>] [--alpha <f>] [--epochs <n>] [--mnist-ratio <f>] [--seed <n>]
+           [--partial-drawings <true|false>]
+      Trains a fresh compact model (or --architecture) to match the teacher's outputs
+      softened by --temperature (default 4) as well as the labels of the drawings, weighing
+      the two by --alpha (default 0.7), and reports size and accuracy of both.
+  rerender --drawings <dir> [--size <n>] [--line-width <f>] [--blur <f>]
+           [--antialias <true|false>] [--labels <labels.json> | --model <model>]
+      Renders every drawing in --drawings that was saved with strokes again with the given
+      settings (unset ones keep their current value), which new drawings then use too.
+      The drawings are labelled with --labels, or the labels --model predicts.
+  import-drawings --source <dir|zip> --drawings <dir>
+           [--labels <labels.json> | --model <model>]
+      Adds the images in a folder or zip archive with a folder per label (<label>/*.png)
+      to the drawings in --drawings, normalized like the app's drawings and without
+      duplicates, and reports which files were accepted or rejected. The label folders
+      are those of --labels, or of the labels --model predicts.
+  export-drawings --drawings <dir> --out <dir|zip> [--format <idx|zip>]
+           [--labels <labels.json> | --model <model>]
+      Writes the drawings in --drawings as MNIST style IDX files into the --out folder, or
+      as a zip archive of 28x28 PNGs in a folder per label with a manifest.json. The
+      drawings are labelled with --labels, or the labels --model predicts.
+  inspect  --model <model>
+      Prints the model's metadata, layer shapes and tensors.
+";
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    let Some((command, rest)) = args.split_first() else {
+        eprint!("{}", USAGE);
+        exit(2);
+    };
+
+    let result = Args::new(rest).and_then(|args| match command.as_str() {
+        "train" => train(&args),
+        "eval" => eval(&args),
+        "predict" => predict(&args),
+        "export" => export(&args),
+        "import" => import(&args),
+        "quantize" => quantize(&args),
+        "prune" => prune(&args),
+        "distill" => distill(&args),
+        "rerender" => rerender(&args),
+        "import-drawings" => import_drawings(&args),
+        "export-drawings" => export_drawings(&args),
+        "inspect" => inspect(&args),
+        "help" | "--help" | "-h" => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("Unknown command '{}'\n\n{}", command, USAGE)),
+    });
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
+
+fn train(args: &Args) -> Result<(), String> {
+    args.allow(&[
+        "drawings",
+        "out",
+        "model",
+        "architecture",
+        "labels",
+        "epochs",
+        "learning-rate",
+        "batch-size",
+        "mnist-ratio",
+        "freeze",
+        "patience",
+        "seed",
+        "tensorboard",
+        "partial-drawings",
+    ])?;
+
+    let drawings_dir = PathBuf::from(args.required("drawings")?);
+    let out = args.required("out")?;
+    let labels = args
+        .get("labels")
+        .map(|path| LabelSet::from_json_file(Path::new(path)))
+        .transpose()
+        .map_err(to_string)?;
+
+    let mut vm = VarMap::new();
+    let model = match (args.get("model"), args.get("architecture")) {
+        (Some(_), Some(_)) => {
+            return Err(
+                "--model and --architecture can't be combined, a saved model \
+                        already records its architecture"
+                    .to_string(),
+            )
+        }
+        (Some(path), None) => match &labels {
+            Some(labels) => ConvNet::expand_from_file(&mut vm, &PathBuf::from(path), labels),
+            None => ConvNet::new_from_file(&mut vm, &PathBuf::from(path)),
+        },
+        (None, architecture) => {
+            let spec = match architecture {
+                Some(path) => ArchitectureSpec::from_json_file(Path::new(path)),
+                None => Ok(ArchitectureSpec::default()),
+            }
+            .map_err(to_string)?;
+            ConvNet::new(&mut vm, &spec, &labels.unwrap_or_else(LabelSet::digits))
+        }
+    }
+    .map_err(to_string)?;
+
+    let mode = match args.parse::<f32>("mnist-ratio")? {
+        Some(mnist_ratio) => DatasetMode::Replay { mnist_ratio },
+        None => DatasetMode::DrawingsOnly,
+    };
+    let seed = args.parse("seed")?;
+    let dataset = utils::create_dataset(
+        &drawings_dir,
+        mode,
+        partial_drawings_arg(args)?,
+        model.labels(),
+        seed,
+    )
+    .map_err(to_string)?;
+
+    let training_args = TrainingArgs {
+        epochs: args.parse("epochs")?.unwrap_or(1),
+        learning_rate: args.parse("learning-rate")?.unwrap_or(0.01),
+        batch_size: args.parse("batch-size")?.unwrap_or(10),
+        load: None,
+        save: Some(out.to_string()),
+        frozen_layers: args
+            .get("freeze")
+            .map(|layers| layers.split(',').map(|l| l.trim().to_string()).collect())
+            .unwrap_or_default(),
+        patience: args.parse("patience")?,
+        log_dir: args.get("tensorboard").map(str::to_string),
+        seed,
+        distillation: None,
+    };
+
+    let summary = model
+        .train(&dataset, &training_args, &mut vm)
+        .map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&summary).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn eval(args: &Args) -> Result<(), String> {
+    args.allow(&["model", "drawings", "batch-size"])?;
+
+    let (model, _) = load_model(args)?;
+    let dev = Device::cuda_if_available(0).map_err(to_string)?;
+
+    let dataset = match args.get("drawings") {
+        Some(dir) => {
+            let (images, labels) =
+                utils::load_drawings(&PathBuf::from(dir), model.labels()).map_err(to_string)?;
+            candle_datasets::vision::Dataset {
+                train_images: images.clone(),
+                train_labels: labels.clone(),
+                test_images: images,
+                test_labels: labels,
+                labels: model.labels().len(),
+            }
+        }
+        None => utils::get_labelled_mnist_dataset(model.labels()).map_err(to_string)?,
+    };
+
+    // Batches are never larger than the test set, or nothing would be evaluated
+    let n_samples = dataset.test_images.dim(0).map_err(to_string)?;
+    let batch_size = args
+        .parse::<usize>("batch-size")?
+        .unwrap_or(100)
+        .clamp(1, n_samples.max(1));
+
+    model.test(&dev, &dataset, batch_size).map_err(to_string)?;
+    Ok(())
+}
+
+fn predict(args: &Args) -> Result<(), String> {
+    args.allow(&["model"])?;
+
+    if args.positional.is_empty() {
+        return Err("predict needs at least one image".to_string());
+    }
+
+    let (model, _) = load_model(args)?;
+    let dev = Device::cuda_if_available(0).map_err(to_string)?;
+
+    for path in args.positional.iter() {
+        let image =
+            utils::image_path_to_formatted_tensor(&PathBuf::from(path), &dev).map_err(to_string)?;
+        let label = model.predict(&image, &dev).map_err(to_string)?;
+        println!("{}\t{}", path, label);
+    }
+
+    Ok(())
+}
+
+fn export(args: &Args) -> Result<(), String> {
+    args.allow(&["model", "out", "format"])?;
+
+    let (model, vm) = load_model(args)?;
+    let out = args.required("out")?;
+
+    match args.get("format").unwrap_or("safetensors") {
+        "safetensors" => {}
+        "onnx" => {
+            onnx::export(&model, &vm, Path::new(out)).map_err(to_string)?;
+            println!("exported ONNX model to {}", out);
+            return Ok(());
+        }
+        other => return Err(format!("Unknown --format '{}'", other)),
+    }
+
+    // Carry over the training summary of the original file
+    let training = metadata::read_metadata(Path::new(args.required("model")?))
+        .map_err(to_string)?
+        .and_then(|m| m.training);
+
+    model
+        .save(&vm, Path::new(out), training)
+        .map_err(to_string)?;
+
+    println!("exported model to {}", out);
+    Ok(())
+}
+
+fn import(args: &Args) -> Result<(), String> {
+    args.allow(&["weights", "out", "architecture", "labels"])?;
+
+    let weights = args.required("weights")?;
+    let out = args.required("out")?;
+    let spec = match args.get("architecture") {
+        Some(path) => ArchitectureSpec::from_json_file(Path::new(path)),
+        None => Ok(ArchitectureSpec::default()),
+    }
+    .map_err(to_string)?;
+    let labels = labels_arg(args)?;
+
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &spec, &labels).map_err(to_string)?;
+    let imported = import::load_into(&model, &vm, Path::new(weights)).map_err(to_string)?;
+
+    for tensor in imported.iter() {
+        println!(
+            "  {:<24} <- {:<32} {:?}{}",
+            tensor.target,
+            tensor.source,
+            tensor.shape,
+            tensor
+                .transform
+                .as_ref()
+                .map(|t| format!(" ({})", t))
+                .unwrap_or_default()
+        );
+    }
+
+    model.save(&vm, Path::new(out), None).map_err(to_string)?;
+
+    println!("imported {} tensors into {}", imported.len(), out);
+    Ok(())
+}
+
+fn quantize(args: &Args) -> Result<(), String> {
+    args.allow(&["model", "drawings", "samples", "batch-size"])?;
+
+    let (model, vm) = load_model(args)?;
+    let dev = Device::cuda_if_available(0).map_err(to_string)?;
+    let drawings_dir = PathBuf::from(args.required("drawings")?);
+
+    // Calibrates on the drawings as they were saved and tests on MNIST
+    let dataset = utils::create_dataset(
+        &drawings_dir,
+        DatasetMode::DrawingsOnly,
+        false,
+        model.labels(),
+        None,
+    )
+    .map_err(to_string)?;
+
+    let n_samples = dataset.test_images.dim(0).map_err(to_string)?;
+    let batch_size = args
+        .parse::<usize>("batch-size")?
+        .unwrap_or(100)
+        .clamp(1, n_samples.max(1));
+    let samples = args
+        .parse("samples")?
+        .unwrap_or(DEFAULT_CALIBRATION_SAMPLES);
+
+    let (calibration, report) =
+        quantize::compare(&model, &vm, &dataset, samples, batch_size, &dev).map_err(to_string)?;
+    calibration
+        .save(Path::new(args.required("model")?))
+        .map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn prune(args: &Args) -> Result<(), String> {
+    args.allow(&[
+        "model",
+        "drawings",
+        "out",
+        "amount",
+        "epochs",
+        "partial-drawings",
+    ])?;
+
+    let model_path = PathBuf::from(args.required("model")?);
+    let out = PathBuf::from(args.required("out")?);
+    let dev = Device::cuda_if_available(0).map_err(to_string)?;
+
+    let (_, labels) = metadata::read_spec_and_labels(&model_path).map_err(to_string)?;
+    let dataset = utils::create_dataset(
+        &PathBuf::from(args.required("drawings")?),
+        DatasetMode::DrawingsOnly,
+        partial_drawings_arg(args)?,
+        &labels,
+        None,
+    )
+    .map_err(to_string)?;
+
+    let report = prune::prune_and_fine_tune(
+        &model_path,
+        &out,
+        args.parse("amount")?.unwrap_or(0.5),
+        args.parse("epochs")?.unwrap_or(1),
+        &dataset,
+        &dev,
+    )
+    .map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn distill(args: &Args) -> Result<(), String> {
+    args.allow(&[
+        "teacher",
+        "drawings",
+        "out",
+        "architecture",
+        "temperature",
+        "alpha",
+        "epochs",
+        "mnist-ratio",
+        "seed",
+        "partial-drawings",
+    ])?;
+
+    let teacher_path = PathBuf::from(args.required("teacher")?);
+    let out = PathBuf::from(args.required("out")?);
+    let dev = Device::cuda_if_available(0).map_err(to_string)?;
+
+    let student = match args.get("architecture") {
+        Some(path) => ArchitectureSpec::from_json_file(Path::new(path)),
+        None => Ok(ArchitectureSpec::student()),
+    }
+    .map_err(to_string)?;
+    let defaults = DistillationSettings::default();
+    let settings = DistillationSettings {
+        temperature: args.parse("temperature")?.unwrap_or(defaults.temperature),
+        alpha: args.parse("alpha")?.unwrap_or(defaults.alpha),
+    };
+
+    let (_, labels) = metadata::read_spec_and_labels(&teacher_path).map_err(to_string)?;
+    let mode = match args.parse::<f32>("mnist-ratio")? {
+        Some(mnist_ratio) => DatasetMode::Replay { mnist_ratio },
+        None => DatasetMode::DrawingsOnly,
+    };
+    let seed = args.parse("seed")?;
+    let dataset = utils::create_dataset(
+        &PathBuf::from(args.required("drawings")?),
+        mode,
+        partial_drawings_arg(args)?,
+        &labels,
+        seed,
+    )
+    .map_err(to_string)?;
+
+    let training_args = TrainingArgs {
+        epochs: args.parse("epochs")?.unwrap_or(1),
+        learning_rate: 0.01,
+        batch_size: 10,
+        load: None,
+        save: None,
+        frozen_layers: Vec::new(),
+        patience: None,
+        log_dir: None,
+        seed,
+        distillation: None,
+    };
+    let report = distill::distill(
+        &teacher_path,
+        &student,
+        &out,
+        &settings,
+        training_args,
+        &dataset,
+        &dev,
+    )
+    .map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn rerender(args: &Args) -> Result<(), String> {
+    args.allow(&[
+        "drawings",
+        "size",
+        "line-width",
+        "blur",
+        "antialias",
+        "labels",
+        "model",
+    ])?;
+
+    let drawings_dir = PathBuf::from(args.required("drawings")?);
+    let labels = labels_arg(args)?;
+
+    let current = RasterConfig::load(&drawings_dir).map_err(to_string)?;
+    let config = RasterConfig {
+        size: args.parse("size")?.unwrap_or(current.size),
+        line_width: args.parse("line-width")?.unwrap_or(current.line_width),
+        antialias: args.parse("antialias")?.unwrap_or(current.antialias),
+        blur: args.parse("blur")?.unwrap_or(current.blur),
+    };
+
+    let report = strokes::rerender_drawings(&drawings_dir, &config, &labels).map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn import_drawings(args: &Args) -> Result<(), String> {
+    args.allow(&["source", "drawings", "labels", "model"])?;
+
+    let labels = labels_arg(args)?;
+
+    let report = drawings::import_drawings(
+        Path::new(args.required("source")?),
+        Path::new(args.required("drawings")?),
+        &labels,
+    )
+    .map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn export_drawings(args: &Args) -> Result<(), String> {
+    args.allow(&["drawings", "out", "format", "labels", "model"])?;
+
+    let labels = labels_arg(args)?;
+    let drawings_dir = Path::new(args.required("drawings")?);
+    let out = Path::new(args.required("out")?);
+
+    let report = match args.get("format").unwrap_or("idx") {
+        "idx" => drawings::export_idx(drawings_dir, &labels, out),
+        "zip" => drawings::export_zip(drawings_dir, &labels, out),
+        other => return Err(format!("Unknown --format '{}'", other)),
+    }
+    .map_err(to_string)?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(to_string)?
+    );
+    Ok(())
+}
+
+fn inspect(args: &Args) -> Result<(), String> {
+    args.allow(&["model"])?;
+
+    let path = PathBuf::from(args.required("model")?);
+
+    match metadata::read_metadata(&path).map_err(to_string)? {
+        Some(metadata) => println!(
+            "metadata:\n{}",
+            serde_json::to_string_pretty(&metadata).map_err(to_string)?
+        ),
+        None => println!("metadata: none (default architecture and digit labels assumed)"),
+    }
+
+    let (spec, labels) = metadata::read_spec_and_labels(&path).map_err(to_string)?;
+    let shapes = spec.shapes(labels.len()).map_err(to_string)?;
+
+    println!("\nlayers of '{}':", spec.id);
+    println!("  {:<40} {:?}", "input", shapes[0]);
+    for (layer, shape) in spec.layers.iter().zip(shapes.iter().skip(1)) {
+        println!("  {:<40} {:?}", format!("{:?}", layer), shape);
+    }
+
+    let tensors = candle_core::safetensors::load(&path, &Device::Cpu).map_err(to_string)?;
+    let mut names = tensors.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    println!("\ntensors:");
+    for name in names.iter() {
+        println!("  {:<24} {:?}", name, tensors[*name].dims());
+    }
+    println!(
+        "\n{} parameters",
+        tensors.values().map(|t| t.elem_count()).sum::<usize>()
+    );
+
+    Ok(())
+}
+
+fn load_model(args: &Args) -> Result<(ConvNet, VarMap), String> {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new_from_file(&mut vm, &PathBuf::from(args.required("model")?))
+        .map_err(to_string)?;
+    Ok((model, vm))
+}
+
+/// The labels in the `--labels` file, or those the `--model` predicts, or the digits when
+/// neither is given.
+fn labels_arg(args: &Args) -> Result<LabelSet, String> {
+    match (args.get("labels"), args.get("model")) {
+        (Some(_), Some(_)) => Err("--labels and --model can't be combined, a saved model \
+                    already records its labels"
+            .to_string()),
+        (Some(path), None) => LabelSet::from_json_file(Path::new(path)).map_err(to_string),
+        (None, Some(path)) => metadata::read_spec_and_labels(Path::new(path))
+            .map(|(_, labels)| labels)
+            .map_err(to_string),
+        (None, None) => Ok(LabelSet::digits()),
+    }
+}
+
+/// `--partial-drawings`, on unless set to false.
+fn partial_drawings_arg(args: &Args) -> Result<bool, String> {
+    Ok(args.parse("partial-drawings")?.unwrap_or(true))
+}
+
+fn to_string<E: std::fmt::Display>(e: E) -> String {
+    e.to_string()
+}
+
+/// `--flag value` pairs and positional arguments of a subcommand.
+struct Args {
+    flags: HashMap<String, String>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn new(args: &[String]) -> Result<Self, String> {
+        let mut flags = HashMap::new();
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(flag) => {
+                    let (name, value) = match flag.split_once('=') {
+                        Some((name, value)) => (name, value.to_string()),
+                        None => {
+                            let value = args
+                                .next()
+                                .ok_or_else(|| format!("--{} needs a value", flag))?;
+                            (flag, value.clone())
+                        }
+                    };
+                    flags.insert(name.to_string(), value);
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        Ok(Self { flags, positional })
+    }
+
+    /// Rejects any flag not in `allowed`, so typos don't get silently ignored.
+    fn allow(&self, allowed: &[&str]) -> Result<(), String> {
+        match self.flags.keys().find(|f| !allowed.contains(&f.as_str())) {
+            Some(flag) => Err(format!("Unknown option --{}\n\n{}", flag, USAGE)),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&self, flag: &str) -> Option<&str> {
+        self.flags.get(flag).map(|v| v.as_str())
+    }
+
+    fn required(&self, flag: &str) -> Result<&str, String> {
+        self.get(flag).ok_or_else(|| format!("Missing --{}", flag))
+    }
+
+    fn parse<T: FromStr>(&self, flag: &str) -> Result<Option<T>, String>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.get(flag)
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|e| format!("Invalid value '{}' for --{}: {}", v, flag, e))
+            })
+            .transpose()
+    }
+}
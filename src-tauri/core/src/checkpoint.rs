@@ -0,0 +1,172 @@
+use crate::metadata::ModelMetadata;
+use candle_core::{Device, Tensor};
+use candle_nn::VarMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Saves `varmap` to `path` without ever leaving a half-written model behind.
+///
+/// The weights are written to a temporary file next to `path`, reloaded to make sure every
+/// variable made it to disk, and then renamed over `path`. The model being replaced is kept
+/// as a backup so `rollback` can restore it. `metadata` goes into the file's header.
+pub fn save_atomic(
+    varmap: &VarMap,
+    path: &Path,
+    metadata: &ModelMetadata,
+) -> candle_core::Result<()> {
+    let tmp_path = sibling_path(path, ".tmp");
+
+    save_with_metadata(varmap, &tmp_path, metadata)?;
+
+    if let Err(e) = verify(varmap, &tmp_path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    commit(&tmp_path, path)
+}
+
+/// Copies the model at `source` over `path` with the same guarantees as `save_atomic`.
+pub fn install(source: &Path, path: &Path) -> candle_core::Result<()> {
+    let tmp_path = sibling_path(path, ".tmp");
+
+    fs::copy(source, &tmp_path)?;
+
+    if let Err(e) = candle_core::safetensors::load(&tmp_path, &Device::Cpu) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    commit(&tmp_path, path)
+}
+
+/// Like `VarMap::save`, but also writes `metadata` into the safetensors header.
+pub fn save_with_metadata(
+    varmap: &VarMap,
+    path: &Path,
+    metadata: &ModelMetadata,
+) -> candle_core::Result<()> {
+    let data = varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?;
+
+    let tensors = data
+        .iter()
+        .map(|(name, var)| (name.as_str(), var.as_tensor()))
+        .collect::<Vec<(&str, &Tensor)>>();
+
+    safetensors::serialize_to_file(tensors, &Some(metadata.to_header()?), path)
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to save {:?}: {}", path, e)))
+}
+
+/// Restores the model that the last `save_atomic` to `path` replaced.
+pub fn rollback(path: &Path) -> candle_core::Result<()> {
+    let backup = backup_path(path);
+
+    if !backup.exists() {
+        return Err(candle_core::Error::Msg(format!(
+            "No previous model to roll back to at {:?}",
+            backup
+        )));
+    }
+
+    // Don't swap in a backup that can't be read back
+    candle_core::safetensors::load(&backup, &Device::Cpu)?;
+
+    fs::rename(&backup, path)?;
+    sync_parent(path)?;
+
+    Ok(())
+}
+
+/// Path of the backup kept by `save_atomic`, e.g. `model.safetensors.prev`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling_path(path, ".prev")
+}
+
+/// Backs up the model at `path`, then renames the verified `tmp_path` over it.
+///
+/// The backup goes through a temporary file of its own, so a crash while copying can't leave
+/// a torn backup in place of a good one.
+fn commit(tmp_path: &Path, path: &Path) -> candle_core::Result<()> {
+    if path.exists() {
+        let backup = backup_path(path);
+        let backup_tmp = sibling_path(&backup, ".tmp");
+        if let Err(e) = fs::copy(path, &backup_tmp) {
+            let _ = fs::remove_file(&backup_tmp);
+            return Err(e.into());
+        }
+        replace(&backup_tmp, &backup)?;
+    }
+
+    replace(tmp_path, path)
+}
+
+/// Renames the fully written `tmp_path` over `path` so the new contents survive a crash: the
+/// file is flushed to disk before the rename and the directory entry after it.
+pub(crate) fn replace(tmp_path: &Path, path: &Path) -> candle_core::Result<()> {
+    if let Err(e) = fs::File::open(tmp_path).and_then(|file| file.sync_all()) {
+        let _ = fs::remove_file(tmp_path);
+        return Err(e.into());
+    }
+
+    fs::rename(tmp_path, path)?;
+    sync_parent(path)
+}
+
+/// Flushes the directory holding `path`, making renames into it durable. Directories can't be
+/// opened as files on Windows, where renames are flushed by the file system itself.
+fn sync_parent(path: &Path) -> candle_core::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+pub(crate) fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Checks that the file at `path` holds every variable in `varmap` with the right shape.
+fn verify(varmap: &VarMap, path: &Path) -> candle_core::Result<()> {
+    let saved = candle_core::safetensors::load(path, &Device::Cpu)?;
+
+    let data = varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?;
+
+    for (name, var) in data.iter() {
+        match saved.get(name) {
+            Some(tensor) if tensor.shape() == var.shape() => {}
+            Some(tensor) => {
+                return Err(candle_core::Error::Msg(format!(
+                    "Saved '{}' has shape {:?}, expected {:?}",
+                    name,
+                    tensor.shape(),
+                    var.shape()
+                )))
+            }
+            None => {
+                return Err(candle_core::Error::Msg(format!(
+                    "'{}' is missing from the saved model",
+                    name
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
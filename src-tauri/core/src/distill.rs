@@ -0,0 +1,127 @@
+use crate::architecture::ArchitectureSpec;
+use crate::model::{ConvNet, ModelStats, TrainingArgs, TrainingSummary};
+use candle_core::{Device, Tensor, D};
+use candle_datasets::vision::Dataset;
+use candle_nn::{loss, ops, VarMap};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Softens the teacher's probabilities enough that the student learns which wrong labels a
+/// drawing resembles, not just the right one.
+pub const DEFAULT_TEMPERATURE: f64 = 4.0;
+
+/// Weight of the soft targets in the loss, the hard labels get the rest.
+pub const DEFAULT_ALPHA: f64 = 0.7;
+
+/// How a student model is trained from its teacher, recorded with its hyperparameters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DistillationSettings {
+    pub temperature: f64,
+    /// Weight of the soft targets in the loss, in [0, 1].
+    pub alpha: f64,
+}
+
+impl Default for DistillationSettings {
+    fn default() -> Self {
+        Self {
+            temperature: DEFAULT_TEMPERATURE,
+            alpha: DEFAULT_ALPHA,
+        }
+    }
+}
+
+/// Makes `ConvNet::train` fit the teacher's softened outputs as well as the dataset labels.
+pub struct Distillation {
+    /// Must predict the same labels, in the same order, as the student.
+    pub teacher: ConvNet,
+    pub temperature: f64,
+    pub alpha: f64,
+}
+
+impl Distillation {
+    /// `alpha * T² * KL(teacher ‖ student)` on the logits divided by the temperature `T`, plus
+    /// `(1 - alpha)` times the usual cross-entropy with `labels`. The `T²` keeps the soft
+    /// targets' gradients on the same scale whatever the temperature.
+    pub fn loss(
+        &self,
+        images: &Tensor,
+        student_logits: &Tensor,
+        labels: &Tensor,
+    ) -> candle_core::Result<Tensor> {
+        let teacher_logits = self.teacher.logits(images)?.detach();
+
+        let teacher_log_sm = ops::log_softmax(&(teacher_logits / self.temperature)?, D::Minus1)?;
+        let student_log_sm = ops::log_softmax(&(student_logits / self.temperature)?, D::Minus1)?;
+        let batch_size = images.dim(0)? as f64;
+        let soft = (teacher_log_sm.exp()? * (&teacher_log_sm - &student_log_sm)?)?
+            .sum_all()?
+            .affine(self.temperature * self.temperature / batch_size, 0.0)?;
+
+        let hard = loss::nll(&ops::log_softmax(student_logits, D::Minus1)?, labels)?;
+
+        soft.affine(self.alpha, 0.0)? + hard.affine(1.0 - self.alpha, 0.0)?
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DistillationReport {
+    pub settings: DistillationSettings,
+    pub teacher: ModelStats,
+    pub student: ModelStats,
+    pub training: TrainingSummary,
+}
+
+/// Trains a fresh `student` architecture on `data` with the model saved at `teacher_path` as
+/// teacher (see `Distillation`) and saves it to `out`, reporting parameter count, file size
+/// and test accuracy of both.
+///
+/// `args.load` and `args.save` are ignored, the student always starts from scratch.
+pub fn distill(
+    teacher_path: &Path,
+    student: &ArchitectureSpec,
+    out: &Path,
+    settings: &DistillationSettings,
+    mut args: TrainingArgs,
+    data: &Dataset,
+    device: &Device,
+) -> candle_core::Result<DistillationReport> {
+    let DistillationSettings { temperature, alpha } = *settings;
+    if temperature <= 0.0 {
+        return Err(candle_core::Error::Msg(format!(
+            "Can't distill at temperature {}, expected a positive one",
+            temperature
+        )));
+    }
+    if !(0.0..=1.0).contains(&alpha) {
+        return Err(candle_core::Error::Msg(format!(
+            "Can't weigh soft targets by {}, expected a value in [0, 1]",
+            alpha
+        )));
+    }
+
+    let mut teacher_varmap = VarMap::new();
+    let teacher = ConvNet::new_from_file(&mut teacher_varmap, &teacher_path.to_path_buf())?;
+    let teacher_stats = teacher.stats(&teacher_varmap, teacher_path, data, device)?;
+
+    let mut student_varmap = VarMap::new();
+    let student_model = ConvNet::new(&mut student_varmap, student, teacher.labels())?;
+
+    let distillation = Distillation {
+        teacher,
+        temperature,
+        alpha,
+    };
+    args.load = None;
+    args.save = Some(out.to_string_lossy().to_string());
+    args.distillation = Some(distillation);
+    let training = student_model.train(data, &args, &mut student_varmap)?;
+
+    let student_stats = student_model.stats(&student_varmap, out, data, device)?;
+
+    Ok(DistillationReport {
+        settings: settings.clone(),
+        teacher: teacher_stats,
+        student: student_stats,
+        training,
+    })
+}
@@ -0,0 +1,594 @@
+use crate::checkpoint;
+use crate::labels::LabelSet;
+use crate::strokes;
+use crate::utils;
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// File names of `export_idx`'s output, after the MNIST ones.
+const IDX_IMAGES_FILE: &str = "drawings-images-idx3-ubyte";
+const IDX_LABELS_FILE: &str = "drawings-labels-idx1-ubyte";
+const LABELS_FILE: &str = "labels.json";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// IDX magic numbers: two zero bytes, the unsigned byte type (0x08) and the dimension count.
+const IDX_IMAGES_MAGIC: u32 = 0x0000_0803;
+const IDX_LABELS_MAGIC: u32 = 0x0000_0801;
+
+/// Largest file `import_drawings` reads, far above any drawing, so a zip entry that inflates
+/// to gigabytes is rejected instead of exhausting memory.
+const MAX_FILE_BYTES: u64 = 16 << 20;
+
+/// Largest total size of the files `import_drawings` reads from one folder or archive.
+const MAX_IMPORT_BYTES: u64 = 256 << 20;
+
+/// A file `import_drawings` added to the drawings store.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedFile {
+    /// Path of the file in the imported folder or zip archive.
+    pub source: String,
+    pub label: String,
+    /// Path of the stored drawing, relative to the drawings store.
+    pub stored: String,
+}
+
+/// A file `import_drawings` skipped, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedFile {
+    pub source: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub accepted: Vec<ImportedFile>,
+    pub rejected: Vec<RejectedFile>,
+}
+
+/// Adds the labeled images in `source` to the drawings store in `drawings_dir`. `source` is
+/// either a folder or a zip archive holding a folder per label (`<label>/*.png`, possibly
+/// nested in other folders). Symbolic links in a folder aren't followed.
+///
+/// Images are normalized like the app's drawings: composited on black, inverted when they are
+/// dark on light, and scaled to 28x28. Each one is stored as `<label>/<hash>.png`, where the
+/// hash is of the normalized pixels, so images already in the store (under any label) are
+/// rejected as duplicates. So are images with a label not in `labels`, ones that can't be
+/// decoded and blank ones.
+pub fn import_drawings(
+    source: &Path,
+    drawings_dir: &Path,
+    labels: &LabelSet,
+) -> candle_core::Result<ImportReport> {
+    let files = if source.is_dir() {
+        read_folder(source)?
+    } else {
+        read_zip(source)?
+    };
+
+    fs::create_dir_all(drawings_dir)?;
+    let mut known = stored_hashes(drawings_dir)?;
+    let mut report = ImportReport::default();
+
+    for (name, bytes) in files {
+        let mut reject = |reason: String| {
+            report.rejected.push(RejectedFile {
+                source: name.clone(),
+                reason,
+            })
+        };
+
+        let Some(label) = label_of(&name) else {
+            reject("not in a label folder".to_string());
+            continue;
+        };
+        if labels.index_of(&label).is_none() {
+            reject(format!(
+                "label '{}' isn't one of {:?}",
+                label,
+                labels.labels()
+            ));
+            continue;
+        }
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                reject(format!("can't be read: {}", e));
+                continue;
+            }
+        };
+        let image = match image::load_from_memory(&bytes) {
+            Ok(image) => normalize(&image),
+            Err(e) => {
+                reject(format!("not a valid image: {}", e));
+                continue;
+            }
+        };
+        if image
+            .pixels()
+            .all(|pixel| pixel[0] == image.get_pixel(0, 0)[0])
+        {
+            reject("blank image".to_string());
+            continue;
+        }
+
+        let hash = utils::fnv1a(image.as_raw());
+        if !known.insert(hash) {
+            reject("duplicate of a drawing already in the store".to_string());
+            continue;
+        }
+
+        let stored = Path::new(&label).join(format!("{:016x}.png", hash));
+        fs::create_dir_all(drawings_dir.join(&label))?;
+        image
+            .save_with_format(drawings_dir.join(&stored), ImageFormat::Png)
+            .map_err(|e| candle_core::Error::Msg(format!("Failed to save {:?}: {}", stored, e)))?;
+
+        report.accepted.push(ImportedFile {
+            source: name,
+            label,
+            stored: stored.to_string_lossy().to_string(),
+        });
+    }
+
+    Ok(report)
+}
+
+/// A drawing of the store as `export_zip` lists it in the manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedDrawing {
+    /// Path in the archive.
+    pub file: String,
+    pub label: String,
+    pub label_index: usize,
+    /// Path in the drawings store.
+    pub source: String,
+    /// Path in the archive of the drawing's strokes, for drawings saved with strokes.
+    pub strokes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Manifest<'a> {
+    /// Describes the images, which are all alike.
+    format: &'a str,
+    labels: &'a [String],
+    drawings: &'a [ExportedDrawing],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportReport {
+    /// The files written.
+    pub files: Vec<PathBuf>,
+    pub drawings: usize,
+    pub per_label: BTreeMap<String, usize>,
+}
+
+/// A drawing in the store, at the 28x28 size the model takes.
+struct StoredDrawing {
+    source: PathBuf,
+    label_index: usize,
+    image: GrayImage,
+}
+
+/// Writes the drawings store in `drawings_dir` to `out_dir` as MNIST style IDX files, an
+/// image file (`n x 28 x 28` unsigned bytes) and a label file holding each image's index in
+/// `labels`, which is saved along as `labels.json`.
+///
+/// The files are written next to their destinations first and only renamed over them once
+/// all three are complete, so a failed export leaves any previous one in place. The three
+/// renames aren't atomic together, though.
+pub fn export_idx(
+    drawings_dir: &Path,
+    labels: &LabelSet,
+    out_dir: &Path,
+) -> candle_core::Result<ExportReport> {
+    if labels.len() > u8::MAX as usize + 1 {
+        return Err(candle_core::Error::Msg(format!(
+            "IDX labels are single bytes, which can't index {} labels",
+            labels.len()
+        )));
+    }
+
+    let drawings = stored_drawings(drawings_dir, labels)?;
+    fs::create_dir_all(out_dir)?;
+
+    let count = drawings.len() as u32;
+    let mut images = idx_header(IDX_IMAGES_MAGIC, &[count, 28, 28]);
+    let mut label_bytes = idx_header(IDX_LABELS_MAGIC, &[count]);
+    for drawing in drawings.iter() {
+        images.extend_from_slice(drawing.image.as_raw());
+        label_bytes.push(drawing.label_index as u8);
+    }
+
+    let files = vec![
+        out_dir.join(IDX_IMAGES_FILE),
+        out_dir.join(IDX_LABELS_FILE),
+        out_dir.join(LABELS_FILE),
+    ];
+    let contents = [images, label_bytes, to_json(labels)?.into_bytes()];
+    let tmp_paths = files
+        .iter()
+        .map(|file| checkpoint::sibling_path(file, ".tmp"))
+        .collect::<Vec<PathBuf>>();
+    for (tmp_path, contents) in tmp_paths.iter().zip(contents) {
+        if let Err(e) = fs::write(tmp_path, contents) {
+            for tmp_path in tmp_paths.iter() {
+                let _ = fs::remove_file(tmp_path);
+            }
+            return Err(e.into());
+        }
+    }
+    for (tmp_path, file) in tmp_paths.iter().zip(files.iter()) {
+        fs::rename(tmp_path, file)?;
+    }
+
+    Ok(ExportReport {
+        files,
+        drawings: drawings.len(),
+        per_label: per_label(&drawings, labels),
+    })
+}
+
+/// Writes the drawings store in `drawings_dir` to a zip archive at `out` with the 28x28 PNG
+/// of each drawing in a folder per label (`<label>/<hash>.png`), next to its strokes if it
+/// has any, and a `manifest.json` listing them all.
+pub fn export_zip(
+    drawings_dir: &Path,
+    labels: &LabelSet,
+    out: &Path,
+) -> candle_core::Result<ExportReport> {
+    let drawings = stored_drawings(drawings_dir, labels)?;
+
+    // Written next to `out` first, so a failed export doesn't leave half an archive
+    let tmp_path = checkpoint::sibling_path(out, ".tmp");
+    let written = File::create(&tmp_path)
+        .map_err(candle_core::Error::from)
+        .and_then(|file| write_zip(file, drawings_dir, labels, &drawings, out));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    fs::rename(&tmp_path, out)?;
+
+    Ok(ExportReport {
+        files: vec![out.to_path_buf()],
+        drawings: drawings.len(),
+        per_label: per_label(&drawings, labels),
+    })
+}
+
+/// Writes the archive `export_zip` exports to `file`. `out` is only used in errors.
+fn write_zip(
+    file: File,
+    drawings_dir: &Path,
+    labels: &LabelSet,
+    drawings: &[StoredDrawing],
+    out: &Path,
+) -> candle_core::Result<()> {
+    let mut zip = ZipWriter::new(file);
+    let zip_error = |e: zip::result::ZipError| {
+        candle_core::Error::Msg(format!("Failed to write zip archive {:?}: {}", out, e))
+    };
+
+    let mut exported = Vec::with_capacity(drawings.len());
+    let mut names = HashSet::new();
+    for drawing in drawings.iter() {
+        let label = &labels.labels()[drawing.label_index];
+        let mut name = format!("{}/{:016x}", label, utils::fnv1a(drawing.image.as_raw()));
+        // The store can hold the same image twice, e.g. drawn and imported
+        if !names.insert(name.clone()) {
+            name = format!("{}-{}", name, exported.len());
+            names.insert(name.clone());
+        }
+
+        let mut png = Cursor::new(Vec::new());
+        drawing
+            .image
+            .write_to(&mut png, ImageFormat::Png)
+            .map_err(|e| candle_core::Error::Msg(format!("Failed to encode PNG: {}", e)))?;
+        zip.start_file(format!("{}.png", name), SimpleFileOptions::default())
+            .map_err(zip_error)?;
+        zip.write_all(png.get_ref())?;
+
+        let strokes_path = strokes::strokes_path(&drawings_dir.join(&drawing.source));
+        let strokes = if strokes_path.exists() {
+            let file = format!("{}.strokes.json", name);
+            zip.start_file(file.clone(), SimpleFileOptions::default())
+                .map_err(zip_error)?;
+            zip.write_all(&fs::read(&strokes_path)?)?;
+            Some(file)
+        } else {
+            None
+        };
+
+        exported.push(ExportedDrawing {
+            file: format!("{}.png", name),
+            label: label.clone(),
+            label_index: drawing.label_index,
+            source: drawing.source.to_string_lossy().to_string(),
+            strokes,
+        });
+    }
+
+    let manifest = Manifest {
+        format: "28x28 8-bit grayscale PNG, white on black",
+        labels: labels.labels(),
+        drawings: &exported,
+    };
+    zip.start_file(MANIFEST_FILE, SimpleFileOptions::default())
+        .map_err(zip_error)?;
+    zip.write_all(to_json(&manifest)?.as_bytes())?;
+    zip.finish().map_err(zip_error)?;
+
+    Ok(())
+}
+
+/// Every drawing in the store, the ones drawn in the app first and then the imported ones,
+/// each in name order. Like `utils::load_drawings`, a drawing whose label isn't one of
+/// `labels` is an error.
+fn stored_drawings(
+    drawings_dir: &Path,
+    labels: &LabelSet,
+) -> candle_core::Result<Vec<StoredDrawing>> {
+    let mut entries = fs::read_dir(drawings_dir)
+        .map_err(|e| {
+            candle_core::Error::Msg(format!(
+                "Failed to read drawings directory {:?}: {}",
+                drawings_dir, e
+            ))
+        })?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    let mut drawn = Vec::new();
+    let mut imported = Vec::new();
+    for path in entries {
+        if utils::is_image_file(&path) {
+            let label = path.file_stem().and_then(|stem| stem.to_str());
+            drawn.push((label.unwrap_or_default().to_string(), path));
+        } else if path.is_dir() {
+            let label = path.file_name().and_then(|name| name.to_str());
+            let label = label.unwrap_or_default().to_string();
+
+            let mut images = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<PathBuf>>>()?;
+            images.sort();
+            imported.extend(
+                images
+                    .into_iter()
+                    .filter(|image| utils::is_image_file(image))
+                    .map(|image| (label.clone(), image)),
+            );
+        }
+    }
+
+    drawn
+        .into_iter()
+        .chain(imported)
+        .map(|(label, path)| {
+            let label_index = labels.index_of(&label).ok_or_else(|| {
+                candle_core::Error::Msg(format!(
+                    "Drawing {:?} is labelled '{}', which isn't one of {:?}",
+                    path,
+                    label,
+                    labels.labels()
+                ))
+            })?;
+            let image = image::open(&path).map_err(|e| {
+                candle_core::Error::Msg(format!("Failed to open drawing {:?}: {}", path, e))
+            })?;
+
+            Ok(StoredDrawing {
+                source: path
+                    .strip_prefix(drawings_dir)
+                    .unwrap_or(&path)
+                    .to_path_buf(),
+                label_index,
+                image: utils::resize_drawing(&image).to_luma8(),
+            })
+        })
+        .collect()
+}
+
+fn idx_header(magic: u32, dims: &[u32]) -> Vec<u8> {
+    std::iter::once(magic)
+        .chain(dims.iter().copied())
+        .flat_map(u32::to_be_bytes)
+        .collect()
+}
+
+fn per_label(drawings: &[StoredDrawing], labels: &LabelSet) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for drawing in drawings {
+        *counts
+            .entry(labels.labels()[drawing.label_index].clone())
+            .or_default() += 1;
+    }
+    counts
+}
+
+fn to_json<T: Serialize>(value: &T) -> candle_core::Result<String> {
+    serde_json::to_string_pretty(value)
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to encode JSON: {}", e)))
+}
+
+/// Brings an image to the format of the drawings store: white on black, 28x28 grayscale.
+fn normalize(image: &DynamicImage) -> GrayImage {
+    // Transparent pixels are background, like the black of the app's canvas
+    let composited = image.to_luma_alpha8();
+    let mut gray = GrayImage::from_fn(composited.width(), composited.height(), |x, y| {
+        let [luma, alpha] = composited.get_pixel(x, y).0;
+        Luma([(luma as u16 * alpha as u16 / 255) as u8])
+    });
+
+    // Scans and other datasets are often dark ink on light paper
+    let opaque = composited.pixels().all(|pixel| pixel[1] == 255);
+    if opaque && border_mean(&gray) > 127.0 {
+        image::imageops::invert(&mut gray);
+    }
+
+    utils::resize_drawing(&DynamicImage::ImageLuma8(gray)).to_luma8()
+}
+
+fn border_mean(image: &GrayImage) -> f32 {
+    let (width, height) = image.dimensions();
+    let border = image
+        .enumerate_pixels()
+        .filter(|(x, y, _)| *x == 0 || *y == 0 || *x == width - 1 || *y == height - 1)
+        .map(|(_, _, pixel)| pixel[0] as f32)
+        .collect::<Vec<f32>>();
+    border.iter().sum::<f32>() / border.len().max(1) as f32
+}
+
+/// The label of a file from the name of the folder it's in.
+fn label_of(name: &str) -> Option<String> {
+    let mut components = Path::new(name).components().rev().skip(1);
+    match components.next() {
+        Some(Component::Normal(label)) => Some(label.to_string_lossy().to_string()),
+        _ => None,
+    }
+}
+
+/// Files that aren't worth reporting, like `.DS_Store` or the `__MACOSX` folder of archives
+/// made on macOS.
+fn is_hidden(name: &str) -> bool {
+    Path::new(name).components().any(|component| {
+        let component = component.as_os_str().to_string_lossy();
+        component.starts_with('.') || component == "__MACOSX"
+    })
+}
+
+type SourceFile = (String, std::io::Result<Vec<u8>>);
+
+/// Every file below `root`, named by its path relative to `root`, in name order. Symbolic
+/// links are left out, so the import can't escape `root` or loop.
+fn read_folder(root: &Path) -> candle_core::Result<Vec<SourceFile>> {
+    let mut files = Vec::new();
+    let mut total = 0;
+    let mut folders = vec![root.to_path_buf()];
+
+    while let Some(folder) = folders.pop() {
+        for entry in fs::read_dir(&folder)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_symlink() {
+                continue;
+            } else if file_type.is_dir() {
+                folders.push(path);
+            } else {
+                let name = path
+                    .strip_prefix(root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .to_string();
+                if !is_hidden(&name) {
+                    let bytes = File::open(&path).and_then(read_limited);
+                    count_bytes(&mut total, &bytes, root)?;
+                    files.push((name, bytes));
+                }
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+/// Every file in the zip archive at `path`, in name order. Entries whose names would escape
+/// the archive (like `../x.png`) are left out.
+fn read_zip(path: &Path) -> candle_core::Result<Vec<SourceFile>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(|e| {
+        candle_core::Error::Msg(format!("Failed to open zip archive {:?}: {}", path, e))
+    })?;
+
+    let mut files = Vec::new();
+    let mut total = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to read zip archive {:?}: {}", path, e))
+        })?;
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let name = name.to_string_lossy().to_string();
+        if entry.is_dir() || is_hidden(&name) {
+            continue;
+        }
+
+        // The sizes in the archive can't be trusted, only what actually inflates
+        let bytes = read_limited(&mut entry);
+        count_bytes(&mut total, &bytes, path)?;
+        files.push((name, bytes));
+    }
+
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+/// Reads all of `reader`, failing for files larger than `MAX_FILE_BYTES`.
+fn read_limited(reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(MAX_FILE_BYTES + 1).read_to_end(&mut bytes)?;
+
+    if bytes.len() as u64 > MAX_FILE_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("larger than {} MiB", MAX_FILE_BYTES >> 20),
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Adds the size of a file read from `source` to `total`, failing the import once it
+/// exceeds `MAX_IMPORT_BYTES`.
+fn count_bytes(
+    total: &mut u64,
+    bytes: &std::io::Result<Vec<u8>>,
+    source: &Path,
+) -> candle_core::Result<()> {
+    if let Ok(bytes) = bytes {
+        *total += bytes.len() as u64;
+    }
+
+    if *total > MAX_IMPORT_BYTES {
+        return Err(candle_core::Error::Msg(format!(
+            "{:?} holds more than {} MiB of files, import it in parts",
+            source,
+            MAX_IMPORT_BYTES >> 20
+        )));
+    }
+    Ok(())
+}
+
+/// Hashes of the pixels of every drawing in the store, as `import_drawings` computes them.
+fn stored_hashes(drawings_dir: &Path) -> candle_core::Result<HashSet<u64>> {
+    let mut hashes = HashSet::new();
+
+    for entry in fs::read_dir(drawings_dir)? {
+        let path = entry?.path();
+        let images = if path.is_dir() {
+            fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<PathBuf>>>()?
+        } else {
+            vec![path]
+        };
+
+        for image in images.iter().filter(|image| utils::is_image_file(image)) {
+            let image = image::open(image).map_err(|e| {
+                candle_core::Error::Msg(format!("Failed to open drawing {:?}: {}", image, e))
+            })?;
+            hashes.insert(utils::fnv1a(normalize(&image).as_raw()));
+        }
+    }
+
+    Ok(hashes)
+}
@@ -0,0 +1,157 @@
+use crate::checkpoint::{self, sibling_path};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Loss of a single optimizer step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchMetrics {
+    pub epoch: usize,
+    /// Position of the batch within its epoch, in training order.
+    pub batch: usize,
+    pub loss: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EpochMetrics {
+    pub epoch: usize,
+    pub train_loss: f32,
+    /// Share of the test set predicted correctly, in `[0, 1]`.
+    pub test_accuracy: f32,
+}
+
+/// A line of the JSON Lines file, tagged with its kind so both can share one file.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum HistoryRecord {
+    Batch(BatchMetrics),
+    Epoch(EpochMetrics),
+}
+
+/// Metrics recorded while training, saved next to the model as
+/// `<model>.history.jsonl` and `<model>.history.csv`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrainingHistory {
+    pub batches: Vec<BatchMetrics>,
+    pub epochs: Vec<EpochMetrics>,
+}
+
+impl TrainingHistory {
+    pub fn record_batch(&mut self, epoch: usize, batch: usize, loss: f32) {
+        self.batches.push(BatchMetrics { epoch, batch, loss });
+    }
+
+    pub fn record_epoch(&mut self, epoch: usize, train_loss: f32, test_accuracy: f32) {
+        self.epochs.push(EpochMetrics {
+            epoch,
+            train_loss,
+            test_accuracy,
+        });
+    }
+
+    /// Writes the history of the model saved at `model_path` in both formats. Each file is
+    /// written to a temporary file first and renamed into place, so a crash can't leave a
+    /// truncated history behind.
+    pub fn save(&self, model_path: &Path) -> candle_core::Result<()> {
+        let files = [
+            (jsonl_path(model_path), self.to_jsonl()?),
+            (csv_path(model_path), self.to_csv()),
+        ];
+
+        for (path, contents) in files.iter() {
+            let tmp_path = sibling_path(path, ".tmp");
+            if let Err(e) = fs::write(&tmp_path, contents) {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e.into());
+            }
+            checkpoint::replace(&tmp_path, path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the history of the model saved at `model_path`, if it has one.
+    pub fn load(model_path: &Path) -> candle_core::Result<Option<Self>> {
+        let path = jsonl_path(model_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut history = TrainingHistory::default();
+        for (i, line) in fs::read_to_string(&path)?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(line).map_err(|e| {
+                candle_core::Error::Msg(format!(
+                    "Invalid record on line {} of {:?}: {}",
+                    i + 1,
+                    path,
+                    e
+                ))
+            })?;
+            match record {
+                HistoryRecord::Batch(batch) => history.batches.push(batch),
+                HistoryRecord::Epoch(epoch) => history.epochs.push(epoch),
+            }
+        }
+
+        Ok(Some(history))
+    }
+
+    /// One JSON object per line, batches of an epoch followed by the epoch itself.
+    pub fn to_jsonl(&self) -> candle_core::Result<String> {
+        let mut out = String::new();
+        for record in self.records() {
+            let line = serde_json::to_string(&record).map_err(|e| {
+                candle_core::Error::Msg(format!("Failed to serialize training history: {}", e))
+            })?;
+            out.push_str(&line);
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    /// One row per record, with the columns that don't apply to its kind left empty.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("kind,epoch,batch,loss,test_accuracy\n");
+        for record in self.records() {
+            // Writing to a String can't fail
+            let _ = match record {
+                HistoryRecord::Batch(b) => {
+                    writeln!(out, "batch,{},{},{},", b.epoch, b.batch, b.loss)
+                }
+                HistoryRecord::Epoch(e) => writeln!(
+                    out,
+                    "epoch,{},,{},{}",
+                    e.epoch, e.train_loss, e.test_accuracy
+                ),
+            };
+        }
+        out
+    }
+
+    fn records(&self) -> Vec<HistoryRecord> {
+        let mut records = Vec::with_capacity(self.batches.len() + self.epochs.len());
+        let mut batches = self.batches.iter().peekable();
+        for epoch in self.epochs.iter() {
+            while let Some(batch) = batches.next_if(|b| b.epoch <= epoch.epoch) {
+                records.push(HistoryRecord::Batch(batch.clone()));
+            }
+            records.push(HistoryRecord::Epoch(epoch.clone()));
+        }
+        records.extend(batches.cloned().map(HistoryRecord::Batch));
+        records
+    }
+}
+
+/// Path of the JSON Lines history of the model at `model_path`.
+pub fn jsonl_path(model_path: &Path) -> PathBuf {
+    sibling_path(model_path, ".history.jsonl")
+}
+
+/// Path of the CSV history of the model at `model_path`.
+pub fn csv_path(model_path: &Path) -> PathBuf {
+    sibling_path(model_path, ".history.csv")
+}
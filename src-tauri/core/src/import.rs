@@ -0,0 +1,402 @@
+use crate::architecture::{FeatureShape, LayerSpec};
+use crate::model::ConvNet;
+use candle_core::pickle::{self, Object};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarMap;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Keys PyTorch training checkpoints commonly nest the state dict under.
+const STATE_DICT_KEYS: [&str; 3] = ["state_dict", "model_state_dict", "model"];
+
+/// Prefixes added by wrappers like `nn.DataParallel`, stripped before matching names.
+const WRAPPER_PREFIXES: [&str; 3] = ["module.", "model.", "net."];
+
+/// Where an imported tensor ended up, and what had to be done to it on the way.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedTensor {
+    pub source: String,
+    pub target: String,
+    pub shape: Vec<usize>,
+    /// Layout change applied to fit the target, e.g. a transpose of a TensorFlow kernel.
+    pub transform: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LayerKind {
+    Conv,
+    BatchNorm,
+    Linear,
+}
+
+/// A layer's parameters in the imported file, keyed by parameter name (`weight`, `bias`, ...).
+struct SourceLayer {
+    name: String,
+    kind: LayerKind,
+    params: BTreeMap<String, (String, Tensor)>,
+}
+
+/// Reads the tensors of a PyTorch state dict (`.pt`/`.pth`, either bare or nested in a
+/// training checkpoint) or a numpy `.npz` archive.
+pub fn read_state_dict(path: &Path) -> candle_core::Result<Vec<(String, Tensor)>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let tensors = match extension.as_str() {
+        "npz" => Tensor::read_npz(path)?,
+        "pt" | "pth" => {
+            let tensors = pickle::read_all_with_key(path, None)?;
+            if tensors.is_empty() {
+                // A training checkpoint, with the state dict under one of its keys
+                let keys = pickle_keys(path)?;
+                match STATE_DICT_KEYS
+                    .iter()
+                    .find(|key| keys.iter().any(|k| k == *key))
+                {
+                    Some(key) => pickle::read_all_with_key(path, Some(key))?,
+                    None => tensors,
+                }
+            } else {
+                tensors
+            }
+        }
+        _ => {
+            return Err(candle_core::Error::Msg(format!(
+                "Can't import {:?}, expected a .pt, .pth or .npz file",
+                path
+            )))
+        }
+    };
+
+    if tensors.is_empty() {
+        return Err(candle_core::Error::Msg(format!(
+            "No tensors found in {:?}",
+            path
+        )));
+    }
+
+    Ok(tensors)
+}
+
+/// Keys of the dict pickled at the top of the PyTorch file at `path`, or none if it isn't a
+/// dict.
+fn pickle_keys(path: &Path) -> candle_core::Result<Vec<String>> {
+    let zip_error = |e: zip::result::ZipError| {
+        candle_core::Error::Msg(format!("Failed to read PyTorch file {:?}: {}", path, e))
+    };
+
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(path)?)).map_err(zip_error)?;
+    let pickle_name = zip
+        .file_names()
+        .find(|name| name.ends_with("data.pkl"))
+        .map(String::from)
+        .ok_or_else(|| candle_core::Error::Msg(format!("{:?} has no pickled data.pkl", path)))?;
+
+    let mut stack = pickle::Stack::empty();
+    stack.read_loop(&mut BufReader::new(
+        zip.by_name(&pickle_name).map_err(zip_error)?,
+    ))?;
+
+    Ok(match stack.finalize()? {
+        Object::Dict(entries) => entries
+            .into_iter()
+            .filter_map(|(key, _)| key.unicode().ok())
+            .collect(),
+        _ => Vec::new(),
+    })
+}
+
+/// Loads the weights in the state dict at `path` into `model`'s variables in `varmap`.
+///
+/// Layers are matched by name once wrapper prefixes are stripped (`module.c1.weight` becomes
+/// `c1.weight`). When the names don't match, as with `conv1`/`features.0` style names, layers
+/// of each kind (conv, batch norm, linear) are paired up in order, comparing names naturally
+/// so `conv10` comes after `conv2`. TensorFlow style layouts (HWIO conv kernels, `[in, out]`
+/// dense kernels flattened channels last) are converted. Every target variable must be
+/// filled and every imported layer used, with matching shapes.
+pub fn load_into(
+    model: &ConvNet,
+    varmap: &VarMap,
+    path: &Path,
+) -> candle_core::Result<Vec<ImportedTensor>> {
+    let sources = group_layers(read_state_dict(path)?)?;
+    let spec = model.spec();
+    let shapes = spec.shapes(model.labels().len())?;
+
+    // Each layer with weights, along with the spatial shape flattened into it for the first
+    // linear layer, as that's where channels-last inputs need reordering
+    let mut targets: Vec<(&str, LayerKind, Option<[usize; 3]>)> = Vec::new();
+    let mut last_spatial = None;
+    for (layer, input) in spec.layers.iter().zip(shapes) {
+        if let FeatureShape::Spatial {
+            channels,
+            height,
+            width,
+        } = input
+        {
+            last_spatial = Some([channels, height, width]);
+        }
+        match layer {
+            LayerSpec::Conv { name, .. } => targets.push((name, LayerKind::Conv, None)),
+            LayerSpec::BatchNorm { name, .. } => targets.push((name, LayerKind::BatchNorm, None)),
+            LayerSpec::Linear { name, .. } => {
+                targets.push((name, LayerKind::Linear, last_spatial.take()))
+            }
+            _ => {}
+        }
+    }
+
+    let by_name = targets.iter().all(|(target, kind, _)| {
+        sources
+            .iter()
+            .any(|source| source.name == *target && source.kind == *kind)
+    });
+
+    let mut pairs = Vec::with_capacity(targets.len());
+    for kind in [LayerKind::Conv, LayerKind::BatchNorm, LayerKind::Linear] {
+        let kind_targets = targets.iter().filter(|(_, k, _)| *k == kind);
+        let mut kind_sources = sources
+            .iter()
+            .filter(|s| s.kind == kind)
+            .collect::<Vec<&SourceLayer>>();
+        kind_sources.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+
+        if kind_targets.clone().count() != kind_sources.len() {
+            return Err(candle_core::Error::Msg(format!(
+                "Expected {} {:?} layers but found {}: {:?}",
+                kind_targets.count(),
+                kind,
+                kind_sources.len(),
+                kind_sources.iter().map(|s| &s.name).collect::<Vec<_>>()
+            )));
+        }
+
+        for (i, target) in kind_targets.enumerate() {
+            let source = if by_name {
+                kind_sources.iter().find(|s| s.name == target.0).copied()
+            } else {
+                kind_sources.get(i).copied()
+            };
+            // Counts were checked above, and name matching was checked for every target
+            pairs.push((target, source.expect("every target has a source layer")));
+        }
+    }
+
+    // Whether the weights come from TensorFlow, with HWIO conv kernels, `[in, out]` dense
+    // kernels and the flattened features feeding the first linear layer ordered (height, width,
+    // channels) rather than candle's (channels, height, width)
+    let channels_last = pairs
+        .iter()
+        .any(|((_, kind, _), source)| *kind == LayerKind::Conv && is_hwio(source));
+
+    let vars = varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?;
+
+    let mut imported = Vec::new();
+    for ((target, kind, flattened), source) in pairs {
+        let mut used = 0;
+        for (param, var) in vars
+            .iter()
+            .filter_map(|(name, var)| Some((name.strip_prefix(&format!("{target}."))?, var)))
+        {
+            let (source_name, tensor) = source.params.get(param).ok_or_else(|| {
+                candle_core::Error::Msg(format!(
+                    "Layer '{}' has no '{}' for {}.{}",
+                    source.name, param, target, param
+                ))
+            })?;
+            let target_name = format!("{target}.{param}");
+
+            let (tensor, transform) =
+                fit_layout(tensor, var.dims(), *kind, flattened, channels_last)?;
+            if tensor.dims() != var.dims() {
+                return Err(candle_core::Error::Msg(format!(
+                    "Can't import '{}' into '{}', it has shape {:?} but needs {:?}",
+                    source_name,
+                    target_name,
+                    tensor.dims(),
+                    var.dims()
+                )));
+            }
+
+            var.set(&tensor.to_dtype(DType::F32)?.to_device(var.device())?)?;
+            imported.push(ImportedTensor {
+                source: source_name.clone(),
+                target: target_name,
+                shape: tensor.dims().to_vec(),
+                transform,
+            });
+            used += 1;
+        }
+
+        if used != source.params.len() {
+            return Err(candle_core::Error::Msg(format!(
+                "Layer '{}' has parameters {:?} but '{}' only takes {}",
+                source.name,
+                source.params.keys().collect::<Vec<_>>(),
+                target,
+                used
+            )));
+        }
+    }
+
+    imported.sort_by(|a, b| a.target.cmp(&b.target));
+    Ok(imported)
+}
+
+/// Groups tensors into layers by the part of their name before the last `.`, working out
+/// each layer's kind from its parameters.
+fn group_layers(tensors: Vec<(String, Tensor)>) -> candle_core::Result<Vec<SourceLayer>> {
+    let mut layers: HashMap<String, BTreeMap<String, (String, Tensor)>> = HashMap::new();
+
+    for (name, tensor) in tensors {
+        let mut stripped = name.as_str();
+        while let Some(rest) = WRAPPER_PREFIXES
+            .iter()
+            .find_map(|prefix| stripped.strip_prefix(prefix))
+        {
+            stripped = rest;
+        }
+
+        let Some((layer, param)) = stripped.rsplit_once('.') else {
+            return Err(candle_core::Error::Msg(format!(
+                "Can't tell which layer '{}' belongs to",
+                name
+            )));
+        };
+        // Bookkeeping of PyTorch batch norm layers, not a weight
+        if param == "num_batches_tracked" {
+            continue;
+        }
+
+        layers.entry(layer.to_string()).or_default().insert(
+            param.to_string(),
+            (name.clone(), tensor.to_device(&Device::Cpu)?),
+        );
+    }
+
+    layers
+        .into_iter()
+        .map(|(name, params)| {
+            let weight_rank = params.get("weight").map(|(_, t)| t.rank());
+            let kind = match (params.contains_key("running_mean"), weight_rank) {
+                (true, _) => LayerKind::BatchNorm,
+                (false, Some(4)) => LayerKind::Conv,
+                (false, Some(2)) => LayerKind::Linear,
+                _ => {
+                    return Err(candle_core::Error::Msg(format!(
+                        "Can't map layer '{}' with parameters {:?} to a conv, batch norm or \
+                         linear layer",
+                        name,
+                        params.keys().collect::<Vec<_>>()
+                    )))
+                }
+            };
+            Ok(SourceLayer { name, kind, params })
+        })
+        .collect()
+}
+
+/// Whether a conv layer's kernel is laid out `[height, width, in, out]` like TensorFlow's,
+/// judged by its trailing dimension matching the bias length while the leading one doesn't.
+fn is_hwio(source: &SourceLayer) -> bool {
+    let (Some((_, weight)), Some((_, bias))) =
+        (source.params.get("weight"), source.params.get("bias"))
+    else {
+        return false;
+    };
+    let dims = weight.dims();
+    let out_channels = bias.elem_count();
+    dims.len() == 4 && dims[0] != out_channels && dims[3] == out_channels
+}
+
+/// Converts `tensor` from the layout of the source format (TensorFlow's if `channels_last`,
+/// otherwise PyTorch's) to the one candle expects for a parameter of shape `target`.
+///
+/// Without conv layers to tell the format by, a dense kernel is only taken for an `[in, out]`
+/// one when it doesn't fit `target` as it is, so square TensorFlow kernels of such models are
+/// imported untransposed.
+fn fit_layout(
+    tensor: &Tensor,
+    target: &[usize],
+    kind: LayerKind,
+    flattened: &Option<[usize; 3]>,
+    channels_last: bool,
+) -> candle_core::Result<(Tensor, Option<String>)> {
+    match (kind, tensor.rank()) {
+        // HWIO -> OIHW
+        (LayerKind::Conv, 4) if channels_last => Ok((
+            tensor.permute((3, 2, 0, 1))?.contiguous()?,
+            Some("transposed HWIO kernel to OIHW".to_string()),
+        )),
+        (LayerKind::Linear, 2) => {
+            let mut tensor = tensor.clone();
+            let mut transforms = Vec::new();
+
+            let in_out = channels_last || (tensor.dims() != target && tensor.t()?.dims() == target);
+            if in_out {
+                tensor = tensor.t()?.contiguous()?;
+                transforms.push("transposed [in, out] kernel to [out, in]");
+            }
+
+            if let (true, Some([channels, height, width])) = (channels_last, flattened) {
+                let out = tensor.dim(0)?;
+                tensor = tensor
+                    .reshape((out, *height, *width, *channels))?
+                    .permute((0, 3, 1, 2))?
+                    .reshape((out, channels * height * width))?;
+                transforms.push("reordered channels-last flattened inputs");
+            }
+
+            let transform = (!transforms.is_empty()).then(|| transforms.join(", "));
+            Ok((tensor, transform))
+        }
+        _ => Ok((tensor.clone(), None)),
+    }
+}
+
+/// Orders names with embedded numbers by value, so `conv2` < `conv10` and `features.3` <
+/// `features.10`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut n = 0u64;
+                    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                        n = n.saturating_mul(10).saturating_add(d as u64);
+                        chars.next();
+                    }
+                    n
+                };
+                match take_number(&mut a).cmp(&take_number(&mut b)) {
+                    Ordering::Equal => continue,
+                    other => return other,
+                }
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+                match x.cmp(&y) {
+                    Ordering::Equal => continue,
+                    other => return other,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Characters that can't appear in a label, since drawings are saved as `<label>.png`.
+const FORBIDDEN_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*', '.'];
+
+/// Ordered list of the classes a model predicts. A label's index is the model output it
+/// corresponds to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct LabelSet {
+    labels: Vec<String>,
+}
+
+impl LabelSet {
+    pub fn new(labels: Vec<String>) -> candle_core::Result<Self> {
+        if labels.is_empty() {
+            return Err(candle_core::Error::Msg(
+                "A label set needs at least one label".to_string(),
+            ));
+        }
+
+        let mut seen = HashSet::new();
+        for label in labels.iter() {
+            if label.trim().is_empty() || label.contains(FORBIDDEN_CHARS) {
+                return Err(candle_core::Error::Msg(format!(
+                    "Invalid label '{}', labels can't be blank or contain any of {:?}",
+                    label, FORBIDDEN_CHARS
+                )));
+            }
+            if !seen.insert(label) {
+                return Err(candle_core::Error::Msg(format!(
+                    "Duplicate label '{}'",
+                    label
+                )));
+            }
+        }
+
+        Ok(Self { labels })
+    }
+
+    /// The digits 0-9, which the bundled model predicts.
+    pub fn digits() -> Self {
+        Self {
+            labels: (0..10).map(|digit: u32| digit.to_string()).collect(),
+        }
+    }
+
+    /// Loads a label set stored as a JSON array of strings.
+    pub fn from_json_file(path: &Path) -> candle_core::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to load label set {:?}: {}", path, e))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn index_of(&self, label: &str) -> Option<usize> {
+        self.labels.iter().position(|l| l == label)
+    }
+
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.labels.get(index).map(|l| l.as_str())
+    }
+
+    /// Maps each MNIST digit to its index in this label set.
+    pub fn digit_indices(&self) -> candle_core::Result<[u32; 10]> {
+        let mut indices = [0; 10];
+        for (digit, index) in indices.iter_mut().enumerate() {
+            *index = self.index_of(&digit.to_string()).ok_or_else(|| {
+                candle_core::Error::Msg(format!(
+                    "MNIST samples need the digit labels 0-9, but {:?} has no '{}'",
+                    self.labels, digit
+                ))
+            })? as u32;
+        }
+        Ok(indices)
+    }
+}
+
+impl TryFrom<Vec<String>> for LabelSet {
+    type Error = candle_core::Error;
+
+    fn try_from(labels: Vec<String>) -> candle_core::Result<Self> {
+        Self::new(labels)
+    }
+}
+
+impl From<LabelSet> for Vec<String> {
+    fn from(set: LabelSet) -> Self {
+        set.labels
+    }
+}
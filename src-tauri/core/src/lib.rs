@@ -0,0 +1,18 @@
+pub mod architecture;
+pub mod checkpoint;
+pub mod distill;
+pub mod drawings;
+pub mod history;
+pub mod import;
+pub mod labels;
+pub mod metadata;
+pub mod model;
+pub mod onnx;
+mod proto;
+pub mod prune;
+pub mod quantize;
+pub mod registry;
+pub mod strokes;
+pub mod tensorboard;
+pub mod utils;
+pub mod visualization;
@@ -0,0 +1,181 @@
+use crate::architecture::ArchitectureSpec;
+use crate::labels::LabelSet;
+use crate::model::TrainingSummary;
+use crate::utils::NORMALIZATION;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Description of a saved model, stored in the `__metadata__` header of its safetensors file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    pub architecture: String,
+    /// Missing from models saved before architectures were configurable.
+    pub architecture_spec: Option<ArchitectureSpec>,
+    pub labels: Vec<String>,
+    pub input_shape: Vec<usize>,
+    pub normalization: String,
+    pub training: Option<TrainingSummary>,
+    pub app_version: String,
+}
+
+impl ModelMetadata {
+    /// Metadata for a model with the architecture `spec` predicting `labels`, created by this
+    /// build of the app.
+    pub fn current(
+        spec: &ArchitectureSpec,
+        labels: &LabelSet,
+        training: Option<TrainingSummary>,
+    ) -> Self {
+        Self {
+            architecture: spec.id.clone(),
+            architecture_spec: Some(spec.clone()),
+            labels: labels.labels().to_vec(),
+            input_shape: spec.input_shape.to_vec(),
+            normalization: NORMALIZATION.to_string(),
+            training,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Checks that a model saved with this metadata can be loaded by the running code and
+    /// returns the architecture and label set to build it with.
+    ///
+    /// The architecture id, input shape and labels are checked against the architecture the
+    /// model describes, so a header whose fields disagree with each other is rejected rather
+    /// than building a model the weights don't fit.
+    pub fn validate(&self) -> candle_core::Result<(ArchitectureSpec, LabelSet)> {
+        let spec = match &self.architecture_spec {
+            Some(spec) => spec.clone(),
+            None => {
+                let default = ArchitectureSpec::default();
+                if self.architecture != default.id {
+                    return Err(candle_core::Error::Msg(format!(
+                        "Model uses architecture '{}' but doesn't describe it",
+                        self.architecture
+                    )));
+                }
+                default
+            }
+        };
+        let labels = LabelSet::new(self.labels.clone())?;
+
+        if self.architecture != spec.id {
+            return Err(candle_core::Error::Msg(format!(
+                "Model metadata names architecture '{}' but describes '{}'",
+                self.architecture, spec.id
+            )));
+        }
+        if self.input_shape != spec.input_shape {
+            return Err(candle_core::Error::Msg(format!(
+                "Model metadata gives input shape {:?} but its architecture takes {:?}",
+                self.input_shape, spec.input_shape
+            )));
+        }
+        if self.normalization != NORMALIZATION {
+            return Err(mismatch(
+                "normalization",
+                &self.normalization,
+                &NORMALIZATION.to_string(),
+            ));
+        }
+        spec.shapes(labels.len()).map_err(|e| {
+            candle_core::Error::Msg(format!(
+                "Model's architecture doesn't fit its {} labels: {}",
+                labels.len(),
+                e
+            ))
+        })?;
+
+        Ok((spec, labels))
+    }
+
+    /// Flattens the metadata into the string map safetensors stores. Values that aren't
+    /// plain strings are JSON encoded.
+    pub fn to_header(&self) -> candle_core::Result<HashMap<String, String>> {
+        let mut header = HashMap::new();
+        header.insert("architecture".to_string(), self.architecture.clone());
+        if let Some(spec) = &self.architecture_spec {
+            header.insert("architecture_spec".to_string(), spec.to_json()?);
+        }
+        header.insert("labels".to_string(), to_json(&self.labels)?);
+        header.insert("input_shape".to_string(), to_json(&self.input_shape)?);
+        header.insert("normalization".to_string(), self.normalization.clone());
+        header.insert("training".to_string(), to_json(&self.training)?);
+        header.insert("app_version".to_string(), self.app_version.clone());
+        Ok(header)
+    }
+
+    pub fn from_header(header: &HashMap<String, String>) -> candle_core::Result<Self> {
+        let field = |key: &str| {
+            header.get(key).ok_or_else(|| {
+                candle_core::Error::Msg(format!("Model metadata is missing '{}'", key))
+            })
+        };
+
+        Ok(Self {
+            architecture: field("architecture")?.clone(),
+            architecture_spec: header
+                .get("architecture_spec")
+                .map(|json| ArchitectureSpec::from_json(json))
+                .transpose()?,
+            labels: from_json(field("labels")?)?,
+            input_shape: from_json(field("input_shape")?)?,
+            normalization: field("normalization")?.clone(),
+            training: from_json(field("training")?)?,
+            app_version: field("app_version")?.clone(),
+        })
+    }
+}
+
+/// Reads the metadata of the safetensors file at `path`. Models saved before metadata was
+/// written (like the bundled one) have none.
+pub fn read_metadata(path: &Path) -> candle_core::Result<Option<ModelMetadata>> {
+    let buffer = fs::read(path)?;
+
+    let (_, metadata) = safetensors::SafeTensors::read_metadata(&buffer).map_err(|e| {
+        candle_core::Error::Msg(format!("Failed to read header of {:?}: {}", path, e))
+    })?;
+
+    match metadata.metadata() {
+        Some(header) if header.contains_key("architecture") => {
+            Ok(Some(ModelMetadata::from_header(header)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads the architecture and label set of the model at `path`, validated against the running
+/// code. Models without metadata are assumed to be the default architecture predicting digits.
+pub fn read_spec_and_labels(path: &Path) -> candle_core::Result<(ArchitectureSpec, LabelSet)> {
+    match read_metadata(path)? {
+        Some(metadata) => metadata
+            .validate()
+            .map_err(|e| candle_core::Error::Msg(format!("Can't load model at {:?}: {}", path, e))),
+        None => {
+            println!(
+                "{:?} has no metadata, assuming the default architecture",
+                path
+            );
+            Ok((ArchitectureSpec::default(), LabelSet::digits()))
+        }
+    }
+}
+
+fn mismatch<T: std::fmt::Debug>(what: &str, saved: &T, expected: &T) -> candle_core::Error {
+    candle_core::Error::Msg(format!(
+        "Model was saved with {} {:?} but this app expects {:?}",
+        what, saved, expected
+    ))
+}
+
+fn to_json<T: Serialize>(value: &T) -> candle_core::Result<String> {
+    serde_json::to_string(value)
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to encode model metadata: {}", e)))
+}
+
+fn from_json<T: for<'de> Deserialize<'de>>(value: &str) -> candle_core::Result<T> {
+    serde_json::from_str(value)
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to decode model metadata: {}", e)))
+}
@@ -0,0 +1,720 @@
+use crate::architecture::{Activation, ArchitectureSpec, FeatureShape, LayerSpec};
+use crate::distill::Distillation;
+use crate::history::TrainingHistory;
+use crate::labels::LabelSet;
+use crate::metadata::{self, ModelMetadata};
+use crate::tensorboard::EventWriter;
+use crate::{checkpoint, utils};
+use candle_core::{DType, Tensor, Var, D};
+use candle_datasets;
+use candle_nn::{
+    loss, ops, BatchNorm, BatchNormConfig, Conv2d, Conv2dConfig, Linear, ModuleT, Optimizer,
+    VarBuilder, VarMap,
+};
+use image::{GrayImage, Luma};
+use rand::prelude::*;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::MutexGuard;
+
+/// How many test images `ConvNet::train` logs predictions of.
+const PREDICTION_SAMPLES: usize = 32;
+
+/// A layer of `ConvNet`, built from a `LayerSpec`.
+#[derive(Debug)]
+enum Layer {
+    Conv(Conv2d),
+    MaxPool { size: usize, stride: usize },
+    Dropout(f32),
+    BatchNorm(BatchNorm),
+    Flatten,
+    Linear(Linear),
+    Activation(Activation),
+}
+
+#[derive(Debug)]
+pub struct ConvNet {
+    spec: ArchitectureSpec,
+    labels: LabelSet,
+    layers: Vec<Layer>,
+    /// Whether the weights are still the initialization of `new`, rather than loaded ones.
+    pub(crate) fresh: bool,
+}
+
+impl ConvNet {
+    pub fn new(
+        vm: &mut VarMap,
+        spec: &ArchitectureSpec,
+        labels: &LabelSet,
+    ) -> candle_core::Result<Self> {
+        let dev = candle_core::Device::cuda_if_available(0)?;
+
+        let vb = VarBuilder::from_varmap(vm, DType::F32, &dev);
+
+        let shapes = spec.shapes(labels.len())?;
+        if shapes.last() != Some(&FeatureShape::Flat(labels.len())) {
+            return Err(candle_core::Error::Msg(format!(
+                "Architecture '{}' outputs {:?} but there are {} labels",
+                spec.id,
+                shapes.last(),
+                labels.len()
+            )));
+        }
+
+        let mut layers = Vec::with_capacity(spec.layers.len());
+
+        for (layer, input) in spec.layers.iter().zip(shapes) {
+            let layer = match layer {
+                LayerSpec::Conv {
+                    name,
+                    out_channels,
+                    kernel,
+                    stride,
+                    padding,
+                } => {
+                    let FeatureShape::Spatial { channels, .. } = input else {
+                        unreachable!("conv input shape is checked by ArchitectureSpec::shapes")
+                    };
+                    let config = Conv2dConfig {
+                        padding: *padding,
+                        stride: *stride,
+                        ..Default::default()
+                    };
+                    Layer::Conv(candle_nn::conv2d(
+                        channels,
+                        *out_channels,
+                        *kernel,
+                        config,
+                        vb.pp(name),
+                    )?)
+                }
+                LayerSpec::MaxPool { size, stride } => Layer::MaxPool {
+                    size: *size,
+                    stride: stride.unwrap_or(*size),
+                },
+                LayerSpec::Dropout { p } => Layer::Dropout(*p),
+                LayerSpec::BatchNorm {
+                    name,
+                    eps,
+                    momentum,
+                } => {
+                    let config = BatchNormConfig {
+                        eps: *eps,
+                        momentum: *momentum,
+                        ..Default::default()
+                    };
+                    Layer::BatchNorm(candle_nn::batch_norm(
+                        input.channels(),
+                        config,
+                        vb.pp(name),
+                    )?)
+                }
+                LayerSpec::Flatten => Layer::Flatten,
+                LayerSpec::Linear { name, out_features } => Layer::Linear(candle_nn::linear(
+                    input.elem_count(),
+                    out_features.unwrap_or(labels.len()),
+                    vb.pp(name),
+                )?),
+                LayerSpec::Activation { kind } => Layer::Activation(*kind),
+            };
+            layers.push(layer);
+        }
+
+        Ok(Self {
+            spec: spec.clone(),
+            labels: labels.clone(),
+            layers,
+            fresh: true,
+        })
+    }
+
+    /// Loads a saved model, building the architecture and label set recorded in its metadata.
+    /// Models without metadata are assumed to be the default architecture predicting digits.
+    pub fn new_from_file(vm: &mut VarMap, path: &PathBuf) -> candle_core::Result<Self> {
+        let (spec, labels) = metadata::read_spec_and_labels(path)?;
+
+        let mut model = ConvNet::new(vm, &spec, &labels)?;
+        vm.load(path)?;
+        model.fresh = false;
+        Ok(model)
+    }
+
+    /// Loads the model saved at `path` and grows its output to predict `labels`, which must
+    /// include every label the saved model knows.
+    ///
+    /// Weights of unchanged layers are kept as they are. In label-sized layers the rows of
+    /// existing labels are copied over and only the rows of new labels keep their fresh
+    /// initialization. Fine-tune the label-sized layers with the rest frozen afterwards to
+    /// teach the model the new labels, as the app's `expand_labels` does.
+    pub fn expand_from_file(
+        vm: &mut VarMap,
+        path: &PathBuf,
+        labels: &LabelSet,
+    ) -> candle_core::Result<Self> {
+        let (spec, old_labels) = metadata::read_spec_and_labels(path)?;
+
+        // Where each saved output row ends up in the expanded model
+        let row_targets = old_labels
+            .labels()
+            .iter()
+            .map(|label| {
+                labels.index_of(label).ok_or_else(|| {
+                    candle_core::Error::Msg(format!(
+                        "New label set {:?} is missing existing label '{}'",
+                        labels.labels(),
+                        label
+                    ))
+                })
+            })
+            .collect::<candle_core::Result<Vec<usize>>>()?;
+
+        let mut model = ConvNet::new(vm, &spec, labels)?;
+        model.fresh = false;
+        let label_sized_layers = spec.label_sized_layers();
+
+        let dev = candle_core::Device::cuda_if_available(0)?;
+        let saved = candle_core::safetensors::load(path, &dev)?;
+
+        for (name, var) in lock_vars(vm)?.iter() {
+            let saved_tensor = saved.get(name).ok_or_else(|| {
+                candle_core::Error::Msg(format!("'{}' is missing from {:?}", name, path))
+            })?;
+
+            let layer = name.split('.').next().unwrap_or_default();
+            let carries_rows = label_sized_layers.contains(&layer)
+                && saved_tensor.dim(0)? == old_labels.len()
+                && saved_tensor.dims()[1..] == var.dims()[1..];
+
+            if !carries_rows {
+                if saved_tensor.shape() != var.shape() {
+                    return Err(candle_core::Error::Msg(format!(
+                        "Can't carry '{}' over, it has shape {:?} but needs {:?}",
+                        name,
+                        saved_tensor.shape(),
+                        var.shape()
+                    )));
+                }
+                var.set(saved_tensor)?;
+                continue;
+            }
+
+            // Start from the fresh rows and copy the existing labels' rows into place
+            let fresh = var.as_tensor().copy()?;
+            let mut rows = (0..labels.len())
+                .map(|i| fresh.get(i))
+                .collect::<candle_core::Result<Vec<Tensor>>>()?;
+            for (old_row, &new_row) in row_targets.iter().enumerate() {
+                rows[new_row] = saved_tensor.get(old_row)?;
+            }
+            var.set(&Tensor::stack(&rows, 0)?)?;
+        }
+
+        println!(
+            "expanded model from {} to {} labels",
+            old_labels.len(),
+            labels.len()
+        );
+
+        Ok(model)
+    }
+
+    /// Atomically saves the model's weights along with its metadata.
+    pub fn save(
+        &self,
+        varmap: &VarMap,
+        path: &Path,
+        training: Option<TrainingSummary>,
+    ) -> candle_core::Result<()> {
+        let metadata = ModelMetadata::current(&self.spec, &self.labels, training);
+        checkpoint::save_atomic(varmap, path, &metadata)
+    }
+
+    pub fn spec(&self) -> &ArchitectureSpec {
+        &self.spec
+    }
+
+    pub fn labels(&self) -> &LabelSet {
+        &self.labels
+    }
+
+    /// Redraws the weights of every conv and linear layer from `rng`, Kaiming-normal weights
+    /// and uniform biases like `candle_nn`'s defaults, which draw from an unseedable generator.
+    fn init_weights(&self, varmap: &VarMap, rng: &mut StdRng) -> candle_core::Result<()> {
+        let vars = lock_vars(varmap)?;
+        let var = |name: String| {
+            vars.get(&name).ok_or_else(|| {
+                candle_core::Error::Msg(format!("Variable '{}' has not been created", name))
+            })
+        };
+
+        for layer in self.spec.layers.iter() {
+            let (LayerSpec::Conv { name, .. } | LayerSpec::Linear { name, .. }) = layer else {
+                continue;
+            };
+
+            let weight = var(format!("{name}.weight"))?;
+            let fan_in = weight.dims()[1..].iter().product::<usize>() as f32;
+            let normal = Normal::new(0.0, (2.0 / fan_in).sqrt())
+                .map_err(|e| candle_core::Error::Msg(e.to_string()))?;
+            let values = (0..weight.elem_count())
+                .map(|_| normal.sample(rng))
+                .collect::<Vec<f32>>();
+            weight.set(&Tensor::from_vec(values, weight.shape(), weight.device())?)?;
+
+            let bias = var(format!("{name}.bias"))?;
+            let bound = 1.0 / fan_in.sqrt();
+            let values = (0..bias.elem_count())
+                .map(|_| rng.gen_range(-bound..bound))
+                .collect::<Vec<f32>>();
+            bias.set(&Tensor::from_vec(values, bias.shape(), bias.device())?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the model on a batch. Passing `rng` puts the model in training mode, where it
+    /// draws the dropout masks.
+    fn forward(&self, xs: &Tensor, rng: Option<&mut StdRng>) -> candle_core::Result<Tensor> {
+        self.forward_observed(xs, rng, &mut |_, _| Ok(()))
+    }
+
+    /// Like `forward`, handing `observe` the input of every layer before running it.
+    pub(crate) fn forward_observed(
+        &self,
+        xs: &Tensor,
+        mut rng: Option<&mut StdRng>,
+        observe: &mut dyn FnMut(&LayerSpec, &Tensor) -> candle_core::Result<()>,
+    ) -> candle_core::Result<Tensor> {
+        let train = rng.is_some();
+        let [channels, height, width] = self.spec.input_shape;
+        let mut xs = xs.reshape(&[xs.dim(0)?, channels, height, width])?;
+
+        for (layer, spec) in self.layers.iter().zip(self.spec.layers.iter()) {
+            observe(spec, &xs)?;
+            xs = match layer {
+                Layer::Conv(conv) => xs.apply(conv)?,
+                Layer::MaxPool { size, stride } => xs.max_pool2d_with_stride(*size, *stride)?,
+                Layer::Dropout(p) => match rng.as_deref_mut() {
+                    Some(rng) => dropout(&xs, *p, rng)?,
+                    None => xs,
+                },
+                // Uses batch statistics (and updates the running ones) while training
+                Layer::BatchNorm(batch_norm) => batch_norm.forward_t(&xs, train)?,
+                Layer::Flatten => xs.flatten_from(1)?,
+                Layer::Linear(linear) => xs.flatten_from(1)?.apply(linear)?,
+                Layer::Activation(activation) => activation.apply(&xs)?,
+            };
+        }
+
+        Ok(xs)
+    }
+
+    /// The raw outputs for a batch of images, one row of logits per image in label order.
+    pub fn logits(&self, images: &Tensor) -> candle_core::Result<Tensor> {
+        self.forward(images, None)
+    }
+
+    pub fn predict(
+        &self,
+        image: &Tensor,
+        device: &candle_core::Device,
+    ) -> candle_core::Result<String> {
+        let image = image.unsqueeze(0)?;
+        let logits = self.forward(&image.to_device(device)?, None)?;
+        let label = logits.argmax(D::Minus1)?;
+        let label = label.squeeze(0)?;
+        let label = label.to_scalar::<u32>()?;
+        let label = self.labels.name(label as usize).ok_or_else(|| {
+            candle_core::Error::Msg(format!("Model predicted unknown label index {}", label))
+        })?;
+        Ok(label.to_string())
+    }
+
+    pub fn train(
+        &self,
+        m: &candle_datasets::vision::Dataset,
+        args: &TrainingArgs,
+        varmap: &mut VarMap,
+    ) -> candle_core::Result<TrainingSummary> {
+        let dev = candle_core::Device::cuda_if_available(0)?;
+
+        let train_labels = m.train_labels.to_dtype(DType::U32)?.to_device(&dev)?;
+        let train_images = m.train_images.to_device(&dev)?;
+
+        let mut rng = utils::seeded_rng(args.seed);
+
+        if let Some(load) = &args.load {
+            println!("loading weights from {load}");
+            varmap.load(load)?
+        } else if self.fresh {
+            self.init_weights(varmap, &mut rng)?;
+        }
+
+        let adamw_params = candle_nn::ParamsAdamW {
+            lr: args.learning_rate,
+            ..Default::default()
+        };
+
+        let trainable = trainable_vars(varmap, &self.spec.layer_names(), &args.frozen_layers)?;
+        let mut opt = candle_nn::AdamW::new(trainable, adamw_params)?;
+
+        let test_images = m.test_images.to_device(&dev)?;
+        let test_labels = m.test_labels.to_dtype(DType::U32)?.to_device(&dev)?;
+
+        let n_batches = train_images.dim(0)? / args.batch_size;
+
+        let mut batch_idxs = (0..n_batches).collect::<Vec<usize>>();
+
+        // Best-so-far weights, kept in memory so the saved model is the best epoch
+        let mut best_weights: Option<HashMap<String, Tensor>> = None;
+        let mut summary = TrainingSummary {
+            seed: args.seed,
+            ..Default::default()
+        };
+        let mut epochs_without_improvement = 0;
+        let mut history = TrainingHistory::default();
+        let mut events = args
+            .log_dir
+            .as_ref()
+            .map(|dir| EventWriter::create(Path::new(dir)))
+            .transpose()?;
+        let mut global_step = 0;
+
+        for epoch in 1..=args.epochs {
+            let mut sum_loss = 0f32;
+
+            batch_idxs.shuffle(&mut rng);
+
+            for (step, batch_idx) in batch_idxs.iter().enumerate() {
+                let train_images =
+                    train_images.narrow(0, batch_idx * args.batch_size, args.batch_size)?;
+                let train_labels =
+                    train_labels.narrow(0, batch_idx * args.batch_size, args.batch_size)?;
+                let logits = self.forward(&train_images, Some(&mut rng))?;
+                let loss = match &args.distillation {
+                    Some(distillation) => {
+                        distillation.loss(&train_images, &logits, &train_labels)?
+                    }
+                    None => loss::nll(&ops::log_softmax(&logits, D::Minus1)?, &train_labels)?,
+                };
+                opt.backward_step(&loss)?;
+                let loss = loss.to_vec0::<f32>()?;
+                history.record_batch(epoch, step, loss);
+                sum_loss += loss;
+
+                global_step += 1;
+                if let Some(events) = events.as_mut() {
+                    events.add_scalar("train/loss", loss, global_step)?;
+                }
+            }
+
+            let avg_loss = sum_loss / n_batches as f32;
+
+            let test_logits = self.forward(&test_images, None)?;
+            let test_prediction = test_logits.argmax(D::Minus1)?;
+
+            let sum_ok = test_prediction
+                .eq(&test_labels)?
+                .to_dtype(DType::F32)?
+                .sum_all()?
+                .to_scalar::<f32>()?;
+
+            let test_accuracy = sum_ok / test_labels.dims1()? as f32;
+
+            println!(
+                "{epoch:4} train loss {:8.5} test acc: {:5.2}%",
+                avg_loss,
+                100. * test_accuracy
+            );
+
+            history.record_epoch(epoch, avg_loss, test_accuracy);
+
+            if let Some(events) = events.as_mut() {
+                events.add_scalar("epoch/train_loss", avg_loss, global_step)?;
+                events.add_scalar("epoch/test_accuracy", test_accuracy, global_step)?;
+                events.add_scalar(
+                    "epoch/learning_rate",
+                    opt.learning_rate() as f32,
+                    global_step,
+                )?;
+                log_weights(events, varmap, global_step)?;
+                self.log_predictions(events, &test_images, &test_prediction, global_step)?;
+                events.flush()?;
+            }
+            summary.epochs_run = epoch;
+
+            if best_weights.is_none() || test_accuracy > summary.test_accuracy {
+                best_weights = Some(snapshot_weights(varmap)?);
+                summary.best_epoch = epoch;
+                summary.test_accuracy = test_accuracy;
+                summary.train_loss = avg_loss;
+                epochs_without_improvement = 0;
+            } else {
+                epochs_without_improvement += 1;
+                if args
+                    .patience
+                    .is_some_and(|patience| epochs_without_improvement >= patience)
+                {
+                    println!(
+                        "stopping early, no improvement for {epochs_without_improvement} epochs"
+                    );
+                    break;
+                }
+            }
+        }
+
+        if let Some(weights) = &best_weights {
+            if summary.best_epoch != summary.epochs_run {
+                println!(
+                    "restoring weights from epoch {} (test acc: {:5.2}%)",
+                    summary.best_epoch,
+                    100. * summary.test_accuracy
+                );
+                restore_weights(varmap, weights)?;
+            }
+        }
+
+        if let Some(save) = &args.save {
+            println!("saving trained weights in {save}");
+            self.save(varmap, save.as_ref(), Some(summary.clone()))?;
+            history.save(save.as_ref())?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Logs the first test images, one image per predicted label with every sample predicted
+    /// as that label side by side.
+    fn log_predictions(
+        &self,
+        events: &mut EventWriter,
+        test_images: &Tensor,
+        test_prediction: &Tensor,
+        step: i64,
+    ) -> candle_core::Result<()> {
+        let n = test_images.dim(0)?.min(PREDICTION_SAMPLES);
+        let images = test_images
+            .narrow(0, 0, n)?
+            .flatten_from(1)?
+            .to_vec2::<f32>()?;
+        let predictions = test_prediction.narrow(0, 0, n)?.to_vec1::<u32>()?;
+        let [_, height, width] = self.spec.input_shape;
+
+        for (label_idx, label) in self.labels.labels().iter().enumerate() {
+            let samples = images
+                .iter()
+                .zip(predictions.iter())
+                .filter(|(_, &predicted)| predicted as usize == label_idx)
+                .map(|(image, _)| image)
+                .collect::<Vec<&Vec<f32>>>();
+            if samples.is_empty() {
+                continue;
+            }
+
+            let grid = GrayImage::from_fn((width * samples.len()) as u32, height as u32, |x, y| {
+                let (sample, x) = (x as usize / width, x as usize % width);
+                let value = samples[sample][y as usize * width + x];
+                Luma([(value * 255.0).clamp(0.0, 255.0) as u8])
+            });
+            events.add_image(&format!("predictions/{}", label), &grid, step)?;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the model on the test split of `data`, returning the accuracy in percent.
+    pub fn test(
+        &self,
+        device: &candle_core::Device,
+        data: &candle_datasets::vision::Dataset,
+        batch_size: usize,
+    ) -> candle_core::Result<f32> {
+        let test_start_time = std::time::Instant::now();
+
+        let test_images = data.test_images.to_device(device)?;
+        let test_labels = data.test_labels.to_device(device)?;
+        let n_batches = test_images.dim(0)? / batch_size;
+        let mut correct_predictions = 0;
+        let mut total_predictions = 0;
+
+        for i in 0..n_batches {
+            let images = test_images.narrow(0, i * batch_size, batch_size)?;
+            let labels = test_labels.narrow(0, i * batch_size, batch_size)?;
+            let labels = labels.to_dtype(DType::U32)?;
+
+            let logits = self.forward(&images, None)?;
+
+            let predictions = logits.argmax(D::Minus1)?;
+
+            correct_predictions += predictions
+                .eq(&labels)?
+                .to_dtype(DType::F32)?
+                .sum_all()?
+                .to_scalar::<f32>()? as usize;
+
+            total_predictions += batch_size;
+        }
+
+        let accuracy = correct_predictions as f32 / total_predictions as f32 * 100.0;
+
+        println!(
+            "Test Accuracy: {:.2}% - took {:.2} seconds",
+            accuracy,
+            test_start_time.elapsed().as_secs_f64()
+        );
+
+        Ok(accuracy)
+    }
+
+    /// Parameter count and test accuracy of the model with the weights in `varmap`, and the
+    /// size of the file at `path` it is saved in.
+    pub fn stats(
+        &self,
+        varmap: &VarMap,
+        path: &Path,
+        data: &candle_datasets::vision::Dataset,
+        device: &candle_core::Device,
+    ) -> candle_core::Result<ModelStats> {
+        let batch_size = data.test_images.dim(0)?.clamp(1, 100);
+
+        Ok(ModelStats {
+            parameters: varmap.all_vars().iter().map(|var| var.elem_count()).sum(),
+            file_bytes: std::fs::metadata(path)?.len(),
+            test_accuracy: self.test(device, data, batch_size)?,
+        })
+    }
+}
+
+/// Size and accuracy of a saved model, as pruning and distillation report them.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelStats {
+    pub parameters: usize,
+    pub file_bytes: u64,
+    /// Test accuracy in percent.
+    pub test_accuracy: f32,
+}
+
+pub struct TrainingArgs {
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    pub load: Option<String>,
+    /// Where the trained model is saved, along with its `TrainingHistory`.
+    pub save: Option<String>,
+    /// Layers (see `ArchitectureSpec::layer_names`) whose weights are left untouched by the
+    /// optimizer.
+    pub frozen_layers: Vec<String>,
+    /// Stop once test accuracy hasn't improved for this many epochs.
+    pub patience: Option<usize>,
+    /// Writes TensorBoard event files (loss, accuracy and learning rate scalars, weight
+    /// histograms and sample predictions) into this run directory.
+    pub log_dir: Option<String>,
+    /// Seeds batch shuffling, dropout and, for a model fresh from `ConvNet::new`, the initial
+    /// weights. Two CPU runs with the same seed and data produce identical weights.
+    pub seed: Option<u64>,
+    /// Trains against a teacher model's soft targets as well as the labels.
+    pub distillation: Option<Distillation>,
+}
+
+/// Metrics of the epoch whose weights `ConvNet::train` kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrainingSummary {
+    pub epochs_run: usize,
+    pub best_epoch: usize,
+    pub test_accuracy: f32,
+    pub train_loss: f32,
+    /// `TrainingArgs::seed`, so a run can be reproduced.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Zeroes each element with probability `p` and scales the rest by `1 / (1 - p)`, drawing the
+/// mask from `rng`.
+fn dropout(xs: &Tensor, p: f32, rng: &mut StdRng) -> candle_core::Result<Tensor> {
+    let scale = 1.0 / (1.0 - p);
+    let mask = (0..xs.elem_count())
+        .map(|_| if rng.gen::<f32>() < p { 0.0 } else { scale })
+        .collect::<Vec<f32>>();
+    xs.mul(&Tensor::from_vec(mask, xs.shape(), xs.device())?)
+}
+
+/// Logs a histogram of every variable's values.
+fn log_weights(events: &mut EventWriter, varmap: &VarMap, step: i64) -> candle_core::Result<()> {
+    let vars = lock_vars(varmap)?;
+    let mut names = vars.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    for name in names {
+        let values = vars[name].as_tensor().flatten_all()?.to_vec1::<f32>()?;
+        events.add_histogram(&format!("weights/{}", name), &values, step)?;
+    }
+
+    Ok(())
+}
+
+fn lock_vars(varmap: &VarMap) -> candle_core::Result<MutexGuard<'_, HashMap<String, Var>>> {
+    varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))
+}
+
+/// Copies every variable in `varmap` so later optimizer steps don't touch the copies.
+fn snapshot_weights(varmap: &VarMap) -> candle_core::Result<HashMap<String, Tensor>> {
+    lock_vars(varmap)?
+        .iter()
+        .map(|(name, var)| Ok((name.clone(), var.as_tensor().copy()?)))
+        .collect()
+}
+
+fn restore_weights(varmap: &VarMap, weights: &HashMap<String, Tensor>) -> candle_core::Result<()> {
+    for (name, var) in lock_vars(varmap)?.iter() {
+        if let Some(tensor) = weights.get(name) {
+            var.set(tensor)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the variables of every layer in `varmap` that isn't listed in `frozen_layers`.
+fn trainable_vars(
+    varmap: &VarMap,
+    layer_names: &[&str],
+    frozen_layers: &[String],
+) -> candle_core::Result<Vec<Var>> {
+    if let Some(unknown) = frozen_layers
+        .iter()
+        .find(|layer| !layer_names.contains(&layer.as_str()))
+    {
+        return Err(candle_core::Error::Msg(format!(
+            "Unknown layer '{}', expected one of {:?}",
+            unknown, layer_names
+        )));
+    }
+
+    let data = lock_vars(varmap)?;
+
+    // Variables are named "<layer>.weight" / "<layer>.bias". Batch norm running statistics
+    // are updated by the forward pass, not the optimizer.
+    let vars = data
+        .iter()
+        .filter(|(name, _)| {
+            let (layer, param) = name.split_once('.').unwrap_or((name.as_str(), ""));
+            !matches!(param, "running_mean" | "running_var")
+                && !frozen_layers.iter().any(|frozen| frozen == layer)
+        })
+        .map(|(_, var)| var.clone())
+        .collect::<Vec<Var>>();
+
+    if vars.is_empty() {
+        return Err(candle_core::Error::Msg(
+            "Every layer is frozen, there is nothing to train".to_string(),
+        ));
+    }
+
+    println!("Training {} of {} variables", vars.len(), data.len());
+
+    Ok(vars)
+}
@@ -0,0 +1,325 @@
+use crate::architecture::{Activation, FeatureShape, LayerSpec};
+use crate::model::ConvNet;
+use crate::proto::Proto;
+use candle_core::{Tensor, Var};
+use candle_nn::VarMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// ONNX IR version 7 goes with opset 13, which every op we emit is part of.
+const IR_VERSION: i64 = 7;
+const OPSET_VERSION: i64 = 13;
+
+/// Name of the graph input, a `[batch, channels, height, width]` float tensor.
+pub const INPUT_NAME: &str = "input";
+/// Name of the graph output, the `[batch, labels]` logits.
+pub const OUTPUT_NAME: &str = "logits";
+
+// Enum values from onnx.proto
+const FLOAT: i64 = 1;
+const ATTRIBUTE_FLOAT: i64 = 1;
+const ATTRIBUTE_INT: i64 = 2;
+const ATTRIBUTE_INTS: i64 = 7;
+
+/// Writes `model` with the weights in `varmap` as an ONNX model at `path`.
+pub fn export(model: &ConvNet, varmap: &VarMap, path: &Path) -> candle_core::Result<()> {
+    fs::write(path, to_onnx(model, varmap)?)?;
+    Ok(())
+}
+
+/// Encodes `model` as an ONNX graph equivalent to `ConvNet::logits`, with the weights in
+/// `varmap` as initializers. Dropout is left out as it does nothing at inference, and the
+/// label names are stored in the `labels` metadata property.
+pub fn to_onnx(model: &ConvNet, varmap: &VarMap) -> candle_core::Result<Vec<u8>> {
+    let spec = model.spec();
+    let labels = model.labels();
+    let weights = varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?
+        .clone();
+
+    let mut graph = GraphBuilder::new(&weights);
+    let mut xs = INPUT_NAME.to_string();
+
+    for (layer, input) in spec.layers.iter().zip(spec.shapes(labels.len())?) {
+        xs = match layer {
+            LayerSpec::Conv {
+                name,
+                kernel,
+                stride,
+                padding,
+                ..
+            } => {
+                let (kernel, stride, padding) = (*kernel as i64, *stride as i64, *padding as i64);
+                let weight = graph.weight(name, "weight")?;
+                let bias = graph.weight(name, "bias")?;
+                graph.node(
+                    "Conv",
+                    &[&xs, &weight, &bias],
+                    vec![
+                        attribute_ints("kernel_shape", &[kernel, kernel]),
+                        attribute_ints("strides", &[stride, stride]),
+                        attribute_ints("pads", &[padding; 4]),
+                    ],
+                )
+            }
+            LayerSpec::MaxPool { size, stride } => {
+                let size = *size as i64;
+                let stride = stride.map(|s| s as i64).unwrap_or(size);
+                graph.node(
+                    "MaxPool",
+                    &[&xs],
+                    vec![
+                        attribute_ints("kernel_shape", &[size, size]),
+                        attribute_ints("strides", &[stride, stride]),
+                    ],
+                )
+            }
+            LayerSpec::Dropout { .. } => xs,
+            LayerSpec::BatchNorm { name, eps, .. } => {
+                let params = ["weight", "bias", "running_mean", "running_var"]
+                    .into_iter()
+                    .map(|param| graph.weight(name, param))
+                    .collect::<candle_core::Result<Vec<String>>>()?;
+                graph.node(
+                    "BatchNormalization",
+                    &[&xs, &params[0], &params[1], &params[2], &params[3]],
+                    vec![attribute_float("epsilon", *eps as f32)],
+                )
+            }
+            LayerSpec::Flatten => graph.node("Flatten", &[&xs], vec![attribute_int("axis", 1)]),
+            LayerSpec::Linear { name, .. } => {
+                // `ConvNet` flattens before every linear layer
+                let flat = match input {
+                    FeatureShape::Spatial { .. } => {
+                        graph.node("Flatten", &[&xs], vec![attribute_int("axis", 1)])
+                    }
+                    FeatureShape::Flat(_) => xs,
+                };
+                let weight = graph.weight(name, "weight")?;
+                let bias = graph.weight(name, "bias")?;
+                graph.node(
+                    "Gemm",
+                    &[&flat, &weight, &bias],
+                    vec![attribute_int("transB", 1)],
+                )
+            }
+            LayerSpec::Activation { kind } => graph.activation(*kind, &xs)?,
+        };
+    }
+
+    let [channels, height, width] = spec.input_shape;
+    let input = value_info(
+        INPUT_NAME,
+        &[None, Some(channels), Some(height), Some(width)],
+    );
+    let output = value_info(OUTPUT_NAME, &[None, Some(labels.len())]);
+
+    // Node outputs are named after their node, so route the last one to the graph output
+    graph
+        .nodes
+        .push(node_proto("output", "Identity", &[&xs], OUTPUT_NAME, &[]));
+
+    let mut graph_proto = Proto::default();
+    for node in graph.nodes.iter() {
+        graph_proto.message(1, node);
+    }
+    graph_proto.string(2, &spec.id);
+    for initializer in graph.initializers.values() {
+        graph_proto.message(5, initializer);
+    }
+    graph_proto.message(11, &input);
+    graph_proto.message(12, &output);
+
+    let labels_json = serde_json::to_string(labels.labels())
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to serialize labels: {}", e)))?;
+    let mut labels_entry = Proto::default();
+    labels_entry.string(1, "labels");
+    labels_entry.string(2, &labels_json);
+
+    let mut opset = Proto::default();
+    opset.string(1, "");
+    opset.int64(2, OPSET_VERSION);
+
+    let mut model_proto = Proto::default();
+    model_proto.int64(1, IR_VERSION);
+    model_proto.string(2, "train-an-ai");
+    model_proto.string(3, env!("CARGO_PKG_VERSION"));
+    model_proto.message(7, &graph_proto);
+    model_proto.message(8, &opset);
+    model_proto.message(14, &labels_entry);
+
+    Ok(model_proto.0)
+}
+
+/// Collects the nodes and initializers of the graph, naming each node output after its node.
+struct GraphBuilder<'a> {
+    weights: &'a HashMap<String, Var>,
+    nodes: Vec<Proto>,
+    // Ordered by name so exports are reproducible
+    initializers: BTreeMap<String, Proto>,
+}
+
+impl<'a> GraphBuilder<'a> {
+    fn new(weights: &'a HashMap<String, Var>) -> Self {
+        Self {
+            weights,
+            nodes: Vec::new(),
+            initializers: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a node and returns the name of its output.
+    fn node(&mut self, op_type: &str, inputs: &[&str], attributes: Vec<Proto>) -> String {
+        let name = format!("{}_{}", op_type.to_lowercase(), self.nodes.len());
+        let output = format!("{}_out", name);
+
+        self.nodes
+            .push(node_proto(&name, op_type, inputs, &output, &attributes));
+
+        output
+    }
+
+    /// Adds the variable `<layer>.<param>` as an initializer and returns its name.
+    fn weight(&mut self, layer: &str, param: &str) -> candle_core::Result<String> {
+        let name = format!("{}.{}", layer, param);
+        let var = self.weights.get(&name).ok_or_else(|| {
+            candle_core::Error::Msg(format!("Variable '{}' has not been created", name))
+        })?;
+        let initializer = tensor_proto(&name, var.as_tensor())?;
+        self.initializers.insert(name.clone(), initializer);
+        Ok(name)
+    }
+
+    fn constant(&mut self, name: &str, value: f32) -> candle_core::Result<String> {
+        let tensor = Tensor::new(value, &candle_core::Device::Cpu)?;
+        self.initializers
+            .insert(name.to_string(), tensor_proto(name, &tensor)?);
+        Ok(name.to_string())
+    }
+
+    fn activation(&mut self, kind: Activation, xs: &str) -> candle_core::Result<String> {
+        Ok(match kind {
+            Activation::Relu => self.node("Relu", &[xs], Vec::new()),
+            Activation::LeakyRelu => {
+                self.node("LeakyRelu", &[xs], vec![attribute_float("alpha", 0.01)])
+            }
+            Activation::Tanh => self.node("Tanh", &[xs], Vec::new()),
+            Activation::Silu => {
+                let sigmoid = self.node("Sigmoid", &[xs], Vec::new());
+                self.node("Mul", &[xs, &sigmoid], Vec::new())
+            }
+            // Gelu only became an op in opset 20, so spell out the tanh approximation candle
+            // uses: 0.5 * x * (1 + tanh(sqrt(2 / pi) * (x + 0.044715 * x^3)))
+            Activation::Gelu => {
+                let half = self.constant("gelu.half", 0.5)?;
+                let one = self.constant("gelu.one", 1.0)?;
+                let coefficient = self.constant("gelu.coefficient", 0.044715)?;
+                let scale = self.constant("gelu.scale", (2.0 / std::f32::consts::PI).sqrt())?;
+
+                let squared = self.node("Mul", &[xs, xs], Vec::new());
+                let cubed = self.node("Mul", &[&squared, xs], Vec::new());
+                let scaled_cube = self.node("Mul", &[&cubed, &coefficient], Vec::new());
+                let inner = self.node("Add", &[xs, &scaled_cube], Vec::new());
+                let inner = self.node("Mul", &[&inner, &scale], Vec::new());
+                let tanh = self.node("Tanh", &[&inner], Vec::new());
+                let gate = self.node("Add", &[&tanh, &one], Vec::new());
+                let gated = self.node("Mul", &[xs, &gate], Vec::new());
+                self.node("Mul", &[&gated, &half], Vec::new())
+            }
+        })
+    }
+}
+
+fn node_proto(
+    name: &str,
+    op_type: &str,
+    inputs: &[&str],
+    output: &str,
+    attributes: &[Proto],
+) -> Proto {
+    let mut node = Proto::default();
+    for input in inputs {
+        node.string(1, input);
+    }
+    node.string(2, output);
+    node.string(3, name);
+    node.string(4, op_type);
+    for attribute in attributes {
+        node.message(5, attribute);
+    }
+    node
+}
+
+/// A float tensor stored as little-endian raw data.
+fn tensor_proto(name: &str, tensor: &Tensor) -> candle_core::Result<Proto> {
+    let values = tensor.flatten_all()?.to_vec1::<f32>()?;
+
+    let mut proto = Proto::default();
+    for &dim in tensor.dims() {
+        proto.int64(1, dim as i64);
+    }
+    proto.int64(2, FLOAT);
+    proto.string(8, name);
+    proto.bytes(
+        9,
+        &values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>(),
+    );
+    Ok(proto)
+}
+
+/// A float tensor input or output. `None` dimensions are the batch size, left symbolic.
+fn value_info(name: &str, dims: &[Option<usize>]) -> Proto {
+    let mut shape = Proto::default();
+    for dim in dims {
+        let mut dimension = Proto::default();
+        match dim {
+            Some(value) => dimension.int64(1, *value as i64),
+            None => dimension.string(2, "batch"),
+        }
+        shape.message(1, &dimension);
+    }
+
+    let mut tensor_type = Proto::default();
+    tensor_type.int64(1, FLOAT);
+    tensor_type.message(2, &shape);
+
+    let mut type_proto = Proto::default();
+    type_proto.message(1, &tensor_type);
+
+    let mut value_info = Proto::default();
+    value_info.string(1, name);
+    value_info.message(2, &type_proto);
+    value_info
+}
+
+fn attribute_int(name: &str, value: i64) -> Proto {
+    let mut attribute = Proto::default();
+    attribute.string(1, name);
+    attribute.int64(3, value);
+    attribute.int64(20, ATTRIBUTE_INT);
+    attribute
+}
+
+fn attribute_ints(name: &str, values: &[i64]) -> Proto {
+    let mut attribute = Proto::default();
+    attribute.string(1, name);
+    for &value in values {
+        attribute.int64(8, value);
+    }
+    attribute.int64(20, ATTRIBUTE_INTS);
+    attribute
+}
+
+fn attribute_float(name: &str, value: f32) -> Proto {
+    let mut attribute = Proto::default();
+    attribute.string(1, name);
+    attribute.float(2, value);
+    attribute.int64(20, ATTRIBUTE_FLOAT);
+    attribute
+}
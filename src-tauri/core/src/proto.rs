@@ -0,0 +1,55 @@
+/// A protocol buffer message being encoded, just enough of the wire format for the TensorBoard
+/// event files and ONNX models we write.
+#[derive(Default)]
+pub(crate) struct Proto(pub(crate) Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    pub(crate) fn int64(&mut self, field: u32, value: i64) {
+        self.key(field, 0);
+        self.varint(value as u64);
+    }
+
+    pub(crate) fn double(&mut self, field: u32, value: f64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn float(&mut self, field: u32, value: f32) {
+        self.key(field, 5);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, 2);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    pub(crate) fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    pub(crate) fn message(&mut self, field: u32, message: &Proto) {
+        self.bytes(field, &message.0);
+    }
+
+    pub(crate) fn packed_doubles(&mut self, field: u32, values: &[f64]) {
+        let bytes = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<u8>>();
+        self.bytes(field, &bytes);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::architecture::{ArchitectureSpec, FeatureShape, LayerSpec};
+use crate::model::{ConvNet, ModelStats, TrainingArgs, TrainingSummary};
+use candle_core::{Device, Tensor, D};
+use candle_datasets::vision::Dataset;
+use candle_nn::VarMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Suffix added to the architecture id of pruned models, as their layers are narrower.
+const PRUNED_SUFFIX: &str = "-pruned";
+
+/// How many channels or neurons of a layer pruning kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct PrunedLayer {
+    pub name: String,
+    pub before: usize,
+    pub after: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PruningReport {
+    /// Share of channels and neurons removed from every prunable layer.
+    pub amount: f32,
+    pub layers: Vec<PrunedLayer>,
+    pub before: ModelStats,
+    /// After fine-tuning.
+    pub after: ModelStats,
+    pub fine_tuning: TrainingSummary,
+    /// Settings the pruned model was fine-tuned with.
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub batch_size: usize,
+}
+
+/// Removes the `amount` share of output channels of every conv layer, and of neurons of every
+/// linear layer but the label-sized ones, with the smallest L1 norm. The inputs that fed on
+/// them are cut from the following layers too, so the result is a narrower architecture
+/// rather than one padded with zeros.
+///
+/// Returns the pruned model, ready for fine-tuning, and the variables holding its weights.
+pub fn prune(
+    model: &ConvNet,
+    varmap: &VarMap,
+    amount: f32,
+) -> candle_core::Result<(ConvNet, VarMap, Vec<PrunedLayer>)> {
+    if !(0.0..1.0).contains(&amount) {
+        return Err(candle_core::Error::Msg(format!(
+            "Can't prune {} of each layer, expected a share in [0, 1)",
+            amount
+        )));
+    }
+
+    let spec = model.spec();
+    let labels = model.labels();
+    let vars = varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?
+        .clone();
+    let var = |layer: &str, param: &str| {
+        let name = format!("{}.{}", layer, param);
+        vars.get(&name)
+            .map(|var| var.as_tensor().clone())
+            .ok_or_else(|| {
+                candle_core::Error::Msg(format!("Variable '{}' has not been created", name))
+            })
+    };
+
+    let mut layers = Vec::with_capacity(spec.layers.len());
+    let mut weights = HashMap::new();
+    let mut pruned = Vec::new();
+    // Which of the unpruned model's channels (or flat features) the current features are
+    let mut kept: Option<Vec<u32>> = None;
+
+    for (layer, input) in spec.layers.iter().zip(spec.shapes(labels.len())?) {
+        // Conv and linear layers lose the inputs whose channels were pruned before them
+        let mut inputs_of = |name: &str| -> candle_core::Result<Tensor> {
+            let weight = var(name, "weight")?;
+            match kept.take() {
+                Some(inputs) => {
+                    let inputs = match input {
+                        FeatureShape::Spatial { height, width, .. }
+                            if matches!(layer, LayerSpec::Linear { .. }) =>
+                        {
+                            flatten_channels(&inputs, height * width)
+                        }
+                        _ => inputs,
+                    };
+                    weight.index_select(&indices(&inputs, weight.device())?, 1)
+                }
+                None => Ok(weight),
+            }
+        };
+
+        let layer = match layer {
+            LayerSpec::Conv {
+                name,
+                out_channels,
+                kernel,
+                stride,
+                padding,
+            } => {
+                let weight = inputs_of(name)?;
+                let keep = strongest(&weight, amount)?;
+                let rows = indices(&keep, weight.device())?;
+                weights.insert(format!("{name}.weight"), weight.index_select(&rows, 0)?);
+                weights.insert(
+                    format!("{name}.bias"),
+                    var(name, "bias")?.index_select(&rows, 0)?,
+                );
+                pruned.push(PrunedLayer {
+                    name: name.clone(),
+                    before: *out_channels,
+                    after: keep.len(),
+                });
+                let after = keep.len();
+                kept = Some(keep);
+
+                LayerSpec::Conv {
+                    name: name.clone(),
+                    out_channels: after,
+                    kernel: *kernel,
+                    stride: *stride,
+                    padding: *padding,
+                }
+            }
+            LayerSpec::BatchNorm { name, .. } => {
+                for param in ["weight", "bias", "running_mean", "running_var"] {
+                    let tensor = var(name, param)?;
+                    let tensor = match &kept {
+                        Some(keep) => tensor.index_select(&indices(keep, tensor.device())?, 0)?,
+                        None => tensor,
+                    };
+                    weights.insert(format!("{name}.{param}"), tensor);
+                }
+                layer.clone()
+            }
+            LayerSpec::Flatten => {
+                if let (Some(keep), FeatureShape::Spatial { height, width, .. }) = (&kept, input) {
+                    kept = Some(flatten_channels(keep, height * width));
+                }
+                layer.clone()
+            }
+            LayerSpec::Linear { name, out_features } => {
+                let weight = inputs_of(name)?;
+                let bias = var(name, "bias")?;
+
+                match out_features {
+                    Some(out_features) => {
+                        let keep = strongest(&weight, amount)?;
+                        let rows = indices(&keep, weight.device())?;
+                        weights.insert(format!("{name}.weight"), weight.index_select(&rows, 0)?);
+                        weights.insert(format!("{name}.bias"), bias.index_select(&rows, 0)?);
+                        pruned.push(PrunedLayer {
+                            name: name.clone(),
+                            before: *out_features,
+                            after: keep.len(),
+                        });
+                        let after = keep.len();
+                        kept = Some(keep);
+
+                        LayerSpec::Linear {
+                            name: name.clone(),
+                            out_features: Some(after),
+                        }
+                    }
+                    // One output per label, which all stay
+                    None => {
+                        weights.insert(format!("{name}.weight"), weight);
+                        weights.insert(format!("{name}.bias"), bias);
+                        layer.clone()
+                    }
+                }
+            }
+            LayerSpec::MaxPool { .. }
+            | LayerSpec::Dropout { .. }
+            | LayerSpec::Activation { .. } => layer.clone(),
+        };
+        layers.push(layer);
+    }
+
+    let id = if spec.id.ends_with(PRUNED_SUFFIX) {
+        spec.id.clone()
+    } else {
+        format!("{}{}", spec.id, PRUNED_SUFFIX)
+    };
+    let pruned_spec = ArchitectureSpec {
+        id,
+        input_shape: spec.input_shape,
+        layers,
+    };
+
+    let mut pruned_varmap = VarMap::new();
+    let mut pruned_model = ConvNet::new(&mut pruned_varmap, &pruned_spec, labels)?;
+    let pruned_vars = pruned_varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?;
+    for (name, var) in pruned_vars.iter() {
+        let tensor = weights
+            .get(name)
+            .ok_or_else(|| candle_core::Error::Msg(format!("No pruned weights for '{}'", name)))?;
+        var.set(&tensor.to_device(var.device())?)?;
+    }
+    drop(pruned_vars);
+    pruned_model.fresh = false;
+
+    Ok((pruned_model, pruned_varmap, pruned))
+}
+
+/// Prunes the model saved at `model_path` (see `prune`), fine-tunes it on `data` for `epochs`
+/// and saves it to `out`, reporting parameter count, file size and test accuracy before and
+/// after.
+pub fn prune_and_fine_tune(
+    model_path: &Path,
+    out: &Path,
+    amount: f32,
+    epochs: usize,
+    data: &Dataset,
+    device: &Device,
+) -> candle_core::Result<PruningReport> {
+    let mut varmap = VarMap::new();
+    let model = ConvNet::new_from_file(&mut varmap, &model_path.to_path_buf())?;
+    let before = model.stats(&varmap, model_path, data, device)?;
+
+    let (pruned_model, mut pruned_varmap, layers) = prune(&model, &varmap, amount)?;
+
+    let args = TrainingArgs {
+        epochs,
+        learning_rate: 0.01,
+        batch_size: 10,
+        load: None,
+        save: Some(out.to_string_lossy().to_string()),
+        frozen_layers: Vec::new(),
+        patience: None,
+        log_dir: None,
+        seed: None,
+        distillation: None,
+    };
+    let fine_tuning = pruned_model.train(data, &args, &mut pruned_varmap)?;
+
+    let after = pruned_model.stats(&pruned_varmap, out, data, device)?;
+
+    Ok(PruningReport {
+        amount,
+        layers,
+        before,
+        after,
+        fine_tuning,
+        epochs: args.epochs,
+        learning_rate: args.learning_rate,
+        batch_size: args.batch_size,
+    })
+}
+
+/// Indices of the rows of `weight` (one per output channel or neuron) to keep after removing
+/// the `amount` share with the smallest L1 norm, in their original order. At least one row
+/// always stays.
+fn strongest(weight: &Tensor, amount: f32) -> candle_core::Result<Vec<u32>> {
+    let norms = weight
+        .flatten_from(1)?
+        .abs()?
+        .sum(D::Minus1)?
+        .to_vec1::<f32>()?;
+
+    let remove = ((norms.len() as f32 * amount).floor() as usize).min(norms.len() - 1);
+    let mut order = (0..norms.len() as u32).collect::<Vec<u32>>();
+    order.sort_by(|&a, &b| norms[a as usize].total_cmp(&norms[b as usize]));
+
+    let mut keep = order.split_off(remove);
+    keep.sort();
+    Ok(keep)
+}
+
+/// The flat feature indices of `channels` once features of `area` positions per channel are
+/// flattened.
+fn flatten_channels(channels: &[u32], area: usize) -> Vec<u32> {
+    channels
+        .iter()
+        .flat_map(|&c| (0..area as u32).map(move |i| c * area as u32 + i))
+        .collect()
+}
+
+fn indices(indices: &[u32], device: &Device) -> candle_core::Result<Tensor> {
+    Tensor::from_vec(indices.to_vec(), indices.len(), device)
+}
@@ -0,0 +1,655 @@
+use crate::architecture::{Activation, ArchitectureSpec, LayerSpec};
+use crate::checkpoint::sibling_path;
+use crate::labels::LabelSet;
+use crate::model::ConvNet;
+use crate::utils::{self, Fnv1a};
+use candle_core::{DType, Device, Tensor, D};
+use candle_datasets::vision::Dataset;
+use candle_nn::VarMap;
+use rand::seq::index;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// How many drawings are run through the model to calibrate it, unless told otherwise.
+pub const DEFAULT_CALIBRATION_SAMPLES: usize = 256;
+
+/// How many single-image predictions are timed per model when comparing latency.
+const LATENCY_SAMPLES: usize = 100;
+
+/// Value ranges of the inputs of every conv and linear layer, measured on sample drawings, which
+/// fix how their activations are mapped onto 8 bits. Saved next to the model as
+/// `<model>.int8.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// Fingerprint of the weights calibrated for, so a calibration made stale by training isn't
+    /// used with the new weights.
+    pub weights_fingerprint: u64,
+    pub samples: usize,
+    /// `[min, max]` of the input of each quantized layer, by layer name.
+    pub ranges: BTreeMap<String, [f32; 2]>,
+}
+
+impl Calibration {
+    /// Runs `images` through `model` and records the range of every conv and linear input.
+    pub fn measure(
+        model: &ConvNet,
+        varmap: &VarMap,
+        images: &Tensor,
+        batch_size: usize,
+        device: &Device,
+    ) -> candle_core::Result<Self> {
+        let samples = images.dim(0)?;
+        if samples == 0 {
+            return Err(candle_core::Error::Msg(
+                "Calibration needs at least one sample".to_string(),
+            ));
+        }
+
+        let mut ranges: BTreeMap<String, [f32; 2]> = BTreeMap::new();
+        for start in (0..samples).step_by(batch_size.max(1)) {
+            let batch = images
+                .narrow(0, start, batch_size.min(samples - start))?
+                .to_device(device)?;
+
+            model.forward_observed(&batch, None, &mut |layer, xs| {
+                let (LayerSpec::Conv { name, .. } | LayerSpec::Linear { name, .. }) = layer else {
+                    return Ok(());
+                };
+                let xs = xs.flatten_all()?;
+                let min = xs.min(0)?.to_scalar::<f32>()?;
+                let max = xs.max(0)?.to_scalar::<f32>()?;
+
+                let range = ranges.entry(name.clone()).or_insert([min, max]);
+                range[0] = range[0].min(min);
+                range[1] = range[1].max(max);
+                Ok(())
+            })?;
+        }
+
+        Ok(Self {
+            weights_fingerprint: fingerprint(varmap)?,
+            samples,
+            ranges,
+        })
+    }
+
+    /// Writes the calibration of the model saved at `model_path`.
+    pub fn save(&self, model_path: &Path) -> candle_core::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to serialize calibration: {}", e))
+        })?;
+        fs::write(calibration_path(model_path), json)?;
+        Ok(())
+    }
+
+    /// Reads the calibration of the model saved at `model_path`, if it has one.
+    pub fn load(model_path: &Path) -> candle_core::Result<Option<Self>> {
+        let path = calibration_path(model_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let calibration = serde_json::from_str(&fs::read_to_string(&path)?).map_err(|e| {
+            candle_core::Error::Msg(format!("Invalid calibration {:?}: {}", path, e))
+        })?;
+        Ok(Some(calibration))
+    }
+}
+
+/// Path of the int8 calibration of the model at `model_path`.
+pub fn calibration_path(model_path: &Path) -> PathBuf {
+    sibling_path(model_path, ".int8.json")
+}
+
+/// Maps f32 activations onto `u8`, with `x ≈ (q - zero_point) * scale`.
+#[derive(Debug, Clone, Copy)]
+struct ActivationQuantizer {
+    scale: f32,
+    zero_point: i32,
+}
+
+impl ActivationQuantizer {
+    /// Covers `[min, max]`, stretched to include 0 so that zero padding is exact.
+    fn new([min, max]: [f32; 2]) -> Self {
+        let (min, max) = (min.min(0.0), max.max(0.0));
+        let scale = if max > min { (max - min) / 255.0 } else { 1.0 };
+        let zero_point = (-min / scale).round().clamp(0.0, 255.0) as i32;
+        Self { scale, zero_point }
+    }
+
+    /// Quantized values are `u8`s, held as `i16` like the weights.
+    fn quantize(&self, xs: &[f32]) -> Vec<i16> {
+        xs.iter()
+            .map(|x| ((x / self.scale).round() + self.zero_point as f32).clamp(0.0, 255.0) as i16)
+            .collect()
+    }
+}
+
+/// Weights of a conv or linear layer as `[out, in * kernel * kernel]` int8 rows, each with its
+/// own scale so a channel of large weights doesn't cost the others their precision.
+#[derive(Debug)]
+struct QuantizedWeights {
+    /// The int8 values, held as `i16` so that products with the activations vectorize without
+    /// byte-widening instructions older CPUs lack.
+    values: Vec<i16>,
+    scales: Vec<f32>,
+    /// Sum of each row, to take the activation zero point out of the dot products.
+    row_sums: Vec<i32>,
+    bias: Vec<f32>,
+    columns: usize,
+}
+
+impl QuantizedWeights {
+    fn new(weight: &Tensor, bias: &Tensor) -> candle_core::Result<Self> {
+        let rows = weight.flatten_from(1)?.to_vec2::<f32>()?;
+        let columns = rows.first().map(|r| r.len()).unwrap_or_default();
+
+        let mut values = Vec::with_capacity(rows.len() * columns);
+        let mut scales = Vec::with_capacity(rows.len());
+        let mut row_sums = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let max = row.iter().fold(0f32, |a, w| a.max(w.abs()));
+            let scale = if max > 0.0 { max / 127.0 } else { 1.0 };
+            let quantized = row
+                .iter()
+                .map(|w| (w / scale).round().clamp(-127.0, 127.0) as i16)
+                .collect::<Vec<i16>>();
+
+            row_sums.push(quantized.iter().map(|&q| q as i32).sum());
+            values.extend(quantized);
+            scales.push(scale);
+        }
+
+        Ok(Self {
+            values,
+            scales,
+            row_sums,
+            bias: bias.to_vec1::<f32>()?,
+            columns,
+        })
+    }
+
+    fn out_features(&self) -> usize {
+        self.scales.len()
+    }
+
+    /// Multiplies every `columns` long row of quantized inputs `xs` by the weights, in integer
+    /// arithmetic, writing output `o` of row `r` dequantized to `out[index(r, o)]`. Uses AVX2
+    /// when `vectorized` and the CPU has it.
+    fn matmul(
+        &self,
+        xs: &[i16],
+        input: &ActivationQuantizer,
+        out: &mut [f32],
+        index: impl Fn(usize, usize) -> usize,
+        vectorized: bool,
+    ) {
+        #[cfg(target_arch = "x86_64")]
+        if vectorized && is_x86_feature_detected!("avx2") {
+            // Safe as the CPU was just checked to support AVX2
+            return unsafe { self.matmul_avx2(xs, input, out, index) };
+        }
+
+        self.matmul_generic(xs, input, out, index)
+    }
+
+    /// `matmul` compiled for AVX2, twice as wide as the SSE2 every x86-64 CPU has.
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    unsafe fn matmul_avx2(
+        &self,
+        xs: &[i16],
+        input: &ActivationQuantizer,
+        out: &mut [f32],
+        index: impl Fn(usize, usize) -> usize,
+    ) {
+        self.matmul_generic(xs, input, out, index)
+    }
+
+    #[inline(always)]
+    fn matmul_generic(
+        &self,
+        xs: &[i16],
+        input: &ActivationQuantizer,
+        out: &mut [f32],
+        index: impl Fn(usize, usize) -> usize,
+    ) {
+        for (r, x) in xs.chunks_exact(self.columns).enumerate() {
+            for (o, w) in self.values.chunks_exact(self.columns).enumerate() {
+                let acc = dot(w, x) - input.zero_point * self.row_sums[o];
+                out[index(r, o)] = acc as f32 * input.scale * self.scales[o] + self.bias[o];
+            }
+        }
+    }
+
+    /// Bytes the weights take in memory, with the int8 values held as `i16`.
+    fn bytes(&self) -> usize {
+        self.values.len() * std::mem::size_of::<i16>()
+            + (self.scales.len() + self.bias.len()) * std::mem::size_of::<f32>()
+    }
+}
+
+/// A layer of `QuantizedConvNet`. Everything but conv and linear layers runs in f32.
+#[derive(Debug)]
+enum QuantizedLayer {
+    Conv {
+        weights: QuantizedWeights,
+        input: ActivationQuantizer,
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+    },
+    MaxPool {
+        size: usize,
+        stride: usize,
+    },
+    /// Batch norm at inference is a per-channel `xs * scale + shift`.
+    BatchNorm {
+        scale: Tensor,
+        shift: Tensor,
+    },
+    Flatten,
+    Linear {
+        weights: QuantizedWeights,
+        input: ActivationQuantizer,
+    },
+    Activation(Activation),
+}
+
+/// Inference-only `ConvNet` with int8 conv and linear layers, running on the CPU.
+#[derive(Debug)]
+pub struct QuantizedConvNet {
+    spec: ArchitectureSpec,
+    labels: LabelSet,
+    layers: Vec<QuantizedLayer>,
+    vectorized: bool,
+}
+
+impl QuantizedConvNet {
+    /// Quantizes the weights of `model` in `varmap`, using `calibration` for the activations.
+    pub fn new(
+        model: &ConvNet,
+        varmap: &VarMap,
+        calibration: &Calibration,
+    ) -> candle_core::Result<Self> {
+        if calibration.weights_fingerprint != fingerprint(varmap)? {
+            return Err(candle_core::Error::Msg(
+                "The calibration was made for different weights, calibrate the model again"
+                    .to_string(),
+            ));
+        }
+
+        let vars = varmap
+            .data()
+            .lock()
+            .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?;
+        let var = |layer: &str, param: &str| {
+            let name = format!("{}.{}", layer, param);
+            vars.get(&name)
+                .ok_or_else(|| {
+                    candle_core::Error::Msg(format!("Variable '{}' has not been created", name))
+                })?
+                .as_tensor()
+                .to_device(&Device::Cpu)
+        };
+        let range = |layer: &str| {
+            calibration.ranges.get(layer).copied().ok_or_else(|| {
+                candle_core::Error::Msg(format!("Layer '{}' has not been calibrated", layer))
+            })
+        };
+
+        let mut layers = Vec::new();
+        for layer in model.spec().layers.iter() {
+            layers.push(match layer {
+                LayerSpec::Conv {
+                    name,
+                    kernel,
+                    stride,
+                    padding,
+                    ..
+                } => QuantizedLayer::Conv {
+                    weights: QuantizedWeights::new(&var(name, "weight")?, &var(name, "bias")?)?,
+                    input: ActivationQuantizer::new(range(name)?),
+                    kernel: *kernel,
+                    stride: *stride,
+                    padding: *padding,
+                },
+                LayerSpec::MaxPool { size, stride } => QuantizedLayer::MaxPool {
+                    size: *size,
+                    stride: stride.unwrap_or(*size),
+                },
+                LayerSpec::Dropout { .. } => continue,
+                LayerSpec::BatchNorm { name, eps, .. } => {
+                    let std = (var(name, "running_var")? + *eps)?.sqrt()?;
+                    let scale = (var(name, "weight")? / std)?;
+                    let shift = (var(name, "bias")? - (var(name, "running_mean")? * &scale)?)?;
+                    QuantizedLayer::BatchNorm { scale, shift }
+                }
+                LayerSpec::Flatten => QuantizedLayer::Flatten,
+                LayerSpec::Linear { name, .. } => QuantizedLayer::Linear {
+                    weights: QuantizedWeights::new(&var(name, "weight")?, &var(name, "bias")?)?,
+                    input: ActivationQuantizer::new(range(name)?),
+                },
+                LayerSpec::Activation { kind } => QuantizedLayer::Activation(*kind),
+            });
+        }
+
+        Ok(Self {
+            spec: model.spec().clone(),
+            labels: model.labels().clone(),
+            layers,
+            vectorized: true,
+        })
+    }
+
+    pub fn labels(&self) -> &LabelSet {
+        &self.labels
+    }
+
+    /// Whether conv and linear layers run the AVX2 code on CPUs that have it, the default.
+    /// Both paths compute bit-identical logits.
+    pub fn set_vectorized(&mut self, vectorized: bool) {
+        self.vectorized = vectorized;
+    }
+
+    /// The raw outputs for a batch of images, one row of logits per image in label order.
+    pub fn logits(&self, images: &Tensor) -> candle_core::Result<Tensor> {
+        let [channels, height, width] = self.spec.input_shape;
+        let mut xs =
+            images
+                .to_device(&Device::Cpu)?
+                .reshape(&[images.dim(0)?, channels, height, width])?;
+
+        for layer in self.layers.iter() {
+            xs = match layer {
+                QuantizedLayer::Conv {
+                    weights,
+                    input,
+                    kernel,
+                    stride,
+                    padding,
+                } => conv2d(
+                    &xs,
+                    weights,
+                    input,
+                    *kernel,
+                    *stride,
+                    *padding,
+                    self.vectorized,
+                )?,
+                QuantizedLayer::MaxPool { size, stride } => {
+                    xs.max_pool2d_with_stride(*size, *stride)?
+                }
+                QuantizedLayer::BatchNorm { scale, shift } => {
+                    let mut shape = vec![1; xs.rank()];
+                    shape[1] = scale.elem_count();
+                    xs.broadcast_mul(&scale.reshape(shape.clone())?)?
+                        .broadcast_add(&shift.reshape(shape)?)?
+                }
+                QuantizedLayer::Flatten => xs.flatten_from(1)?,
+                QuantizedLayer::Linear { weights, input } => {
+                    linear(&xs.flatten_from(1)?, weights, input, self.vectorized)?
+                }
+                QuantizedLayer::Activation(activation) => activation.apply(&xs)?,
+            };
+        }
+
+        Ok(xs)
+    }
+
+    pub fn predict(&self, image: &Tensor) -> candle_core::Result<String> {
+        let logits = self.logits(&image.unsqueeze(0)?)?;
+        let label = logits.argmax(D::Minus1)?.squeeze(0)?.to_scalar::<u32>()?;
+        let label = self.labels.name(label as usize).ok_or_else(|| {
+            candle_core::Error::Msg(format!("Model predicted unknown label index {}", label))
+        })?;
+        Ok(label.to_string())
+    }
+
+    /// Evaluates the model on the test split of `data`, returning the accuracy in percent.
+    pub fn test(&self, data: &Dataset, batch_size: usize) -> candle_core::Result<f32> {
+        let n_batches = data.test_images.dim(0)? / batch_size;
+        let mut correct_predictions = 0;
+
+        for i in 0..n_batches {
+            let images = data.test_images.narrow(0, i * batch_size, batch_size)?;
+            let labels = data.test_labels.narrow(0, i * batch_size, batch_size)?;
+
+            let predictions = self.logits(&images)?.argmax(D::Minus1)?;
+            correct_predictions += predictions
+                .eq(&labels.to_dtype(DType::U32)?.to_device(&Device::Cpu)?)?
+                .to_dtype(DType::F32)?
+                .sum_all()?
+                .to_scalar::<f32>()? as usize;
+        }
+
+        Ok(correct_predictions as f32 / (n_batches * batch_size) as f32 * 100.0)
+    }
+
+    /// Size of the weights in memory: the int8 values (held as `i16`) plus f32 scales, biases
+    /// and batch norm parameters.
+    pub fn weight_bytes(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| match layer {
+                QuantizedLayer::Conv { weights, .. } | QuantizedLayer::Linear { weights, .. } => {
+                    weights.bytes()
+                }
+                QuantizedLayer::BatchNorm { scale, shift } => {
+                    (scale.elem_count() + shift.elem_count()) * std::mem::size_of::<f32>()
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// How the quantized model holds up against the f32 one.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuantizationReport {
+    pub calibration_samples: usize,
+    /// Test accuracy in percent.
+    pub f32_accuracy: f32,
+    pub int8_accuracy: f32,
+    /// Mean time to predict a single image, in milliseconds.
+    pub f32_latency_ms: f64,
+    pub int8_latency_ms: f64,
+    pub f32_weight_bytes: usize,
+    /// See `QuantizedConvNet::weight_bytes`.
+    pub int8_weight_bytes: usize,
+}
+
+/// Calibrates on up to `calibration_samples` randomly picked training images of `data` (the
+/// user's drawings), then compares the quantized model against `model` on the test split.
+pub fn compare(
+    model: &ConvNet,
+    varmap: &VarMap,
+    data: &Dataset,
+    calibration_samples: usize,
+    batch_size: usize,
+    device: &Device,
+) -> candle_core::Result<(Calibration, QuantizationReport)> {
+    let available = data.train_images.dim(0)?;
+    let picked = index::sample(
+        &mut utils::seeded_rng(Some(0)),
+        available,
+        calibration_samples.min(available),
+    )
+    .into_iter()
+    .map(|i| i as u32)
+    .collect::<Vec<u32>>();
+    let picked = Tensor::from_vec(picked.clone(), picked.len(), data.train_images.device())?;
+    let calibration_images = data.train_images.index_select(&picked, 0)?;
+
+    let calibration = Calibration::measure(model, varmap, &calibration_images, batch_size, device)?;
+    let quantized = QuantizedConvNet::new(model, varmap, &calibration)?;
+
+    let f32_accuracy = model.test(device, data, batch_size)?;
+    let int8_accuracy = quantized.test(data, batch_size)?;
+
+    let latency_images = (0..data.test_images.dim(0)?.min(LATENCY_SAMPLES))
+        .map(|i| data.test_images.get(i))
+        .collect::<candle_core::Result<Vec<Tensor>>>()?;
+    let f32_latency_ms = mean_latency_ms(&latency_images, |image| model.predict(image, device))?;
+    let int8_latency_ms = mean_latency_ms(&latency_images, |image| quantized.predict(image))?;
+
+    let f32_weight_bytes = varmap
+        .all_vars()
+        .iter()
+        .map(|var| var.elem_count() * std::mem::size_of::<f32>())
+        .sum();
+
+    let report = QuantizationReport {
+        calibration_samples: calibration.samples,
+        f32_accuracy,
+        int8_accuracy,
+        f32_latency_ms,
+        int8_latency_ms,
+        f32_weight_bytes,
+        int8_weight_bytes: quantized.weight_bytes(),
+    };
+
+    Ok((calibration, report))
+}
+
+/// Times `predict` on each image after a warm-up run.
+fn mean_latency_ms(
+    images: &[Tensor],
+    predict: impl Fn(&Tensor) -> candle_core::Result<String>,
+) -> candle_core::Result<f64> {
+    let Some(first) = images.first() else {
+        return Ok(0.0);
+    };
+    predict(first)?;
+
+    let start = Instant::now();
+    for image in images {
+        predict(image)?;
+    }
+    Ok(start.elapsed().as_secs_f64() * 1000.0 / images.len() as f64)
+}
+
+/// Convolves `xs` (`[batch, channels, height, width]`) by gathering each output position's
+/// receptive field into a row of quantized inputs, multiplied against the int8 kernels.
+fn conv2d(
+    xs: &Tensor,
+    weights: &QuantizedWeights,
+    input: &ActivationQuantizer,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+    vectorized: bool,
+) -> candle_core::Result<Tensor> {
+    let (batch, channels, height, width) = xs.dims4()?;
+    let out_height = (height + 2 * padding - kernel) / stride + 1;
+    let out_width = (width + 2 * padding - kernel) / stride + 1;
+    let positions = out_height * out_width;
+    let out_channels = weights.out_features();
+
+    let xs = input.quantize(&xs.flatten_all()?.to_vec1::<f32>()?);
+    let mut patches = vec![0i16; positions * weights.columns];
+    let mut out = vec![0f32; batch * out_channels * positions];
+
+    for (image, out) in xs
+        .chunks_exact(channels * height * width)
+        .zip(out.chunks_exact_mut(out_channels * positions))
+    {
+        for (p, patch) in patches.chunks_exact_mut(weights.columns).enumerate() {
+            let (oy, ox) = (p / out_width, p % out_width);
+            let mut values = patch.iter_mut();
+            for channel in image.chunks_exact(height * width) {
+                for ky in 0..kernel {
+                    let y = (oy * stride + ky)
+                        .checked_sub(padding)
+                        .filter(|&y| y < height);
+                    for kx in 0..kernel {
+                        let x = (ox * stride + kx)
+                            .checked_sub(padding)
+                            .filter(|&x| x < width);
+                        if let Some(value) = values.next() {
+                            *value = match (y, x) {
+                                (Some(y), Some(x)) => channel[y * width + x],
+                                // Padding is 0, exactly the zero point
+                                _ => input.zero_point as i16,
+                            };
+                        }
+                    }
+                }
+            }
+        }
+        weights.matmul(&patches, input, out, |p, o| o * positions + p, vectorized);
+    }
+
+    Tensor::from_vec(
+        out,
+        (batch, out_channels, out_height, out_width),
+        &Device::Cpu,
+    )
+}
+
+/// Integer dot product, summed in independent lanes so it vectorizes.
+#[inline(always)]
+fn dot(w: &[i16], x: &[i16]) -> i32 {
+    const LANES: usize = 16;
+    let mut lanes = [0i32; LANES];
+    let (w_chunks, x_chunks) = (w.chunks_exact(LANES), x.chunks_exact(LANES));
+    let tail = w_chunks
+        .remainder()
+        .iter()
+        .zip(x_chunks.remainder())
+        .map(|(&w, &x)| w as i32 * x as i32)
+        .sum::<i32>();
+
+    for (w, x) in w_chunks.zip(x_chunks) {
+        for i in 0..LANES {
+            lanes[i] += w[i] as i32 * x[i] as i32;
+        }
+    }
+
+    lanes.iter().sum::<i32>() + tail
+}
+
+fn linear(
+    xs: &Tensor,
+    weights: &QuantizedWeights,
+    input: &ActivationQuantizer,
+    vectorized: bool,
+) -> candle_core::Result<Tensor> {
+    let batch = xs.dim(0)?;
+    let out_features = weights.out_features();
+
+    let xs = input.quantize(&xs.flatten_all()?.to_vec1::<f32>()?);
+    let mut out = vec![0f32; batch * out_features];
+    weights.matmul(
+        &xs,
+        input,
+        &mut out,
+        |r, o| r * out_features + o,
+        vectorized,
+    );
+
+    Tensor::from_vec(out, (batch, out_features), &Device::Cpu)
+}
+
+/// FNV-1a hash of every variable's name and values, in name order.
+fn fingerprint(varmap: &VarMap) -> candle_core::Result<u64> {
+    let vars = varmap
+        .data()
+        .lock()
+        .map_err(|e| candle_core::Error::Msg(format!("Failed to lock varmap: {}", e)))?;
+    let mut names = vars.keys().collect::<Vec<&String>>();
+    names.sort();
+
+    let mut hasher = Fnv1a::new();
+    for name in names {
+        hasher.write(name.as_bytes());
+        for value in vars[name].flatten_all()?.to_vec1::<f32>()? {
+            hasher.write(&value.to_le_bytes());
+        }
+    }
+
+    Ok(hasher.finish())
+}
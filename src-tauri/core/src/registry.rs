@@ -0,0 +1,402 @@
+use crate::checkpoint;
+use crate::distill::DistillationSettings;
+use crate::history::{self, TrainingHistory};
+use crate::model::TrainingSummary;
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "registry.json";
+
+/// Id of the entry holding the bundled pretrained model.
+pub const PRETRAINED_ID: &str = "pretrained";
+
+/// Settings a model was trained with.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    pub frozen_layers: Vec<String>,
+    pub patience: Option<usize>,
+    pub mnist_ratio: Option<f32>,
+    /// Whether unfinished versions of the drawings saved with strokes were trained on too,
+    /// which counts them in the entry's dataset size.
+    #[serde(default)]
+    pub partial_drawings: bool,
+    /// Set for students distilled from the pretrained model.
+    pub distillation: Option<DistillationSettings>,
+}
+
+/// A model stored in the registry. The bundled pretrained model has no hyperparameters,
+/// dataset size or metrics.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelEntry {
+    pub id: String,
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub hyperparameters: Option<Hyperparameters>,
+    pub dataset_size: Option<usize>,
+    pub metrics: Option<TrainingSummary>,
+    /// Id of the model this one was fine-tuned from.
+    pub parent: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TensorDiff {
+    pub name: String,
+    pub shape_a: Option<Vec<usize>>,
+    pub shape_b: Option<Vec<usize>>,
+    /// Only set when the tensor exists in both models with the same shape.
+    pub mean_abs_diff: Option<f32>,
+    pub max_abs_diff: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelDiff {
+    pub a: ModelEntry,
+    pub b: ModelEntry,
+    pub tensors: Vec<TensorDiff>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryIndex {
+    active: Option<String>,
+    models: Vec<ModelEntry>,
+    /// Number of the next default "Model <n>" name, so names aren't reused after a delete.
+    #[serde(default)]
+    next_number: usize,
+}
+
+/// History of trained models, stored as `<id>.safetensors` files plus a `registry.json` index.
+///
+/// The model used for training and prediction stays at its working path (the temp-assets
+/// `model.safetensors`); activating a registry entry copies it there.
+pub struct ModelRegistry {
+    root: PathBuf,
+    index: RegistryIndex,
+}
+
+impl ModelRegistry {
+    /// Opens the registry in `root`, creating an empty one if needed.
+    pub fn open(root: &Path) -> candle_core::Result<Self> {
+        fs::create_dir_all(root)?;
+
+        let index_path = root.join(INDEX_FILE);
+        let index = if index_path.exists() {
+            let contents = fs::read_to_string(&index_path)?;
+            serde_json::from_str(&contents).map_err(|e| {
+                candle_core::Error::Msg(format!(
+                    "Failed to parse model registry {:?}: {}",
+                    index_path, e
+                ))
+            })?
+        } else {
+            RegistryIndex::default()
+        };
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            index,
+        })
+    }
+
+    pub fn models(&self) -> &[ModelEntry] {
+        &self.index.models
+    }
+
+    pub fn active(&self) -> Option<&ModelEntry> {
+        self.index.active.as_ref().and_then(|id| self.get(id).ok())
+    }
+
+    pub fn get(&self, id: &str) -> candle_core::Result<&ModelEntry> {
+        self.index
+            .models
+            .iter()
+            .find(|m| m.id == id)
+            .ok_or_else(|| candle_core::Error::Msg(format!("No model with id '{}'", id)))
+    }
+
+    pub fn model_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}.safetensors", id))
+    }
+
+    /// Stores the bundled model under `PRETRAINED_ID` if it isn't registered yet and makes it
+    /// the active model.
+    pub fn register_pretrained(&mut self, source: &Path) -> candle_core::Result<()> {
+        if self.get(PRETRAINED_ID).is_err() {
+            fs::copy(source, self.model_path(PRETRAINED_ID))?;
+            self.index.models.push(ModelEntry {
+                id: PRETRAINED_ID.to_string(),
+                name: "Pretrained".to_string(),
+                created_at: now(),
+                hyperparameters: None,
+                dataset_size: None,
+                metrics: None,
+                parent: None,
+            });
+        }
+
+        self.index.active = Some(PRETRAINED_ID.to_string());
+        self.save_index()
+    }
+
+    /// Copies the freshly trained model at `source`, and its training history if there is one,
+    /// into the registry and makes it the active model. Its parent is whichever model was
+    /// active before.
+    pub fn register_trained(
+        &mut self,
+        source: &Path,
+        hyperparameters: Hyperparameters,
+        dataset_size: usize,
+        metrics: TrainingSummary,
+    ) -> candle_core::Result<ModelEntry> {
+        let parent = self.index.active.clone();
+        let entry = self.store(source, parent, hyperparameters, dataset_size, metrics)?;
+
+        self.index.active = Some(entry.id.clone());
+        self.save_index()?;
+
+        Ok(entry)
+    }
+
+    /// Copies the model at `source` into the registry like `register_trained`, but as a model
+    /// derived from `parent` rather than from the active one, which stays active.
+    pub fn register_derived(
+        &mut self,
+        source: &Path,
+        parent: &str,
+        hyperparameters: Hyperparameters,
+        dataset_size: usize,
+        metrics: TrainingSummary,
+    ) -> candle_core::Result<ModelEntry> {
+        self.get(parent)?;
+
+        let entry = self.store(
+            source,
+            Some(parent.to_string()),
+            hyperparameters,
+            dataset_size,
+            metrics,
+        )?;
+        self.save_index()?;
+
+        Ok(entry)
+    }
+
+    /// Makes `id` the active model by installing its weights at `working_path`.
+    pub fn activate(&mut self, id: &str, working_path: &Path) -> candle_core::Result<()> {
+        self.get(id)?;
+
+        checkpoint::install(&self.model_path(id), working_path)?;
+
+        self.index.active = Some(id.to_string());
+        self.save_index()
+    }
+
+    /// Makes the model the active one was trained from active again and returns it. Without
+    /// an active entry, e.g. for a model trained before the registry existed, the working model
+    /// is restored from the backup `checkpoint::save_atomic` keeps instead.
+    pub fn rollback(&mut self, working_path: &Path) -> candle_core::Result<Option<ModelEntry>> {
+        let Some(active) = self.active() else {
+            checkpoint::rollback(working_path)?;
+            return Ok(None);
+        };
+
+        let parent = active.parent.clone().ok_or_else(|| {
+            candle_core::Error::Msg(format!(
+                "Model '{}' has no previous model to roll back to",
+                active.id
+            ))
+        })?;
+        self.activate(&parent, working_path)?;
+
+        self.get(&parent).cloned().map(Some)
+    }
+
+    pub fn rename(&mut self, id: &str, name: &str) -> candle_core::Result<()> {
+        let entry = self
+            .index
+            .models
+            .iter_mut()
+            .find(|m| m.id == id)
+            .ok_or_else(|| candle_core::Error::Msg(format!("No model with id '{}'", id)))?;
+
+        entry.name = name.to_string();
+        self.save_index()
+    }
+
+    /// Removes `id` from the registry along with its weights and every file stored next to
+    /// them, like its training history and calibration. The active model, the pretrained one
+    /// and models others were trained from can't be deleted.
+    pub fn delete(&mut self, id: &str) -> candle_core::Result<()> {
+        self.get(id)?;
+
+        if id == PRETRAINED_ID {
+            return Err(candle_core::Error::Msg(
+                "The pretrained model can't be deleted".to_string(),
+            ));
+        }
+
+        if self.index.active.as_deref() == Some(id) {
+            return Err(candle_core::Error::Msg(format!(
+                "Model '{}' is active and can't be deleted",
+                id
+            )));
+        }
+
+        let children = self
+            .index
+            .models
+            .iter()
+            .filter(|m| m.parent.as_deref() == Some(id))
+            .map(|m| m.id.as_str())
+            .collect::<Vec<&str>>();
+        if !children.is_empty() {
+            return Err(candle_core::Error::Msg(format!(
+                "Model '{}' can't be deleted, {:?} were trained from it",
+                id, children
+            )));
+        }
+
+        self.index.models.retain(|m| m.id != id);
+        self.save_index()?;
+
+        // `<id>.safetensors` and its siblings, e.g. `<id>.safetensors.int8.json`
+        let prefix = format!("{}.safetensors", id);
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let is_model_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix));
+            if is_model_file && path.is_file() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The metrics recorded while training `id`. Models registered without a history, like the
+    /// pretrained one, have none.
+    pub fn history(&self, id: &str) -> candle_core::Result<Option<TrainingHistory>> {
+        self.get(id)?;
+        TrainingHistory::load(&self.model_path(id))
+    }
+
+    /// Compares the metadata and weights of two registered models.
+    pub fn diff(&self, a: &str, b: &str) -> candle_core::Result<ModelDiff> {
+        let entry_a = self.get(a)?.clone();
+        let entry_b = self.get(b)?.clone();
+
+        let weights_a = candle_core::safetensors::load(self.model_path(a), &Device::Cpu)?;
+        let weights_b = candle_core::safetensors::load(self.model_path(b), &Device::Cpu)?;
+
+        let names = weights_a
+            .keys()
+            .chain(weights_b.keys())
+            .cloned()
+            .collect::<BTreeSet<String>>();
+
+        let mut tensors = Vec::with_capacity(names.len());
+        for name in names {
+            let tensor_a = weights_a.get(&name);
+            let tensor_b = weights_b.get(&name);
+
+            let (mean_abs_diff, max_abs_diff) = match (tensor_a, tensor_b) {
+                (Some(ta), Some(tb)) if ta.shape() == tb.shape() => {
+                    let abs_diff = (ta - tb)?.abs()?;
+                    (
+                        Some(abs_diff.mean_all()?.to_scalar::<f32>()?),
+                        Some(abs_diff.flatten_all()?.max(0)?.to_scalar::<f32>()?),
+                    )
+                }
+                _ => (None, None),
+            };
+
+            tensors.push(TensorDiff {
+                name,
+                shape_a: tensor_a.map(|t| t.dims().to_vec()),
+                shape_b: tensor_b.map(|t| t.dims().to_vec()),
+                mean_abs_diff,
+                max_abs_diff,
+            });
+        }
+
+        Ok(ModelDiff {
+            a: entry_a,
+            b: entry_b,
+            tensors,
+        })
+    }
+
+    /// Copies the model at `source` and its training history under a new id and adds an entry
+    /// for it, leaving the index to be saved by the caller.
+    fn store(
+        &mut self,
+        source: &Path,
+        parent: Option<String>,
+        hyperparameters: Hyperparameters,
+        dataset_size: usize,
+        metrics: TrainingSummary,
+    ) -> candle_core::Result<ModelEntry> {
+        let created_at = now();
+
+        let mut id = format!("model-{}", created_at);
+        let mut suffix = 1;
+        while self.get(&id).is_ok() {
+            suffix += 1;
+            id = format!("model-{}-{}", created_at, suffix);
+        }
+
+        let model_path = self.model_path(&id);
+        fs::copy(source, &model_path)?;
+        for history_path in [history::jsonl_path, history::csv_path] {
+            if history_path(source).exists() {
+                fs::copy(history_path(source), history_path(&model_path))?;
+            }
+        }
+
+        // Registries from before `next_number` existed count from their size
+        let number = self.index.next_number.max(self.index.models.len() + 1);
+        self.index.next_number = number + 1;
+
+        let entry = ModelEntry {
+            id,
+            name: format!("Model {}", number),
+            created_at,
+            hyperparameters: Some(hyperparameters),
+            dataset_size: Some(dataset_size),
+            metrics: Some(metrics),
+            parent,
+        };
+        self.index.models.push(entry.clone());
+
+        Ok(entry)
+    }
+
+    /// Writes `registry.json` through a temporary file so a crash can't truncate it.
+    fn save_index(&self) -> candle_core::Result<()> {
+        let index_path = self.root.join(INDEX_FILE);
+        let tmp_path = checkpoint::sibling_path(&index_path, ".tmp");
+
+        let contents = serde_json::to_string_pretty(&self.index).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to serialize model registry: {}", e))
+        })?;
+
+        fs::write(&tmp_path, contents)?;
+        checkpoint::replace(&tmp_path, &index_path)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,437 @@
+use crate::labels::LabelSet;
+use crate::{checkpoint, utils};
+use candle_core::Tensor;
+use image::{DynamicImage, GrayImage, ImageFormat};
+use imageproc::filter::gaussian_blur_f32;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the file in the drawings store holding the `RasterConfig` drawings are rendered
+/// with.
+const RASTER_CONFIG_FILE: &str = "raster.json";
+
+/// Pressure reported by pointers without pressure sensing, like mice, which gets the nominal
+/// line width.
+const DEFAULT_PRESSURE: f32 = 0.5;
+
+/// How far through each drawing `partial_drawings` renders it, as shares of its duration.
+const PARTIAL_DRAWING_SHARES: [f64; 2] = [0.5, 0.75];
+
+/// A drawing as the pen moved, in the coordinates of the canvas it was drawn on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrokeDrawing {
+    pub canvas_width: f32,
+    pub canvas_height: f32,
+    pub strokes: Vec<Stroke>,
+}
+
+/// One polyline, from pen down to pen up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stroke {
+    pub points: Vec<StrokePoint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StrokePoint {
+    pub x: f32,
+    pub y: f32,
+    /// Milliseconds since the drawing started.
+    pub t: f64,
+    /// In [0, 1], as reported by pointer events.
+    #[serde(default = "default_pressure")]
+    pub pressure: f32,
+}
+
+/// How strokes are turned into the white on black images the model takes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RasterConfig {
+    /// Width and height of the rendered image in pixels.
+    pub size: u32,
+    /// Line width in canvas units at the default pressure. Harder pressure draws up to twice
+    /// as wide, lighter pressure down to a quarter.
+    pub line_width: f32,
+    /// Shades the edge pixels of lines by how much of them the line covers.
+    pub antialias: bool,
+    /// Standard deviation in pixels of a Gaussian blur applied after drawing, 0 for none.
+    #[serde(default)]
+    pub blur: f32,
+}
+
+impl Default for RasterConfig {
+    /// Matches the app's canvas, a 10 wide pen on 280x280 pixels, at the model's input size.
+    fn default() -> Self {
+        Self {
+            size: 28,
+            line_width: 10.0,
+            antialias: true,
+            blur: 0.0,
+        }
+    }
+}
+
+impl RasterConfig {
+    /// The config the drawings in `drawings_dir` were rendered with, the default one if
+    /// `rerender_drawings` never changed it.
+    pub fn load(drawings_dir: &Path) -> candle_core::Result<Self> {
+        let path = drawings_dir.join(RASTER_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let json = fs::read_to_string(&path)?;
+        serde_json::from_str(&json).map_err(|e| {
+            candle_core::Error::Msg(format!("Invalid raster config {:?}: {}", path, e))
+        })
+    }
+
+    pub fn save(&self, drawings_dir: &Path) -> candle_core::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to encode raster config: {}", e))
+        })?;
+        fs::write(drawings_dir.join(RASTER_CONFIG_FILE), json)?;
+        Ok(())
+    }
+}
+
+/// Outcome of `rerender_drawings`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RerenderReport {
+    pub config: RasterConfig,
+    /// Drawings rendered again from their strokes.
+    pub rendered: Vec<String>,
+    /// Drawings saved as images only, which were left as they are.
+    pub without_strokes: Vec<String>,
+    /// Number of drawings in the rebuilt training set.
+    pub dataset_size: usize,
+}
+
+fn default_pressure() -> f32 {
+    DEFAULT_PRESSURE
+}
+
+impl StrokeDrawing {
+    pub fn from_json_file(path: &Path) -> candle_core::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        let drawing: Self = serde_json::from_str(&json).map_err(|e| {
+            candle_core::Error::Msg(format!("Invalid stroke drawing {:?}: {}", path, e))
+        })?;
+        drawing.validate()?;
+        Ok(drawing)
+    }
+
+    pub fn save(&self, path: &Path) -> candle_core::Result<()> {
+        let json = serde_json::to_string(self).map_err(|e| {
+            candle_core::Error::Msg(format!("Failed to encode stroke drawing: {}", e))
+        })?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Checks the canvas has a size and every point lies on it with a finite time and a
+    /// pressure in [0, 1].
+    pub fn validate(&self) -> candle_core::Result<()> {
+        let valid_size = |size: f32| size.is_finite() && size > 0.0;
+        if !valid_size(self.canvas_width) || !valid_size(self.canvas_height) {
+            return Err(candle_core::Error::Msg(format!(
+                "Stroke canvas is {}x{}, expected a positive size",
+                self.canvas_width, self.canvas_height
+            )));
+        }
+
+        for (i, stroke) in self.strokes.iter().enumerate() {
+            for point in stroke.points.iter() {
+                let on_canvas = (0.0..=self.canvas_width).contains(&point.x)
+                    && (0.0..=self.canvas_height).contains(&point.y);
+                if !on_canvas || !point.t.is_finite() || !(0.0..=1.0).contains(&point.pressure) {
+                    return Err(candle_core::Error::Msg(format!(
+                        "Stroke {} has invalid point {:?} on a {}x{} canvas",
+                        i, point, self.canvas_width, self.canvas_height
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Time of the last point in milliseconds.
+    pub fn duration(&self) -> f64 {
+        self.strokes
+            .iter()
+            .flat_map(|stroke| stroke.points.iter())
+            .fold(0.0, |duration, point| duration.max(point.t))
+    }
+
+    /// The drawing as it was `t` milliseconds after it started, for augmenting the data with
+    /// unfinished drawings.
+    pub fn up_to(&self, t: f64) -> StrokeDrawing {
+        StrokeDrawing {
+            canvas_width: self.canvas_width,
+            canvas_height: self.canvas_height,
+            strokes: self
+                .strokes
+                .iter()
+                .map(|stroke| Stroke {
+                    points: stroke
+                        .points
+                        .iter()
+                        .copied()
+                        .filter(|point| point.t <= t)
+                        .collect(),
+                })
+                .filter(|stroke| !stroke.points.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Checks the drawing and renders it for the drawings store in `drawings_dir` with
+    /// `config`, or with the store's own config (see `rerender_drawings`) when there is none.
+    pub fn rasterize_for_store(
+        &self,
+        drawings_dir: &Path,
+        config: Option<RasterConfig>,
+    ) -> candle_core::Result<GrayImage> {
+        let config = match config {
+            Some(config) => config,
+            None => RasterConfig::load(drawings_dir)?,
+        };
+
+        self.validate()?;
+        self.rasterize(&config)
+    }
+
+    /// Draws the strokes as round capped white lines on black, scaling the canvas to
+    /// `config.size` pixels. The line width varies along each segment with the pressure at
+    /// its ends.
+    pub fn rasterize(&self, config: &RasterConfig) -> candle_core::Result<GrayImage> {
+        if config.size == 0 || !config.line_width.is_finite() || config.line_width <= 0.0 {
+            return Err(candle_core::Error::Msg(format!(
+                "Can't rasterize {}x{} pixels with line width {}",
+                config.size, config.size, config.line_width
+            )));
+        }
+        if !config.blur.is_finite() || config.blur < 0.0 {
+            return Err(candle_core::Error::Msg(format!(
+                "Can't blur by {} pixels, expected 0 or more",
+                config.blur
+            )));
+        }
+
+        let mut image = GrayImage::new(config.size, config.size);
+        let scale_x = config.size as f32 / self.canvas_width;
+        let scale_y = config.size as f32 / self.canvas_height;
+        // Lines are as wide as on the canvas, relative to its size
+        let width_scale = config.line_width * (scale_x + scale_y) / 2.0;
+
+        for stroke in self.strokes.iter() {
+            let points = stroke
+                .points
+                .iter()
+                .map(|point| {
+                    let pressure = (point.pressure / DEFAULT_PRESSURE).clamp(0.25, 2.0);
+                    (
+                        point.x * scale_x,
+                        point.y * scale_y,
+                        width_scale * pressure / 2.0,
+                    )
+                })
+                .collect::<Vec<(f32, f32, f32)>>();
+
+            match points.as_slice() {
+                [] => {}
+                [point] => draw_segment(&mut image, *point, *point, config.antialias),
+                points => {
+                    for segment in points.windows(2) {
+                        draw_segment(&mut image, segment[0], segment[1], config.antialias);
+                    }
+                }
+            }
+        }
+
+        if config.blur > 0.0 {
+            image = gaussian_blur_f32(&image, config.blur);
+        }
+
+        Ok(image)
+    }
+}
+
+/// Renders the drawings in `drawings_dir` that were saved with strokes as they were partway
+/// through (see `PARTIAL_DRAWING_SHARES`), with the store's `RasterConfig`, so the model also
+/// learns unfinished drawings. Returns `(images, labels)` tensors like `utils::load_drawings`,
+/// or `None` if no drawing has strokes.
+pub fn partial_drawings(
+    drawings_dir: &Path,
+    labels: &LabelSet,
+) -> candle_core::Result<Option<(Tensor, Tensor)>> {
+    let config = RasterConfig::load(drawings_dir)?;
+
+    let mut entries = fs::read_dir(drawings_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    let mut images = Vec::new();
+    let mut image_labels = Vec::new();
+    for path in entries {
+        let strokes_path = strokes_path(&path);
+        if !utils::is_image_file(&path) || !strokes_path.exists() {
+            continue;
+        }
+        // Drawings with unknown labels are rejected by `utils::load_drawings`
+        let Some(label) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| labels.index_of(stem))
+        else {
+            continue;
+        };
+
+        let drawing = StrokeDrawing::from_json_file(&strokes_path)?;
+        for share in PARTIAL_DRAWING_SHARES {
+            let partial = drawing.up_to(drawing.duration() * share);
+            if partial.strokes.is_empty() || partial == drawing {
+                continue;
+            }
+
+            let image = DynamicImage::ImageLuma8(partial.rasterize(&config)?);
+            images.push(utils::image_to_formatted_tensor(image)?);
+            image_labels.push(label as f32);
+        }
+    }
+
+    if images.is_empty() {
+        return Ok(None);
+    }
+    let count = image_labels.len();
+    let labels = Tensor::from_vec(image_labels, count, images[0].device())?;
+    Ok(Some((Tensor::stack(&images, 0)?, labels)))
+}
+
+/// Renders every drawing in `drawings_dir` that has strokes again with `config`, which
+/// becomes the config new drawings are rendered with, then reloads the drawings as a training
+/// set for `labels` to check it still builds.
+///
+/// All drawings are rendered and written next to their images before any image is replaced,
+/// so an invalid strokes file or a failed write leaves the store untouched. The config is
+/// only saved once every image is renamed into place; the renames aren't atomic together,
+/// though.
+pub fn rerender_drawings(
+    drawings_dir: &Path,
+    config: &RasterConfig,
+    labels: &LabelSet,
+) -> candle_core::Result<RerenderReport> {
+    let mut entries = fs::read_dir(drawings_dir)
+        .map_err(|e| {
+            candle_core::Error::Msg(format!(
+                "Failed to read drawings directory {:?}: {}",
+                drawings_dir, e
+            ))
+        })?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    let mut rendered = Vec::new();
+    let mut without_strokes = Vec::new();
+    for path in entries {
+        let is_png = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+        if !is_png {
+            continue;
+        }
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let strokes_path = strokes_path(&path);
+        if !strokes_path.exists() {
+            without_strokes.push(name);
+            continue;
+        }
+
+        let image = StrokeDrawing::from_json_file(&strokes_path)?.rasterize(config)?;
+        rendered.push((path, name, image));
+    }
+
+    let tmp_paths = rendered
+        .iter()
+        .map(|(path, _, _)| checkpoint::sibling_path(path, ".tmp"))
+        .collect::<Vec<PathBuf>>();
+    for (tmp_path, (_, _, image)) in tmp_paths.iter().zip(rendered.iter()) {
+        if let Err(e) = image.save_with_format(tmp_path, ImageFormat::Png) {
+            for tmp_path in tmp_paths.iter() {
+                let _ = fs::remove_file(tmp_path);
+            }
+            return Err(candle_core::Error::Msg(format!(
+                "Failed to save {:?}: {}",
+                tmp_path, e
+            )));
+        }
+    }
+    for (tmp_path, (path, _, _)) in tmp_paths.iter().zip(rendered.iter()) {
+        fs::rename(tmp_path, path)?;
+    }
+    config.save(drawings_dir)?;
+
+    let (images, _) = utils::load_drawings(&drawings_dir.to_path_buf(), labels)?;
+
+    Ok(RerenderReport {
+        config: config.clone(),
+        rendered: rendered.into_iter().map(|(_, name, _)| name).collect(),
+        without_strokes,
+        dataset_size: images.dim(0)?,
+    })
+}
+
+/// Where the strokes of the drawing saved as `image_path` are kept, e.g. `3.strokes.json`
+/// next to `3.png`.
+pub fn strokes_path(image_path: &Path) -> PathBuf {
+    image_path.with_extension("strokes.json")
+}
+
+/// Draws a capsule from `a` to `b`, each given as `(x, y, radius)` in pixels, keeping the
+/// brighter value where lines overlap.
+fn draw_segment(image: &mut GrayImage, a: (f32, f32, f32), b: (f32, f32, f32), antialias: bool) {
+    let (ax, ay, ar) = a;
+    let (bx, by, br) = b;
+    let reach = ar.max(br) + 1.0;
+
+    let x_range = ((ax.min(bx) - reach).floor().max(0.0) as u32)
+        ..((ax.max(bx) + reach).ceil().min(image.width() as f32) as u32);
+    let y_range = ((ay.min(by) - reach).floor().max(0.0) as u32)
+        ..((ay.max(by) + reach).ceil().min(image.height() as f32) as u32);
+
+    let (dx, dy) = (bx - ax, by - ay);
+    let length_sq = dx * dx + dy * dy;
+
+    for y in y_range {
+        for x in x_range.clone() {
+            // Distance from the pixel center to the closest point of the segment
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            let along = if length_sq > 0.0 {
+                (((px - ax) * dx + (py - ay) * dy) / length_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (cx, cy) = (ax + along * dx, ay + along * dy);
+            let distance = ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+            let radius = ar + along * (br - ar);
+
+            let coverage = if antialias {
+                (radius - distance + 0.5).clamp(0.0, 1.0)
+            } else if distance <= radius {
+                1.0
+            } else {
+                0.0
+            };
+
+            let value = (coverage * 255.0).round() as u8;
+            let pixel = image.get_pixel_mut(x, y);
+            pixel[0] = pixel[0].max(value);
+        }
+    }
+}
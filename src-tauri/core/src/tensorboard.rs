@@ -0,0 +1,179 @@
+use crate::proto::Proto;
+use image::{GrayImage, ImageFormat};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of equal-width buckets in weight histograms.
+const HISTOGRAM_BUCKETS: usize = 30;
+
+/// Writes a TensorBoard event file (`events.out.tfevents.*`) into a run directory, so training
+/// runs can be inspected with `tensorboard --logdir <dir>`.
+///
+/// The file is a sequence of TFRecords, each holding an `Event` protocol buffer. Both are
+/// encoded by hand to avoid pulling in protobuf tooling for the handful of messages we need.
+pub struct EventWriter {
+    file: BufWriter<File>,
+}
+
+impl EventWriter {
+    /// Creates a new event file in `run_dir`, creating the directory if needed.
+    pub fn create(run_dir: &Path) -> candle_core::Result<Self> {
+        fs::create_dir_all(run_dir)?;
+
+        let file_name = format!("events.out.tfevents.{}.train-an-ai", wall_time() as u64);
+        let mut writer = Self {
+            file: BufWriter::new(File::create(run_dir.join(file_name))?),
+        };
+
+        // Every event file starts with its version
+        let mut event = event_header(0);
+        event.string(3, "brain.Event:2");
+        writer.write_record(&event.0)?;
+
+        Ok(writer)
+    }
+
+    pub fn add_scalar(&mut self, tag: &str, value: f32, step: i64) -> candle_core::Result<()> {
+        let mut summary_value = Proto::default();
+        summary_value.string(1, tag);
+        summary_value.float(2, value);
+        self.write_summary(&summary_value, step)
+    }
+
+    /// Logs the distribution of `values`.
+    pub fn add_histogram(
+        &mut self,
+        tag: &str,
+        values: &[f32],
+        step: i64,
+    ) -> candle_core::Result<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let min = values.iter().fold(f32::INFINITY, |a, &b| a.min(b)) as f64;
+        let max = values.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b)) as f64;
+        let width = (max - min) / HISTOGRAM_BUCKETS as f64;
+
+        let mut counts = vec![0f64; HISTOGRAM_BUCKETS];
+        for &v in values {
+            let bucket = if width > 0.0 {
+                (((v as f64 - min) / width) as usize).min(HISTOGRAM_BUCKETS - 1)
+            } else {
+                0
+            };
+            counts[bucket] += 1.0;
+        }
+        // Upper edge of each bucket
+        let limits = (1..=HISTOGRAM_BUCKETS)
+            .map(|i| {
+                if i == HISTOGRAM_BUCKETS {
+                    max
+                } else {
+                    min + width * i as f64
+                }
+            })
+            .collect::<Vec<f64>>();
+
+        let mut histogram = Proto::default();
+        histogram.double(1, min);
+        histogram.double(2, max);
+        histogram.double(3, values.len() as f64);
+        histogram.double(4, values.iter().map(|&v| v as f64).sum());
+        histogram.double(5, values.iter().map(|&v| (v as f64) * (v as f64)).sum());
+        histogram.packed_doubles(6, &limits);
+        histogram.packed_doubles(7, &counts);
+
+        let mut summary_value = Proto::default();
+        summary_value.string(1, tag);
+        summary_value.message(5, &histogram);
+        self.write_summary(&summary_value, step)
+    }
+
+    /// Logs a grayscale image.
+    pub fn add_image(
+        &mut self,
+        tag: &str,
+        image: &GrayImage,
+        step: i64,
+    ) -> candle_core::Result<()> {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, ImageFormat::Png)
+            .map_err(|e| candle_core::Error::Msg(format!("Failed to encode image: {}", e)))?;
+
+        let mut encoded = Proto::default();
+        encoded.int64(1, image.height() as i64);
+        encoded.int64(2, image.width() as i64);
+        // Colorspace 1 is grayscale
+        encoded.int64(3, 1);
+        encoded.bytes(4, &png.into_inner());
+
+        let mut summary_value = Proto::default();
+        summary_value.string(1, tag);
+        summary_value.message(4, &encoded);
+        self.write_summary(&summary_value, step)
+    }
+
+    pub fn flush(&mut self) -> candle_core::Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_summary(&mut self, summary_value: &Proto, step: i64) -> candle_core::Result<()> {
+        let mut summary = Proto::default();
+        summary.message(1, summary_value);
+
+        let mut event = event_header(step);
+        event.message(5, &summary);
+        self.write_record(&event.0)
+    }
+
+    /// Frames `data` as a TFRecord: length, checksum of the length, data, checksum of the data.
+    fn write_record(&mut self, data: &[u8]) -> candle_core::Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.file.write_all(&len)?;
+        self.file.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.file.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+/// The `wall_time` and `step` fields every `Event` starts with.
+fn event_header(step: i64) -> Proto {
+    let mut event = Proto::default();
+    event.double(1, wall_time());
+    event.int64(2, step);
+    event
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// CRC-32C (Castagnoli) of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// `crc32c` of `data`, rotated and offset the way TFRecords expect.
+pub fn masked_crc32c(data: &[u8]) -> u32 {
+    crc32c(data).rotate_right(15).wrapping_add(0xA282_EAD8)
+}
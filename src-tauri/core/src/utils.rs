@@ -0,0 +1,384 @@
+use crate::labels::LabelSet;
+use crate::strokes;
+use candle_core::{DType, Tensor};
+use candle_datasets;
+use image::DynamicImage;
+use rand::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// How pixels are scaled before they're fed to the model, recorded in model metadata.
+pub const NORMALIZATION: &str = "luma8 / 255";
+
+/// How the training split returned by `create_dataset` is assembled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatasetMode {
+    /// Train only on the user's drawings.
+    DrawingsOnly,
+    /// Replay MNIST training samples alongside the drawings so fine-tuning doesn't make the
+    /// pretrained model forget everything else. `mnist_ratio` is the share of each batch
+    /// taken up by MNIST samples, in `[0, 1)`.
+    Replay { mnist_ratio: f32 },
+}
+
+pub fn image_path_to_formatted_tensor(
+    path: &PathBuf,
+    device: &candle_core::Device,
+) -> candle_core::Result<Tensor> {
+    let image = image::open(path)
+        .map_err(|e| format!("Failed to open image: {}", e))
+        .expect("Failed to open image");
+    let image = image.resize_exact(28, 28, image::imageops::FilterType::Nearest);
+    let image = image.to_luma8().into_raw();
+    let image = image
+        .into_iter()
+        .map(|p: u8| p as f32 / 255.0)
+        .collect::<Vec<f32>>();
+    let image = Tensor::from_vec(image, &[784], device)?;
+
+    Ok(image)
+}
+
+pub fn image_to_formatted_tensor(image: DynamicImage) -> candle_core::Result<Tensor> {
+    // Assuming device is handled externally
+    let image = format_image(image);
+    let dev = candle_core::Device::cuda_if_available(0).unwrap_or(candle_core::Device::Cpu);
+    let image = Tensor::from_vec(image, &[784], &dev).expect("Failed to create tensor");
+    Ok(image)
+}
+
+pub fn format_image(image: DynamicImage) -> Vec<f32> {
+    let image = resize_drawing(&image);
+    let image = image.to_luma8().into_raw();
+    image
+        .into_iter()
+        .map(|p: u8| p as f32 / 255.0)
+        .collect::<Vec<f32>>()
+}
+
+/// Scales a canvas drawing down to the 28x28 size the model and the stored drawings use.
+pub fn resize_drawing(image: &DynamicImage) -> DynamicImage {
+    image.resize_exact(28, 28, image::imageops::FilterType::Nearest)
+}
+
+/// A random number generator seeded with `seed`, or from entropy when there is none.
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// FNV-1a hasher, stable across runs and platforms unlike `DefaultHasher`, for hashes that
+/// are stored on disk or end up in file names.
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// FNV-1a hash of `bytes`.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Creates a `Dataset` with training images from `drawings`, optionally blended with MNIST
+/// training samples (see `DatasetMode`), and test images from MNIST. Labels are indices into
+/// `labels`. `seed` makes the choice of replayed MNIST samples reproducible.
+///
+/// With `partial_drawings` set, unfinished versions of the drawings saved with strokes (see
+/// `strokes::partial_drawings`) are added for training. Leave it off when the training split
+/// should only hold the drawings as they were saved, e.g. to calibrate quantization.
+pub fn create_dataset(
+    drawings_dir: &PathBuf,
+    mode: DatasetMode,
+    partial_drawings: bool,
+    labels: &LabelSet,
+    seed: Option<u64>,
+) -> candle_core::Result<candle_datasets::vision::Dataset> {
+    let (concatenated_train_images, train_labels_tensor) = load_drawings(drawings_dir, labels)?;
+    let partial = if partial_drawings {
+        strokes::partial_drawings(drawings_dir, labels)?
+    } else {
+        None
+    };
+    let (concatenated_train_images, train_labels_tensor) = match partial {
+        Some((partial_images, partial_labels)) => (
+            Tensor::cat(&[&concatenated_train_images, &partial_images], 0)?,
+            Tensor::cat(&[&train_labels_tensor, &partial_labels], 0)?,
+        ),
+        None => (concatenated_train_images, train_labels_tensor),
+    };
+
+    // Load MNIST dataset for testing
+    let mnist_dataset = get_labelled_mnist_dataset(labels)?;
+
+    // Assuming mnist_dataset has train and test sets; we'll use test set for our testing
+    let test_images = mnist_dataset.test_images.clone();
+    let test_labels = mnist_dataset.test_labels.clone();
+
+    // Determine the number of unique labels
+    let unique_labels = labels.len();
+
+    let (concatenated_train_images, train_labels_tensor) = match mode {
+        DatasetMode::DrawingsOnly => (concatenated_train_images, train_labels_tensor),
+        DatasetMode::Replay { mnist_ratio } => mix_in_mnist(
+            &concatenated_train_images,
+            &train_labels_tensor,
+            &mnist_dataset,
+            mnist_ratio,
+            &mut seeded_rng(seed),
+        )?,
+    };
+
+    // **Add Logging Here**
+    println!(
+        "Concatenated train_images shape: {:?}",
+        concatenated_train_images.shape().dims()
+    );
+    println!(
+        "train_labels_tensor shape: {:?}",
+        train_labels_tensor.shape().dims()
+    );
+
+    Ok(candle_datasets::vision::Dataset {
+        train_images: concatenated_train_images, // Shape: [num_train_samples,784]
+        train_labels: train_labels_tensor,       // Shape: [num_train_samples]
+        test_images,
+        test_labels,
+        labels: unique_labels,
+    })
+}
+
+/// Whether `path` is a PNG or JPEG file, going by its extension.
+pub fn is_image_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "png" | "jpg" | "jpeg"))
+}
+
+/// Loads every drawing in `drawings_dir` as `(images, labels)` tensors, labelled by the index
+/// of their filename in `labels`, along with the imported drawings in its label folders.
+pub fn load_drawings(
+    drawings_dir: &PathBuf,
+    labels: &LabelSet,
+) -> candle_core::Result<(Tensor, Tensor)> {
+    // Get the device
+    let dev = candle_core::Device::cuda_if_available(0)?;
+
+    // Read all image files from the drawings directory
+    let entries = std::fs::read_dir(drawings_dir)
+        .map_err(|e| {
+            format!(
+                "Failed to read drawings directory '{}': {}",
+                drawings_dir.display(),
+                e
+            )
+        })
+        .expect("Failed to read drawings directory");
+
+    let mut train_images = Vec::new();
+    let mut train_labels = Vec::new();
+
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| format!("Failed to read directory entry: {}", e))
+            .expect("Failed to read directory entry");
+        let path = entry.path();
+
+        if path.is_file() {
+            if let Some(ext) = path.extension() {
+                let ext_lower = ext.to_str().map(|s| s.to_lowercase()).unwrap_or_default();
+                if ext_lower == "png" || ext_lower == "jpg" || ext_lower == "jpeg" {
+                    // Extract label from filename, assuming filename is like "3.png"
+                    if let Some(stem) = path.file_stem() {
+                        if let Some(stem_str) = stem.to_str() {
+                            // Look the label up in the label set
+                            if let Some(label) = labels.index_of(stem_str) {
+                                // Load and process the image
+                                let tensor = image_path_to_formatted_tensor(&path, &dev)?;
+                                train_images.push(tensor);
+                                train_labels.push(label as f32); // Label index, as f32
+                            } else {
+                                return Err(candle_core::Error::Msg(format!(
+                                    "Drawing {:?} is labelled '{}', which isn't one of {:?}",
+                                    path,
+                                    stem_str,
+                                    labels.labels()
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+        } else if path.is_dir() {
+            // Imported drawings are kept in a folder per label, like "3/<hash>.png"
+            let dir_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let label = labels.index_of(dir_name).ok_or_else(|| {
+                candle_core::Error::Msg(format!(
+                    "Drawings folder {:?} is labelled '{}', which isn't one of {:?}",
+                    path,
+                    dir_name,
+                    labels.labels()
+                ))
+            })?;
+
+            let mut images = std::fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<PathBuf>>>()?;
+            images.sort();
+
+            for image in images.iter().filter(|image| is_image_file(image)) {
+                train_images.push(image_path_to_formatted_tensor(image, &dev)?);
+                train_labels.push(label as f32);
+            }
+        }
+    }
+
+    if train_images.is_empty() {
+        return Err(candle_core::Error::CannotFindTensor {
+            path: (drawings_dir
+                .to_str()
+                .expect("Failed to convert path to string"))
+            .to_string(),
+        });
+    }
+
+    let train_labels_length = train_labels.len();
+
+    // Concatenate all training images into a single tensor
+    let concatenated_train_images = Tensor::stack(&train_images, 0)?; // Shape: [num_train_samples,784]
+
+    // Convert training labels into a single tensor
+    let train_labels_tensor =
+        candle_core::Tensor::from_vec(train_labels, &[train_labels_length], &dev)?; // Shape: [num_train_samples]
+
+    Ok((concatenated_train_images, train_labels_tensor))
+}
+
+/// Blends randomly chosen MNIST training samples in with the drawings.
+///
+/// The samples are interleaved rather than appended so that every contiguous batch the
+/// training loop narrows out gets roughly `mnist_ratio` MNIST samples. `create_dataset` calls
+/// this for `DatasetMode::Replay`.
+pub fn mix_in_mnist(
+    drawing_images: &Tensor,
+    drawing_labels: &Tensor,
+    mnist: &candle_datasets::vision::Dataset,
+    mnist_ratio: f32,
+    rng: &mut StdRng,
+) -> candle_core::Result<(Tensor, Tensor)> {
+    if !(0.0..1.0).contains(&mnist_ratio) {
+        return Err(candle_core::Error::Msg(format!(
+            "MNIST replay ratio must be in [0, 1), got {}",
+            mnist_ratio
+        )));
+    }
+
+    let dev = drawing_images.device();
+    let n_drawings = drawing_images.dim(0)?;
+    let n_available = mnist.train_images.dim(0)?;
+    let n_mnist = ((n_drawings as f32 * mnist_ratio) / (1.0 - mnist_ratio)).round() as usize;
+    let n_mnist = n_mnist.min(n_available);
+
+    if n_mnist == 0 {
+        return Ok((drawing_images.clone(), drawing_labels.clone()));
+    }
+
+    // Pick the MNIST samples to replay
+    let mnist_idxs = rand::seq::index::sample(rng, n_available, n_mnist)
+        .into_iter()
+        .map(|i| i as u32)
+        .collect::<Vec<u32>>();
+    let mnist_idxs = Tensor::from_vec(mnist_idxs, &[n_mnist], &candle_core::Device::Cpu)?;
+    let mnist_images = mnist
+        .train_images
+        .index_select(&mnist_idxs, 0)?
+        .to_dtype(DType::F32)?
+        .to_device(dev)?;
+    let mnist_labels = mnist
+        .train_labels
+        .index_select(&mnist_idxs, 0)?
+        .to_dtype(DType::F32)?
+        .to_device(dev)?;
+
+    // Drawings come first in the concatenated tensors, MNIST samples after them
+    let images = Tensor::cat(&[drawing_images, &mnist_images], 0)?;
+    let labels = Tensor::cat(&[&drawing_labels.to_dtype(DType::F32)?, &mnist_labels], 0)?;
+
+    // Spread the MNIST samples evenly through the drawings
+    let total = n_drawings + n_mnist;
+    let mut order = Vec::with_capacity(total);
+    let (mut drawings_taken, mut mnist_taken) = (0, 0);
+    for i in 0..total {
+        if (i + 1) * n_mnist / total > mnist_taken {
+            order.push((n_drawings + mnist_taken) as u32);
+            mnist_taken += 1;
+        } else {
+            order.push(drawings_taken as u32);
+            drawings_taken += 1;
+        }
+    }
+    let order = Tensor::from_vec(order, &[total], dev)?;
+
+    println!(
+        "Replaying {} MNIST samples alongside {} drawings",
+        n_mnist, n_drawings
+    );
+
+    Ok((
+        images.index_select(&order, 0)?,
+        labels.index_select(&order, 0)?,
+    ))
+}
+
+/// Replaces each MNIST digit label with its index in the label set.
+fn remap_digit_labels(labels: &Tensor, digit_indices: &[u32; 10]) -> candle_core::Result<Tensor> {
+    let remapped = labels
+        .to_dtype(DType::U32)?
+        .to_vec1::<u32>()?
+        .into_iter()
+        .map(|digit| digit_indices[digit as usize])
+        .collect::<Vec<u32>>();
+    let len = remapped.len();
+    Tensor::from_vec(remapped, &[len], labels.device())
+}
+
+pub fn get_mnist_dataset() -> candle_core::Result<candle_datasets::vision::Dataset> {
+    candle_datasets::vision::mnist::load()
+}
+
+/// Loads MNIST with its digit labels replaced by their index in `labels`.
+pub fn get_labelled_mnist_dataset(
+    labels: &LabelSet,
+) -> candle_core::Result<candle_datasets::vision::Dataset> {
+    // MNIST labels are digits, map them to their index in the label set
+    let digit_indices = labels.digit_indices()?;
+    let mnist = get_mnist_dataset()?;
+
+    Ok(candle_datasets::vision::Dataset {
+        train_labels: remap_digit_labels(&mnist.train_labels, &digit_indices)?,
+        test_labels: remap_digit_labels(&mnist.test_labels, &digit_indices)?,
+        train_images: mnist.train_images,
+        test_images: mnist.test_images,
+        labels: labels.len(),
+    })
+}
@@ -0,0 +1,44 @@
+use image::{GrayImage, ImageBuffer, Luma};
+use imageproc::filter;
+
+/// Edge detection kernel used to illustrate what a convolution layer does.
+const EDGE_KERNEL: [f32; 9] = [-1.0, -1.0, -1.0, -1.0, 8.0, -1.0, -1.0, -1.0, -1.0];
+
+/// Convolves `img` with an edge detection kernel, clamping the result back to u8.
+pub fn edge_filter(img: &GrayImage) -> GrayImage {
+    let conv_img: ImageBuffer<Luma<f32>, Vec<f32>> = filter::filter3x3(img, &EDGE_KERNEL);
+
+    ImageBuffer::from_fn(conv_img.width(), conv_img.height(), |x, y| {
+        Luma([conv_img.get_pixel(x, y).0[0].clamp(0.0, 255.0) as u8])
+    })
+}
+
+/// Simple max pooling implementation
+pub fn max_pooling(img: &GrayImage, pool_size: usize, stride: usize) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let pooled_width = ((width - pool_size as u32) / stride as u32) + 1;
+    let pooled_height = ((height - pool_size as u32) / stride as u32) + 1;
+
+    let mut pooled_img = ImageBuffer::new(pooled_width, pooled_height);
+
+    for y in 0..pooled_height {
+        for x in 0..pooled_width {
+            let mut max_val = 0u8;
+            for dy in 0..pool_size {
+                for dx in 0..pool_size {
+                    let px = x as usize * stride + dx;
+                    let py = y as usize * stride + dy;
+                    if px < width as usize && py < height as usize {
+                        let p = img.get_pixel(px as u32, py as u32).0[0];
+                        if p > max_val {
+                            max_val = p;
+                        }
+                    }
+                }
+            }
+            pooled_img.put_pixel(x, y, Luma([max_val]));
+        }
+    }
+
+    pooled_img
+}
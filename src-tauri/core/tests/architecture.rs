@@ -0,0 +1,90 @@
+use train_an_ai_core::architecture::{ArchitectureSpec, FeatureShape};
+
+/// A spec with the default input and `layers`, given as JSON objects.
+fn spec(layers: &str) -> ArchitectureSpec {
+    ArchitectureSpec::from_json(&format!(
+        r#"{{ "id": "test", "input_shape": [1, 28, 28], "layers": [{}] }}"#,
+        layers
+    ))
+    .unwrap()
+}
+
+/// The error `ArchitectureSpec::shapes` gives for `layers` with 10 labels.
+fn shape_error(layers: &str) -> String {
+    spec(layers).shapes(10).unwrap_err().to_string()
+}
+
+#[test]
+fn shapes_follow_the_layers() {
+    let shapes = spec(
+        r#"{ "type": "conv", "name": "c1", "out_channels": 4, "kernel": 3, "padding": 1 },
+           { "type": "max_pool", "size": 2 },
+           { "type": "flatten" },
+           { "type": "linear", "name": "fc1" }"#,
+    )
+    .shapes(10)
+    .unwrap();
+
+    assert_eq!(
+        shapes,
+        [
+            FeatureShape::Spatial {
+                channels: 1,
+                height: 28,
+                width: 28
+            },
+            FeatureShape::Spatial {
+                channels: 4,
+                height: 28,
+                width: 28
+            },
+            FeatureShape::Spatial {
+                channels: 4,
+                height: 14,
+                width: 14
+            },
+            FeatureShape::Flat(784),
+            FeatureShape::Flat(10),
+        ]
+    );
+}
+
+#[test]
+fn bad_specs_are_rejected() {
+    let duplicate_names = shape_error(
+        r#"{ "type": "linear", "name": "fc", "out_features": 16 },
+           { "type": "linear", "name": "fc" }"#,
+    );
+    assert!(
+        duplicate_names.contains("duplicate layer names"),
+        "{}",
+        duplicate_names
+    );
+
+    let kernel_too_large =
+        shape_error(r#"{ "type": "conv", "name": "c1", "out_channels": 4, "kernel": 29 }"#);
+    assert!(
+        kernel_too_large.contains("window of 29 is larger than input of 28"),
+        "{}",
+        kernel_too_large
+    );
+
+    let conv_after_flatten = shape_error(
+        r#"{ "type": "flatten" },
+           { "type": "conv", "name": "c1", "out_channels": 4, "kernel": 3 }"#,
+    );
+    assert!(
+        conv_after_flatten.contains("Layer 1") && conv_after_flatten.contains("flattened"),
+        "{}",
+        conv_after_flatten
+    );
+
+    let zero_stride = shape_error(r#"{ "type": "max_pool", "size": 2, "stride": 0 }"#);
+    assert!(zero_stride.contains("must be positive"), "{}", zero_stride);
+
+    let dropout = shape_error(r#"{ "type": "dropout", "p": 1.0 }"#);
+    assert!(dropout.contains("dropout probability"), "{}", dropout);
+
+    let momentum = shape_error(r#"{ "type": "batch_norm", "name": "bn", "momentum": 2.0 }"#);
+    assert!(momentum.contains("momentum"), "{}", momentum);
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use candle_nn::VarMap;
+use common::{constant_model, saved_value};
+use std::fs;
+use train_an_ai_core::checkpoint;
+use train_an_ai_core::model::ConvNet;
+
+#[test]
+fn save_keeps_the_replaced_model_as_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+
+    let (model, vm) = constant_model(1.0);
+    model.save(&vm, &path, None).unwrap();
+    assert_eq!(saved_value(&path), 1.0);
+    assert!(!checkpoint::backup_path(&path).exists());
+
+    let (model, vm) = constant_model(2.0);
+    model.save(&vm, &path, None).unwrap();
+    assert_eq!(saved_value(&path), 2.0);
+    assert_eq!(saved_value(&checkpoint::backup_path(&path)), 1.0);
+
+    // The temporary file is renamed over the model, not left next to it
+    let files = fs::read_dir(dir.path()).unwrap().count();
+    assert_eq!(files, 2);
+}
+
+#[test]
+fn saved_model_loads_with_its_metadata() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+
+    let (model, vm) = constant_model(0.5);
+    model.save(&vm, &path, None).unwrap();
+
+    let mut loaded_vm = VarMap::new();
+    let loaded = ConvNet::new_from_file(&mut loaded_vm, &path).unwrap();
+    assert_eq!(loaded.spec(), model.spec());
+    assert_eq!(loaded.labels().labels(), model.labels().labels());
+}
+
+#[test]
+fn rollback_restores_the_previous_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+
+    for value in [1.0, 2.0] {
+        let (model, vm) = constant_model(value);
+        model.save(&vm, &path, None).unwrap();
+    }
+
+    checkpoint::rollback(&path).unwrap();
+    assert_eq!(saved_value(&path), 1.0);
+    assert!(!checkpoint::backup_path(&path).exists());
+
+    // There is only one backup to go back to
+    assert!(checkpoint::rollback(&path).is_err());
+    assert_eq!(saved_value(&path), 1.0);
+}
+
+#[test]
+fn rollback_refuses_an_unreadable_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+
+    let (model, vm) = constant_model(1.0);
+    model.save(&vm, &path, None).unwrap();
+    fs::write(checkpoint::backup_path(&path), b"not a model").unwrap();
+
+    assert!(checkpoint::rollback(&path).is_err());
+    assert_eq!(saved_value(&path), 1.0);
+}
+
+#[test]
+fn failed_save_keeps_the_model_and_its_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+
+    for value in [1.0, 2.0] {
+        let (model, vm) = constant_model(value);
+        model.save(&vm, &path, None).unwrap();
+    }
+
+    // A model whose source can't be read is never installed
+    assert!(checkpoint::install(&dir.path().join("missing.safetensors"), &path).is_err());
+    assert_eq!(saved_value(&path), 2.0);
+    assert_eq!(saved_value(&checkpoint::backup_path(&path)), 1.0);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
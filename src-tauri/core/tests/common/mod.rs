@@ -0,0 +1,165 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use candle_core::{Device, Tensor};
+use candle_datasets::vision::Dataset;
+use candle_nn::VarMap;
+use rand::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::{ConvNet, TrainingArgs};
+
+/// Random images and labels, the same on every call.
+pub fn synthetic_dataset() -> Dataset {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut split = |n: usize| {
+        let images = (0..n * 784).map(|_| rng.gen::<f32>()).collect::<Vec<f32>>();
+        let labels = (0..n).map(|_| rng.gen_range(0..10u8)).collect::<Vec<u8>>();
+        (
+            Tensor::from_vec(images, &[n, 784], &Device::Cpu).unwrap(),
+            Tensor::from_vec(labels, &[n], &Device::Cpu).unwrap(),
+        )
+    };
+    let (train_images, train_labels) = split(40);
+    let (test_images, test_labels) = split(20);
+
+    Dataset {
+        train_images,
+        train_labels,
+        test_images,
+        test_labels,
+        labels: 10,
+    }
+}
+
+/// Seeded training for `epochs` epochs in batches of 10, saving nothing.
+pub fn training_args(epochs: usize, seed: u64) -> TrainingArgs {
+    TrainingArgs {
+        epochs,
+        learning_rate: 0.01,
+        batch_size: 10,
+        load: None,
+        save: None,
+        frozen_layers: Vec::new(),
+        patience: None,
+        log_dir: None,
+        seed: Some(seed),
+        distillation: None,
+    }
+}
+
+/// Every variable's values, by name.
+pub fn weights(vm: &VarMap) -> HashMap<String, Vec<f32>> {
+    vm.data()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, var)| {
+            let values = var
+                .as_tensor()
+                .flatten_all()
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap();
+            (name.clone(), values)
+        })
+        .collect()
+}
+
+/// The bit patterns of `values`, for comparing floats exactly.
+pub fn bits(values: &[f32]) -> Vec<u32> {
+    values.iter().map(|v| v.to_bits()).collect()
+}
+
+/// Builds a default model whose weights are all `value`.
+pub fn constant_model(value: f32) -> (ConvNet, VarMap) {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits()).unwrap();
+    for var in vm.all_vars() {
+        var.set(&Tensor::full(value, var.shape(), &Device::Cpu).unwrap())
+            .unwrap();
+    }
+    (model, vm)
+}
+
+/// Saves a default model whose weights are all `value` to `path`.
+pub fn save_constant_model(path: &Path, value: f32) {
+    let (model, vm) = constant_model(value);
+    model.save(&vm, path, None).unwrap();
+}
+
+/// The first value of `fc2.weight` in the model file at `path`.
+pub fn saved_value(path: &Path) -> f32 {
+    candle_core::safetensors::load(path, &Device::Cpu).unwrap()["fc2.weight"]
+        .flatten_all()
+        .unwrap()
+        .get(0)
+        .unwrap()
+        .to_scalar::<f32>()
+        .unwrap()
+}
+
+/// A model of `spec` predicting `labels` whose variables are drawn from a seeded generator:
+/// weights within +-1/sqrt(fan in) and batch norm variances in [0.5, 1.5).
+pub fn seeded_model(vm: &mut VarMap, spec: &ArchitectureSpec, labels: &LabelSet) -> ConvNet {
+    let model = ConvNet::new(vm, spec, labels).unwrap();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let vars = vm.data().lock().unwrap();
+    let mut names = vars.keys().cloned().collect::<Vec<String>>();
+    names.sort();
+    for name in names {
+        let var = &vars[&name];
+        let dims = var.dims().to_vec();
+        let count = var.elem_count();
+        let (low, high) = if name.ends_with("running_var") {
+            (0.5, 1.5)
+        } else {
+            let bound = 1.0 / ((count / dims[0]) as f32).sqrt();
+            (-bound, bound)
+        };
+        let values = (0..count)
+            .map(|_| rng.gen_range(low..high))
+            .collect::<Vec<f32>>();
+        var.set(&Tensor::from_vec(values, dims, &Device::Cpu).unwrap())
+            .unwrap();
+    }
+    drop(vars);
+
+    model
+}
+
+/// `n` random 28x28 images, the same for the same `seed`.
+pub fn seeded_images(n: usize, seed: u64) -> Tensor {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let pixels = (0..n * 784).map(|_| rng.gen::<f32>()).collect::<Vec<f32>>();
+    Tensor::from_vec(pixels, (n, 1, 28, 28), &Device::Cpu).unwrap()
+}
+
+/// The largest absolute value in `tensor`.
+pub fn max_abs(tensor: &Tensor) -> f32 {
+    tensor
+        .abs()
+        .unwrap()
+        .flatten_all()
+        .unwrap()
+        .max(0)
+        .unwrap()
+        .to_scalar::<f32>()
+        .unwrap()
+}
+
+/// Asserts `actual` has the shape of `expected` and no element differs by more than
+/// `tolerance`.
+pub fn assert_close(expected: &Tensor, actual: &Tensor, tolerance: f32) {
+    assert_eq!(expected.dims(), actual.dims());
+    let max_diff = max_abs(&(expected - actual).unwrap());
+    assert!(
+        max_diff <= tolerance,
+        "differs by {} (tolerance {})",
+        max_diff,
+        tolerance
+    );
+}
@@ -0,0 +1,68 @@
+use candle_core::{Device, Tensor};
+use candle_datasets::vision::Dataset;
+use rand::prelude::*;
+use train_an_ai_core::utils;
+
+/// `n` blank drawings, all labelled 0.
+fn drawings(n: usize) -> (Tensor, Tensor) {
+    (
+        Tensor::zeros((n, 784), candle_core::DType::F32, &Device::Cpu).unwrap(),
+        Tensor::zeros(n, candle_core::DType::F32, &Device::Cpu).unwrap(),
+    )
+}
+
+/// A stand-in for MNIST whose training samples are all labelled 1, so replayed samples can be
+/// told apart from the drawings.
+fn mnist(n: usize) -> Dataset {
+    let images = Tensor::ones((n, 784), candle_core::DType::F32, &Device::Cpu).unwrap();
+    let labels = Tensor::ones(n, candle_core::DType::U8, &Device::Cpu).unwrap();
+    Dataset {
+        train_images: images.clone(),
+        train_labels: labels.clone(),
+        test_images: images,
+        test_labels: labels,
+        labels: 10,
+    }
+}
+
+#[test]
+fn replay_ratio_is_honoured_in_every_batch() {
+    let (images, labels) = drawings(30);
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let (images, labels) =
+        utils::mix_in_mnist(&images, &labels, &mnist(100), 0.25, &mut rng).unwrap();
+
+    // A quarter of the samples, so 10 MNIST samples alongside the 30 drawings
+    let labels = labels.to_vec1::<f32>().unwrap();
+    assert_eq!(images.dims(), &[40, 784]);
+    assert_eq!(labels.iter().filter(|&&label| label == 1.0).count(), 10);
+
+    // Spread evenly, one in every four consecutive samples
+    for batch in labels.chunks(4) {
+        assert_eq!(
+            batch.iter().filter(|&&label| label == 1.0).count(),
+            1,
+            "batch {:?}",
+            batch
+        );
+    }
+
+    // Images move together with their labels
+    let pixels = images.mean(1).unwrap().to_vec1::<f32>().unwrap();
+    assert_eq!(pixels, labels);
+}
+
+#[test]
+fn replay_ratio_outside_the_range_is_rejected() {
+    let (images, labels) = drawings(10);
+    let mut rng = StdRng::seed_from_u64(0);
+
+    for ratio in [-0.1, 1.0] {
+        assert!(utils::mix_in_mnist(&images, &labels, &mnist(100), ratio, &mut rng).is_err());
+    }
+
+    // No replay leaves the drawings as they are
+    let (mixed, _) = utils::mix_in_mnist(&images, &labels, &mnist(100), 0.0, &mut rng).unwrap();
+    assert_eq!(mixed.dims(), &[10, 784]);
+}
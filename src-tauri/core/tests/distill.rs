@@ -0,0 +1,56 @@
+mod common;
+
+use candle_core::{Device, Tensor, D};
+use candle_nn::{loss, ops, VarMap};
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::distill::Distillation;
+use train_an_ai_core::labels::LabelSet;
+
+fn distillation(alpha: f64) -> Distillation {
+    let mut vm = VarMap::new();
+    let teacher = common::seeded_model(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits());
+    Distillation {
+        teacher,
+        temperature: 4.0,
+        alpha,
+    }
+}
+
+fn labels() -> Tensor {
+    Tensor::new(&[3u32, 0, 7, 9], &Device::Cpu).unwrap()
+}
+
+#[test]
+fn without_soft_targets_the_loss_is_cross_entropy() {
+    let distillation = distillation(0.0);
+    let images = common::seeded_images(4, 1);
+    let student_logits = common::seeded_images(4, 2)
+        .flatten_from(1)
+        .unwrap()
+        .narrow(1, 0, 10)
+        .unwrap();
+
+    let expected = loss::nll(
+        &ops::log_softmax(&student_logits, D::Minus1).unwrap(),
+        &labels(),
+    )
+    .unwrap();
+    let actual = distillation
+        .loss(&images, &student_logits, &labels())
+        .unwrap();
+
+    common::assert_close(&expected, &actual, 1e-6);
+}
+
+#[test]
+fn matching_the_teacher_leaves_no_soft_loss() {
+    let distillation = distillation(1.0);
+    let images = common::seeded_images(4, 1);
+    let student_logits = distillation.teacher.logits(&images).unwrap();
+
+    let actual = distillation
+        .loss(&images, &student_logits, &labels())
+        .unwrap();
+
+    assert!(actual.to_scalar::<f32>().unwrap().abs() < 1e-5);
+}
@@ -0,0 +1,336 @@
+use image::{GrayImage, ImageFormat, Luma};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use train_an_ai_core::drawings::{self, ImportReport};
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::utils;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// A 28x28 image of `background` with a square of `ink` at `(x, y)`.
+fn square(x: u32, y: u32, ink: u8, background: u8) -> GrayImage {
+    GrayImage::from_fn(28, 28, |px, py| {
+        let inside = (x..x + 8).contains(&px) && (y..y + 8).contains(&py);
+        Luma([if inside { ink } else { background }])
+    })
+}
+
+fn png(image: &GrayImage) -> Vec<u8> {
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+    bytes.into_inner()
+}
+
+/// The files of a folder or archive to import, one of each kind `import_drawings` tells
+/// apart.
+fn source_files() -> Vec<(&'static str, Vec<u8>)> {
+    let drawing = png(&square(4, 4, 255, 0));
+    vec![
+        ("3/a.png", drawing.clone()),
+        // Scanned style, dark ink on white paper
+        ("3/dark.png", png(&square(12, 12, 0, 255))),
+        ("3/blank.png", png(&GrayImage::new(28, 28))),
+        ("42/unknown.png", drawing.clone()),
+        ("3/notes.txt", b"not an image".to_vec()),
+        ("3/copy.png", drawing),
+        ("loose.png", png(&square(8, 8, 255, 0))),
+    ]
+}
+
+fn reason<'a>(report: &'a ImportReport, source: &str) -> &'a str {
+    &report
+        .rejected
+        .iter()
+        .find(|file| file.source == source)
+        .unwrap_or_else(|| panic!("{} wasn't rejected: {:?}", source, report))
+        .reason
+}
+
+/// Checks the report and store of importing `source_files` into an empty store.
+fn assert_imported(report: &ImportReport, drawings_dir: &Path) {
+    let accepted = report
+        .accepted
+        .iter()
+        .map(|file| (file.source.as_str(), file.label.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(accepted, [("3/a.png", "3"), ("3/dark.png", "3")]);
+
+    assert!(reason(report, "3/blank.png").contains("blank"));
+    assert!(reason(report, "42/unknown.png").contains("label '42'"));
+    assert!(reason(report, "3/notes.txt").contains("not a valid image"));
+    assert!(reason(report, "3/copy.png").contains("duplicate"));
+    assert!(reason(report, "loose.png").contains("not in a label folder"));
+    assert_eq!(report.rejected.len(), 5);
+
+    // Stored as `<label>/<hash>.png`, white on black
+    let mut stored = fs::read_dir(drawings_dir.join("3"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<PathBuf>>();
+    stored.sort();
+    assert_eq!(stored.len(), 2);
+    for file in report.accepted.iter() {
+        let path = drawings_dir.join(&file.stored);
+        assert!(stored.contains(&path), "{:?} isn't stored", path);
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.len() == "0123456789abcdef.png".len() && name.ends_with(".png"));
+    }
+
+    let image = |file: &str| {
+        let stored = &report
+            .accepted
+            .iter()
+            .find(|accepted| accepted.source == file)
+            .unwrap()
+            .stored;
+        image::open(drawings_dir.join(stored)).unwrap().to_luma8()
+    };
+    assert_eq!(image("3/a.png"), square(4, 4, 255, 0));
+    assert_eq!(image("3/dark.png"), square(12, 12, 255, 0));
+}
+
+#[test]
+fn import_from_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    for (name, bytes) in source_files() {
+        let path = source.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+    let drawings_dir = dir.path().join("drawings");
+
+    let report = drawings::import_drawings(&source, &drawings_dir, &LabelSet::digits()).unwrap();
+    assert_imported(&report, &drawings_dir);
+
+    // Importing again adds nothing
+    let again = drawings::import_drawings(&source, &drawings_dir, &LabelSet::digits()).unwrap();
+    assert!(again.accepted.is_empty());
+    assert!(reason(&again, "3/a.png").contains("duplicate"));
+}
+
+#[cfg(unix)]
+#[test]
+fn import_skips_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    let outside = dir.path().join("outside").join("3");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("a.png"), png(&square(4, 4, 255, 0))).unwrap();
+
+    let source = dir.path().join("source");
+    fs::create_dir_all(source.join("7")).unwrap();
+    fs::write(source.join("7").join("b.png"), png(&square(8, 8, 255, 0))).unwrap();
+    std::os::unix::fs::symlink(&outside, source.join("3")).unwrap();
+    std::os::unix::fs::symlink(&source, source.join("7").join("loop")).unwrap();
+
+    let drawings_dir = dir.path().join("drawings");
+    let report = drawings::import_drawings(&source, &drawings_dir, &LabelSet::digits()).unwrap();
+
+    let accepted = report
+        .accepted
+        .iter()
+        .map(|file| file.source.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(accepted, ["7/b.png"]);
+    assert!(report.rejected.is_empty());
+}
+
+#[test]
+fn import_from_zip() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("drawings.zip");
+    let mut zip = ZipWriter::new(fs::File::create(&archive).unwrap());
+    let mut files = source_files();
+    files.push(("../escaped/3/evil.png", png(&square(16, 16, 255, 0))));
+    for (name, bytes) in files {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(&bytes).unwrap();
+    }
+    zip.finish().unwrap();
+    let drawings_dir = dir.path().join("store").join("drawings");
+
+    let report = drawings::import_drawings(&archive, &drawings_dir, &LabelSet::digits()).unwrap();
+
+    // The entry escaping the archive isn't even considered
+    assert_imported(&report, &drawings_dir);
+    assert!(!dir.path().join("store").join("escaped").exists());
+    assert!(!dir.path().join("escaped").exists());
+}
+
+#[test]
+fn oversized_zip_entries_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("bomb.zip");
+    let mut zip = ZipWriter::new(fs::File::create(&archive).unwrap());
+    zip.start_file("3/huge.png", SimpleFileOptions::default())
+        .unwrap();
+    // Deflates to a few kilobytes
+    zip.write_all(&vec![0; 17 << 20]).unwrap();
+    zip.start_file("3/a.png", SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(&png(&square(4, 4, 255, 0))).unwrap();
+    zip.finish().unwrap();
+    let drawings_dir = dir.path().join("drawings");
+
+    let report = drawings::import_drawings(&archive, &drawings_dir, &LabelSet::digits()).unwrap();
+
+    assert_eq!(report.accepted.len(), 1);
+    assert!(reason(&report, "3/huge.png").contains("larger than"));
+}
+
+#[test]
+fn load_drawings_reads_label_folders() {
+    let dir = tempfile::tempdir().unwrap();
+    let drawings_dir = dir.path().to_path_buf();
+    square(4, 4, 255, 0)
+        .save(drawings_dir.join("3.png"))
+        .unwrap();
+    fs::create_dir_all(drawings_dir.join("7")).unwrap();
+    square(8, 8, 255, 0)
+        .save(drawings_dir.join("7").join("0123456789abcdef.png"))
+        .unwrap();
+    square(12, 12, 255, 0)
+        .save(drawings_dir.join("7").join("fedcba9876543210.png"))
+        .unwrap();
+
+    let (images, labels) = utils::load_drawings(&drawings_dir, &LabelSet::digits()).unwrap();
+
+    assert_eq!(images.dims(), &[3, 784]);
+    let mut labels = labels.to_vec1::<f32>().unwrap();
+    labels.sort_by(f32::total_cmp);
+    assert_eq!(labels, [3.0, 7.0, 7.0]);
+
+    // A folder named after an unknown label is an error, like a drawing file would be
+    fs::create_dir_all(drawings_dir.join("42")).unwrap();
+    assert!(utils::load_drawings(&drawings_dir, &LabelSet::digits()).is_err());
+}
+
+/// A store with a `3` drawn in the app with strokes and an imported `7`.
+fn store_to_export(drawings_dir: &Path) {
+    fs::create_dir_all(drawings_dir.join("7")).unwrap();
+    square(4, 4, 255, 0)
+        .save(drawings_dir.join("3.png"))
+        .unwrap();
+    fs::write(drawings_dir.join("3.strokes.json"), b"{\"strokes\": []}").unwrap();
+    square(12, 12, 255, 0)
+        .save(drawings_dir.join("7").join("0123456789abcdef.png"))
+        .unwrap();
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn export_idx_writes_mnist_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let drawings_dir = dir.path().join("drawings");
+    store_to_export(&drawings_dir);
+    let out_dir = dir.path().join("export");
+
+    let report = drawings::export_idx(&drawings_dir, &LabelSet::digits(), &out_dir).unwrap();
+    assert_eq!(report.drawings, 2);
+    assert_eq!(report.files.len(), 3);
+
+    let images = fs::read(out_dir.join("drawings-images-idx3-ubyte")).unwrap();
+    assert_eq!(be_u32(&images, 0), 0x0803);
+    assert_eq!(
+        [be_u32(&images, 4), be_u32(&images, 8), be_u32(&images, 12)],
+        [2, 28, 28]
+    );
+    assert_eq!(images.len(), 16 + 2 * 784);
+    // Drawn drawings come first, then imported ones
+    assert_eq!(
+        &images[16..16 + 784],
+        square(4, 4, 255, 0).as_raw().as_slice()
+    );
+    assert_eq!(
+        &images[16 + 784..],
+        square(12, 12, 255, 0).as_raw().as_slice()
+    );
+
+    let labels = fs::read(out_dir.join("drawings-labels-idx1-ubyte")).unwrap();
+    assert_eq!(be_u32(&labels, 0), 0x0801);
+    assert_eq!(be_u32(&labels, 4), 2);
+    assert_eq!(&labels[8..], [3, 7]);
+
+    let names: Vec<String> =
+        serde_json::from_slice(&fs::read(out_dir.join("labels.json")).unwrap()).unwrap();
+    assert_eq!(names, LabelSet::digits().labels());
+
+    // Nothing is left over from writing the files
+    assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 3);
+}
+
+#[test]
+fn export_zip_writes_a_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let drawings_dir = dir.path().join("drawings");
+    store_to_export(&drawings_dir);
+    let out = dir.path().join("drawings.zip");
+
+    let report = drawings::export_zip(&drawings_dir, &LabelSet::digits(), &out).unwrap();
+    assert_eq!(report.drawings, 2);
+    assert_eq!(report.per_label.get("3"), Some(&1));
+    assert_eq!(report.per_label.get("7"), Some(&1));
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&out).unwrap()).unwrap();
+    let mut read = |name: &str| {
+        let mut bytes = Vec::new();
+        archive
+            .by_name(name)
+            .unwrap_or_else(|e| panic!("{} isn't in the archive: {}", name, e))
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    };
+
+    let manifest: serde_json::Value = serde_json::from_slice(&read("manifest.json")).unwrap();
+    assert_eq!(
+        manifest["labels"],
+        serde_json::json!(LabelSet::digits().labels())
+    );
+    let drawings = manifest["drawings"].as_array().unwrap();
+    assert_eq!(drawings.len(), 2);
+
+    assert_eq!(drawings[0]["label"], "3");
+    assert_eq!(drawings[0]["label_index"], 3);
+    assert_eq!(drawings[0]["source"], "3.png");
+    assert_eq!(drawings[1]["label"], "7");
+    assert_eq!(
+        drawings[1]["source"],
+        Path::new("7")
+            .join("0123456789abcdef.png")
+            .to_str()
+            .unwrap()
+    );
+    assert!(drawings[1]["strokes"].is_null());
+
+    let strokes = drawings[0]["strokes"].as_str().unwrap();
+    assert_eq!(read(strokes), b"{\"strokes\": []}");
+    for (drawing, expected) in drawings
+        .iter()
+        .zip([square(4, 4, 255, 0), square(12, 12, 255, 0)])
+    {
+        let file = drawing["file"].as_str().unwrap();
+        assert!(file.starts_with(&format!("{}/", drawing["label"].as_str().unwrap())));
+        let image = image::load_from_memory(&read(file)).unwrap().to_luma8();
+        assert_eq!(image, expected);
+    }
+}
+
+#[test]
+fn failed_export_zip_leaves_no_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let drawings_dir = dir.path().join("drawings");
+    store_to_export(&drawings_dir);
+    // Strokes that can't be read fail the export halfway through the archive
+    fs::remove_file(drawings_dir.join("3.strokes.json")).unwrap();
+    fs::create_dir(drawings_dir.join("3.strokes.json")).unwrap();
+    let out = dir.path().join("drawings.zip");
+
+    assert!(drawings::export_zip(&drawings_dir, &LabelSet::digits(), &out).is_err());
+    assert!(!out.exists());
+    assert!(!dir.path().join("drawings.zip.tmp").exists());
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use candle_nn::VarMap;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::ConvNet;
+
+/// The digits with a symbol on either side, so every digit moves to a new output row.
+fn digits_and_symbols() -> LabelSet {
+    let mut labels = vec!["plus".to_string()];
+    labels.extend((0..10).map(|digit| digit.to_string()));
+    labels.push("minus".to_string());
+    LabelSet::new(labels).unwrap()
+}
+
+#[test]
+fn expanding_moves_existing_rows_to_their_new_labels() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("digits.safetensors");
+    let mut vm = VarMap::new();
+    let model = common::seeded_model(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits());
+    model.save(&vm, &path, None).unwrap();
+    let saved = common::weights(&vm);
+
+    let labels = digits_and_symbols();
+    let mut expanded_vm = VarMap::new();
+    let expanded = ConvNet::expand_from_file(&mut expanded_vm, &path, &labels).unwrap();
+    assert_eq!(expanded.labels(), &labels);
+    let weights = common::weights(&expanded_vm);
+
+    // Each digit's output row and bias are carried over bit for bit, one row further down
+    let row_len = saved["fc2.weight"].len() / 10;
+    assert_eq!(weights["fc2.weight"].len(), 12 * row_len);
+    for digit in 0..10 {
+        let new_row = labels.index_of(&digit.to_string()).unwrap();
+        assert_eq!(new_row, digit + 1);
+        assert_eq!(
+            common::bits(&weights["fc2.weight"][new_row * row_len..(new_row + 1) * row_len]),
+            common::bits(&saved["fc2.weight"][digit * row_len..(digit + 1) * row_len]),
+            "row of '{}'",
+            digit
+        );
+        assert_eq!(
+            weights["fc2.bias"][new_row].to_bits(),
+            saved["fc2.bias"][digit].to_bits()
+        );
+    }
+
+    // Every other layer is unchanged
+    for (name, values) in saved.iter().filter(|(name, _)| !name.starts_with("fc2.")) {
+        assert_eq!(
+            common::bits(&weights[name]),
+            common::bits(values),
+            "'{}' changed",
+            name
+        );
+    }
+}
+
+#[test]
+fn expanding_without_an_existing_label_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("digits.safetensors");
+    let mut vm = VarMap::new();
+    let model = common::seeded_model(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits());
+    model.save(&vm, &path, None).unwrap();
+
+    let mut labels = (1..10)
+        .map(|digit| digit.to_string())
+        .collect::<Vec<String>>();
+    labels.push("plus".to_string());
+    let labels = LabelSet::new(labels).unwrap();
+
+    let error = ConvNet::expand_from_file(&mut VarMap::new(), &path, &labels).unwrap_err();
+    assert!(
+        error.to_string().contains("missing existing label '0'"),
+        "unexpected error: {}",
+        error
+    );
+}
@@ -0,0 +1,125 @@
+"""Writes the PyTorch fixtures for tests/import.rs without needing PyTorch.
+
+`torch.save` pickles tensors as calls to `torch._utils._rebuild_tensor_v2` on storages kept
+as separate files in a zip archive. This script stands in minimal fakes of those torch
+objects so the standard pickle module produces the same layout.
+
+    python3 make_pth.py
+
+The tensors follow PyTorch `nn.Sequential` naming (`features.0`, `classifier`), so they only
+match the test architecture's layers (`c1`, `fc1`) by order. Every value is
+`((i + 5 * t) % 17 - 8) / 16` for element `i` of tensor `t`, exactly representable in f32.
+"""
+
+import collections
+import os
+import pickle
+import struct
+import sys
+import types
+import zipfile
+
+torch = types.ModuleType("torch")
+torch_utils = types.ModuleType("torch._utils")
+sys.modules["torch"] = torch
+sys.modules["torch._utils"] = torch_utils
+
+
+def _rebuild_tensor_v2(*args):
+    raise NotImplementedError
+
+
+_rebuild_tensor_v2.__module__ = "torch._utils"
+torch_utils._rebuild_tensor_v2 = _rebuild_tensor_v2
+
+
+class FloatStorage:
+    pass
+
+
+FloatStorage.__module__ = "torch"
+torch.FloatStorage = FloatStorage
+
+
+class Storage:
+    def __init__(self, key, values):
+        self.key = key
+        self.values = values
+
+
+class Tensor:
+    def __init__(self, storage, shape):
+        self.storage = storage
+        self.shape = shape
+
+    def __reduce__(self):
+        stride = []
+        step = 1
+        for dim in reversed(self.shape):
+            stride.insert(0, step)
+            step *= dim
+        return (
+            _rebuild_tensor_v2,
+            (
+                self.storage,
+                0,
+                tuple(self.shape),
+                tuple(stride),
+                False,
+                collections.OrderedDict(),
+            ),
+        )
+
+
+class Pickler(pickle.Pickler):
+    def persistent_id(self, obj):
+        if isinstance(obj, Storage):
+            return ("storage", FloatStorage, obj.key, "cpu", len(obj.values))
+        return None
+
+
+SHAPES = [
+    ("features.0.weight", [2, 1, 3, 3]),
+    ("features.0.bias", [2]),
+    ("classifier.weight", [10, 8]),
+    ("classifier.bias", [10]),
+]
+
+
+def state_dict():
+    tensors = collections.OrderedDict()
+    storages = []
+    for t, (name, shape) in enumerate(SHAPES):
+        count = 1
+        for dim in shape:
+            count *= dim
+        values = [((i + 5 * t) % 17 - 8) / 16 for i in range(count)]
+        storage = Storage(str(t), values)
+        storages.append(storage)
+        tensors[name] = Tensor(storage, shape)
+    return tensors, storages
+
+
+def save(obj, storages, path):
+    archive = os.path.splitext(os.path.basename(path))[0]
+    with open(path + ".pkl", "wb") as f:
+        Pickler(f, protocol=2).dump(obj)
+    with open(path + ".pkl", "rb") as f:
+        data = f.read()
+    os.remove(path + ".pkl")
+
+    def write(zf, name, contents):
+        info = zipfile.ZipInfo(f"{archive}/{name}", date_time=(1980, 1, 1, 0, 0, 0))
+        zf.writestr(info, contents, compress_type=zipfile.ZIP_STORED)
+
+    with zipfile.ZipFile(path, "w") as zf:
+        write(zf, "data.pkl", data)
+        for storage in storages:
+            write(zf, f"data/{storage.key}", struct.pack(f"<{len(storage.values)}f", *storage.values))
+        write(zf, "version", b"3\n")
+
+
+here = os.path.dirname(os.path.abspath(__file__))
+tensors, storages = state_dict()
+save(tensors, storages, os.path.join(here, "state_dict.pth"))
+save({"epoch": 3, "state_dict": tensors}, storages, os.path.join(here, "checkpoint.pth"))
@@ -0,0 +1,75 @@
+use std::fs;
+use train_an_ai_core::history::{self, TrainingHistory};
+
+/// Two epochs of two batches each.
+fn two_epochs() -> TrainingHistory {
+    let mut history = TrainingHistory::default();
+    for epoch in 1..=2 {
+        for batch in 0..2 {
+            history.record_batch(epoch, batch, 1.0 / (epoch * 10 + batch) as f32);
+        }
+        history.record_epoch(epoch, 0.5 / epoch as f32, 0.25 * epoch as f32);
+    }
+    history
+}
+
+#[test]
+fn saved_history_loads_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let model_path = dir.path().join("model.safetensors");
+    assert_eq!(TrainingHistory::load(&model_path).unwrap(), None);
+
+    let history = two_epochs();
+    history.save(&model_path).unwrap();
+    assert_eq!(TrainingHistory::load(&model_path).unwrap(), Some(history));
+
+    // Only the two history files are left, no temporary files
+    let mut files = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<String>>();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "model.safetensors.history.csv",
+            "model.safetensors.history.jsonl"
+        ]
+    );
+}
+
+#[test]
+fn records_interleave_batches_with_their_epoch() {
+    let jsonl = two_epochs().to_jsonl().unwrap();
+    let kinds = jsonl
+        .lines()
+        .map(|line| {
+            let record = serde_json::from_str::<serde_json::Value>(line).unwrap();
+            format!("{}{}", record["kind"].as_str().unwrap(), record["epoch"])
+        })
+        .collect::<Vec<String>>();
+    assert_eq!(
+        kinds,
+        ["batch1", "batch1", "epoch1", "batch2", "batch2", "epoch2"]
+    );
+}
+
+#[test]
+fn csv_leaves_columns_of_the_other_kind_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let model_path = dir.path().join("model.safetensors");
+    two_epochs().save(&model_path).unwrap();
+
+    let csv = fs::read_to_string(history::csv_path(&model_path)).unwrap();
+    let rows = csv
+        .lines()
+        .map(|line| line.split(',').collect::<Vec<&str>>())
+        .collect::<Vec<Vec<&str>>>();
+
+    assert_eq!(rows[0], ["kind", "epoch", "batch", "loss", "test_accuracy"]);
+    assert_eq!(rows.len(), 7);
+    assert!(rows.iter().all(|row| row.len() == 5));
+    assert_eq!(rows[1], ["batch", "1", "0", "0.1", ""]);
+    assert_eq!(rows[3], ["epoch", "1", "", "0.5", "0.25"]);
+    assert_eq!(rows[6], ["epoch", "2", "", "0.25", "0.5"]);
+}
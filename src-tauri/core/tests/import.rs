@@ -0,0 +1,228 @@
+mod common;
+
+use candle_core::Tensor;
+use candle_nn::VarMap;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::import::{self, natural_cmp};
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::ConvNet;
+
+/// A conv net whose first dense layer is square, 16 flattened features to 16 outputs, so its
+/// TensorFlow kernel has the same shape as candle's.
+const SQUARE_DENSE_ARCHITECTURE: &str = r#"{
+    "id": "square-dense",
+    "input_shape": [1, 28, 28],
+    "layers": [
+        { "type": "conv", "name": "c1", "out_channels": 4, "kernel": 3 },
+        { "type": "activation", "kind": "relu" },
+        { "type": "max_pool", "size": 13 },
+        { "type": "flatten" },
+        { "type": "linear", "name": "fc1", "out_features": 16 },
+        { "type": "activation", "kind": "relu" },
+        { "type": "linear", "name": "fc2" }
+    ]
+}"#;
+
+/// The layers of the PyTorch fixtures in tests/fixtures, named `features.0` and `classifier`
+/// there.
+const FIXTURE_ARCHITECTURE: &str = r#"{
+    "id": "fixture",
+    "input_shape": [1, 28, 28],
+    "layers": [
+        { "type": "conv", "name": "c1", "out_channels": 2, "kernel": 3 },
+        { "type": "activation", "kind": "relu" },
+        { "type": "max_pool", "size": 13 },
+        { "type": "flatten" },
+        { "type": "linear", "name": "fc1" }
+    ]
+}"#;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// The values tests/fixtures/make_pth.py gives the fixture's `t`th tensor.
+fn fixture_values(t: usize, count: usize) -> Vec<f32> {
+    (0..count)
+        .map(|i| ((i + 5 * t) % 17) as f32 / 16.0 - 0.5)
+        .collect()
+}
+
+fn weight(vm: &VarMap, name: &str) -> Tensor {
+    vm.data().lock().unwrap()[name].as_tensor().clone()
+}
+
+/// Imports the npz at `path` into a fresh model of `spec` and checks it computes the same
+/// logits as `original`.
+fn assert_imports_as(original: &ConvNet, spec: &ArchitectureSpec, path: &Path) -> Vec<String> {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, spec, &LabelSet::digits()).unwrap();
+    let imported = import::load_into(&model, &vm, path).unwrap();
+
+    let input = common::seeded_images(4, 0);
+    let expected = original.logits(&input).unwrap();
+    let actual = model.logits(&input).unwrap();
+    common::assert_close(&expected, &actual, 1e-5);
+
+    imported
+        .into_iter()
+        .map(|t| format!("{} -> {}: {:?}", t.source, t.target, t.transform))
+        .collect()
+}
+
+#[test]
+fn imports_pytorch_layout_by_name() {
+    let spec = ArchitectureSpec::default();
+    let mut vm = VarMap::new();
+    let original = ConvNet::new(&mut vm, &spec, &LabelSet::digits()).unwrap();
+
+    // As saved from a model wrapped in nn.DataParallel
+    let tensors = vm
+        .data()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, var)| (format!("module.{}", name), var.as_tensor().clone()))
+        .collect::<Vec<(String, Tensor)>>();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state_dict.npz");
+    Tensor::write_npz(&tensors, &path).unwrap();
+
+    let imported = assert_imports_as(&original, &spec, &path);
+    assert_eq!(imported.len(), tensors.len());
+    assert!(imported.iter().all(|t| t.ends_with(": None")), "{:?}", imported);
+}
+
+#[test]
+fn imports_tensorflow_layout_with_square_dense_kernel() {
+    let spec = ArchitectureSpec::from_json(SQUARE_DENSE_ARCHITECTURE).unwrap();
+    let mut vm = VarMap::new();
+    let original = ConvNet::new(&mut vm, &spec, &LabelSet::digits()).unwrap();
+
+    // Keras layouts: HWIO conv kernels, [in, out] dense kernels, and the first dense layer
+    // taking its inputs flattened (height, width, channels)
+    let conv = weight(&vm, "c1.weight").permute((2, 3, 1, 0)).unwrap();
+    let dense = weight(&vm, "fc1.weight")
+        .reshape((16, 4, 2, 2))
+        .unwrap()
+        .permute((0, 2, 3, 1))
+        .unwrap()
+        .reshape((16, 16))
+        .unwrap()
+        .t()
+        .unwrap();
+    let dense_1 = weight(&vm, "fc2.weight").t().unwrap();
+    let tensors = vec![
+        ("conv2d.weight", conv.contiguous().unwrap()),
+        ("conv2d.bias", weight(&vm, "c1.bias")),
+        ("dense.weight", dense.contiguous().unwrap()),
+        ("dense.bias", weight(&vm, "fc1.bias")),
+        ("dense_1.weight", dense_1.contiguous().unwrap()),
+        ("dense_1.bias", weight(&vm, "fc2.bias")),
+    ];
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("keras.npz");
+    Tensor::write_npz(&tensors, &path).unwrap();
+
+    let imported = assert_imports_as(&original, &spec, &path);
+    assert!(imported.contains(
+        &"dense.weight -> fc1.weight: Some(\"transposed [in, out] kernel to [out, in], \
+          reordered channels-last flattened inputs\")"
+            .to_string()
+    ));
+    assert!(imported.contains(
+        &"conv2d.weight -> c1.weight: Some(\"transposed HWIO kernel to OIHW\")".to_string()
+    ));
+}
+
+#[test]
+fn natural_cmp_orders_embedded_numbers_by_value() {
+    let mut names = vec!["conv10", "features.10", "conv2", "features.3", "conv", "conv2a"];
+    names.sort_by(|a, b| natural_cmp(a, b));
+    assert_eq!(
+        names,
+        ["conv", "conv2", "conv2a", "conv10", "features.3", "features.10"]
+    );
+
+    assert_eq!(natural_cmp("layer007", "layer7"), Ordering::Equal);
+    assert_eq!(natural_cmp("b1", "a2"), Ordering::Greater);
+}
+
+#[test]
+fn reads_pytorch_state_dicts_bare_or_nested_in_a_checkpoint() {
+    let read = |name: &str| {
+        let mut tensors = import::read_state_dict(&fixture(name))
+            .unwrap()
+            .into_iter()
+            .map(|(name, tensor)| {
+                let values = tensor.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+                (name, tensor.dims().to_vec(), values)
+            })
+            .collect::<Vec<_>>();
+        tensors.sort_by(|a, b| a.0.cmp(&b.0));
+        tensors
+    };
+
+    let bare = read("state_dict.pth");
+    let shapes = bare
+        .iter()
+        .map(|(name, dims, _)| (name.as_str(), dims.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        shapes,
+        [
+            ("classifier.bias", vec![10]),
+            ("classifier.weight", vec![10, 8]),
+            ("features.0.bias", vec![2]),
+            ("features.0.weight", vec![2, 1, 3, 3]),
+        ]
+    );
+    assert_eq!(bare[3].2, fixture_values(0, 18));
+    assert_eq!(bare[1].2, fixture_values(2, 80));
+
+    // The checkpoint holds the same state dict under "state_dict", next to an epoch count
+    assert_eq!(read("checkpoint.pth"), bare);
+}
+
+#[test]
+fn imports_pytorch_names_by_layer_order() {
+    let spec = ArchitectureSpec::from_json(FIXTURE_ARCHITECTURE).unwrap();
+
+    for name in ["state_dict.pth", "checkpoint.pth"] {
+        let mut vm = VarMap::new();
+        let model = ConvNet::new(&mut vm, &spec, &LabelSet::digits()).unwrap();
+        let mut imported = import::load_into(&model, &vm, &fixture(name))
+            .unwrap()
+            .into_iter()
+            .map(|t| format!("{} -> {}: {:?}", t.source, t.target, t.transform))
+            .collect::<Vec<String>>();
+        imported.sort();
+        assert_eq!(
+            imported,
+            [
+                "classifier.bias -> fc1.bias: None",
+                "classifier.weight -> fc1.weight: None",
+                "features.0.bias -> c1.bias: None",
+                "features.0.weight -> c1.weight: None",
+            ]
+        );
+
+        for (target, t, count) in [
+            ("c1.weight", 0, 18),
+            ("c1.bias", 1, 2),
+            ("fc1.weight", 2, 80),
+            ("fc1.bias", 3, 10),
+        ] {
+            let values = weight(&vm, target)
+                .flatten_all()
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap();
+            assert_eq!(values, fixture_values(t, count), "{} from {}", target, name);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use train_an_ai_core::labels::LabelSet;
+
+fn label_set(labels: &[&str]) -> candle_core::Result<LabelSet> {
+    LabelSet::new(labels.iter().map(|label| label.to_string()).collect())
+}
+
+#[test]
+fn labels_keep_their_order() {
+    let labels = label_set(&["cat", "dog", "7"]).unwrap();
+
+    assert_eq!(labels.len(), 3);
+    assert_eq!(labels.index_of("dog"), Some(1));
+    assert_eq!(labels.index_of("bird"), None);
+    assert_eq!(labels.name(2), Some("7"));
+    assert_eq!(labels.name(3), None);
+}
+
+#[test]
+fn invalid_or_duplicate_labels_are_rejected() {
+    assert!(label_set(&[]).is_err());
+
+    for invalid in ["", "  ", "a/b", "a.b", "what?", "C:"] {
+        let error = label_set(&["cat", invalid]).unwrap_err().to_string();
+        assert!(error.contains("Invalid label"), "{:?}: {}", invalid, error);
+    }
+
+    let error = label_set(&["cat", "dog", "cat"]).unwrap_err().to_string();
+    assert!(error.contains("Duplicate label 'cat'"), "{}", error);
+}
+
+#[test]
+fn label_files_are_validated_as_they_are_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("labels.json");
+
+    fs::write(&path, r#"["cat", "dog"]"#).unwrap();
+    assert_eq!(
+        LabelSet::from_json_file(&path).unwrap(),
+        label_set(&["cat", "dog"]).unwrap()
+    );
+
+    fs::write(&path, r#"["cat", "cat"]"#).unwrap();
+    assert!(LabelSet::from_json_file(&path).is_err());
+}
+
+#[test]
+fn mnist_digits_need_every_digit_label() {
+    let mut digits_and_symbols = (0..10).map(|d| d.to_string()).collect::<Vec<String>>();
+    digits_and_symbols.insert(0, "plus".to_string());
+    let labels = LabelSet::new(digits_and_symbols).unwrap();
+    assert_eq!(
+        labels.digit_indices().unwrap(),
+        [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]
+    );
+
+    assert!(label_set(&["0", "1", "cat"])
+        .unwrap()
+        .digit_indices()
+        .is_err());
+}
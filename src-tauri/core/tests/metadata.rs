@@ -0,0 +1,78 @@
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::metadata::ModelMetadata;
+
+fn current() -> ModelMetadata {
+    ModelMetadata::current(&ArchitectureSpec::default(), &LabelSet::digits(), None)
+}
+
+/// The error `validate` gives for `metadata`, which must be rejected.
+fn rejection(metadata: ModelMetadata) -> String {
+    metadata.validate().unwrap_err().to_string()
+}
+
+#[test]
+fn current_metadata_validates() {
+    let (spec, labels) = current().validate().unwrap();
+    assert_eq!(spec, ArchitectureSpec::default());
+    assert_eq!(labels, LabelSet::digits());
+
+    // Models saved before the spec was stored are the default architecture
+    let mut legacy = current();
+    legacy.architecture_spec = None;
+    assert_eq!(legacy.validate().unwrap().0, ArchitectureSpec::default());
+}
+
+#[test]
+fn architecture_not_matching_the_spec_is_rejected() {
+    let mut metadata = current();
+    metadata.architecture = "another-architecture".to_string();
+    let error = rejection(metadata);
+    assert!(
+        error.contains("names architecture 'another-architecture'"),
+        "{}",
+        error
+    );
+
+    let mut undescribed = current();
+    undescribed.architecture = "another-architecture".to_string();
+    undescribed.architecture_spec = None;
+    let error = rejection(undescribed);
+    assert!(error.contains("doesn't describe it"), "{}", error);
+}
+
+#[test]
+fn input_shape_not_matching_the_spec_is_rejected() {
+    let mut metadata = current();
+    metadata.input_shape = vec![3, 32, 32];
+    let error = rejection(metadata);
+    assert!(error.contains("input shape [3, 32, 32]"), "{}", error);
+}
+
+#[test]
+fn architecture_that_cant_be_built_is_rejected() {
+    // The first 2x2 conv doesn't fit a single pixel
+    let spec = ArchitectureSpec {
+        input_shape: [1, 1, 1],
+        ..ArchitectureSpec::default()
+    };
+    let metadata = ModelMetadata::current(&spec, &LabelSet::digits(), None);
+    let error = rejection(metadata);
+    assert!(error.contains("doesn't fit its 10 labels"), "{}", error);
+}
+
+#[test]
+fn invalid_labels_are_rejected() {
+    let mut duplicate = current();
+    duplicate.labels[1] = "0".to_string();
+    let error = rejection(duplicate);
+    assert!(error.contains("Duplicate label '0'"), "{}", error);
+}
+
+#[test]
+fn other_normalization_is_rejected() {
+    let mut metadata = current();
+    metadata.normalization = "luma8 / 127.5 - 1".to_string();
+    let error = rejection(metadata);
+    assert!(error.contains("normalization"), "{}", error);
+}
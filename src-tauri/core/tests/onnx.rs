@@ -0,0 +1,199 @@
+//! Checks the structure of exported ONNX graphs by decoding the protobuf directly, so it runs
+//! without `protoc`. The `onnx-roundtrip` feature's tests evaluate the graphs as well.
+
+mod common;
+
+use candle_nn::VarMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::onnx;
+
+/// A decoded field value: varints as integers, length-delimited fields as raw bytes.
+enum Value<'a> {
+    Int(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Every field of the message in `buf`, as `(field number, value)` in wire order.
+fn fields(buf: &[u8]) -> Vec<(u32, Value<'_>)> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let key = read_varint(buf, &mut pos);
+        let value = match key & 7 {
+            0 => Value::Int(read_varint(buf, &mut pos)),
+            1 => {
+                pos += 8;
+                Value::Fixed
+            }
+            2 => {
+                let len = read_varint(buf, &mut pos) as usize;
+                pos += len;
+                Value::Bytes(&buf[pos - len..pos])
+            }
+            5 => {
+                pos += 4;
+                Value::Fixed
+            }
+            wire_type => panic!("unexpected wire type {}", wire_type),
+        };
+        fields.push(((key >> 3) as u32, value));
+    }
+    assert_eq!(pos, buf.len(), "message overruns its buffer");
+    fields
+}
+
+fn messages(buf: &[u8], field: u32) -> Vec<&[u8]> {
+    fields(buf)
+        .into_iter()
+        .filter_map(|(number, value)| match value {
+            Value::Bytes(bytes) if number == field => Some(bytes),
+            _ => None,
+        })
+        .collect()
+}
+
+fn strings(buf: &[u8], field: u32) -> Vec<String> {
+    messages(buf, field)
+        .into_iter()
+        .map(|bytes| String::from_utf8(bytes.to_vec()).unwrap())
+        .collect()
+}
+
+fn ints(buf: &[u8], field: u32) -> Vec<u64> {
+    fields(buf)
+        .into_iter()
+        .filter_map(|(number, value)| match value {
+            Value::Int(int) if number == field => Some(int),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Exports a seeded model of `spec` and checks the graph is wired up like `ConvNet`: the ops
+/// follow the layers, every node reads tensors defined before it, every initializer holds a
+/// variable with its shape and values, and the last node produces the logits.
+fn assert_exports(spec: &ArchitectureSpec, expected_ops: &[&str]) {
+    let mut vm = VarMap::new();
+    let model = common::seeded_model(&mut vm, spec, &LabelSet::digits());
+    let model_proto = onnx::to_onnx(&model, &vm).unwrap();
+
+    let graph = messages(&model_proto, 7)[0];
+    let inputs = messages(graph, 11)
+        .into_iter()
+        .flat_map(|input| strings(input, 1))
+        .collect::<Vec<String>>();
+    let outputs = messages(graph, 12)
+        .into_iter()
+        .flat_map(|output| strings(output, 1))
+        .collect::<Vec<String>>();
+    assert_eq!(inputs, [onnx::INPUT_NAME]);
+    assert_eq!(outputs, [onnx::OUTPUT_NAME]);
+
+    let weights = common::weights(&vm);
+    let mut defined = HashSet::from([onnx::INPUT_NAME.to_string()]);
+    let mut initializers = HashMap::new();
+    for initializer in messages(graph, 5) {
+        let name = strings(initializer, 8).remove(0);
+        let dims = ints(initializer, 1);
+        let raw = messages(initializer, 9)[0];
+        let values = raw
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<f32>>();
+        initializers.insert(name.clone(), (dims, values));
+        defined.insert(name);
+    }
+    // Besides the variables, only the constants of spelled out activations are stored
+    let mut names = initializers
+        .keys()
+        .filter(|name| !name.starts_with("gelu."))
+        .collect::<Vec<&String>>();
+    names.sort();
+    let mut expected_names = weights.keys().collect::<Vec<&String>>();
+    expected_names.sort();
+    assert_eq!(names, expected_names);
+
+    let vars = vm.data().lock().unwrap();
+    for name in names {
+        let (dims, values) = &initializers[name];
+        let var_dims = vars[name]
+            .dims()
+            .iter()
+            .map(|&dim| dim as u64)
+            .collect::<Vec<u64>>();
+        assert_eq!(dims, &var_dims, "shape of '{}'", name);
+        assert_eq!(
+            common::bits(values),
+            common::bits(&weights[name]),
+            "values of '{}'",
+            name
+        );
+    }
+
+    let mut ops = Vec::new();
+    let mut last_output = String::new();
+    for node in messages(graph, 1) {
+        for input in strings(node, 1) {
+            assert!(
+                defined.contains(&input),
+                "'{}' is read before it's written",
+                input
+            );
+        }
+        last_output = strings(node, 2).remove(0);
+        defined.insert(last_output.clone());
+        ops.push(strings(node, 4).remove(0));
+    }
+    assert_eq!(ops, expected_ops);
+    assert_eq!(last_output, onnx::OUTPUT_NAME);
+}
+
+#[test]
+fn default_architecture_exports_its_layers() {
+    assert_exports(
+        &ArchitectureSpec::default(),
+        &[
+            "Conv", "Relu", "MaxPool", "Conv", "Relu", "MaxPool", "Conv", "Relu", "Flatten",
+            "Gemm", "Relu", "Gemm", "Identity",
+        ],
+    );
+}
+
+#[test]
+fn batch_norm_architecture_exports_its_layers() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("assets/architectures/convnet-batchnorm-silu.json");
+    let silu = ["Sigmoid", "Mul"];
+    let gelu = [
+        "Mul", "Mul", "Mul", "Add", "Mul", "Tanh", "Add", "Mul", "Mul",
+    ];
+    let ops = [
+        &["Conv", "BatchNormalization"][..],
+        &silu,
+        &["MaxPool", "Conv", "BatchNormalization"],
+        &silu,
+        &["MaxPool", "Flatten", "Gemm", "BatchNormalization"],
+        &gelu,
+        &["Gemm", "Identity"],
+    ]
+    .concat();
+    assert_exports(&ArchitectureSpec::from_json_file(&path).unwrap(), &ops);
+}
@@ -0,0 +1,50 @@
+//! Exports models to ONNX, evaluates the exported graph with candle-onnx, an ONNX runtime
+//! independent of the exporter, and checks it computes the same logits as `ConvNet`.
+
+mod common;
+
+use candle_core::{Device, Tensor};
+use candle_nn::VarMap;
+use std::collections::HashMap;
+use std::path::Path;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::onnx;
+
+/// Builds a model with seeded weights (batch norm statistics included) and checks its ONNX
+/// export against it on random inputs.
+fn assert_roundtrip(spec: &ArchitectureSpec) {
+    let mut vm = VarMap::new();
+    let model = common::seeded_model(&mut vm, spec, &LabelSet::digits());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.onnx");
+    onnx::export(&model, &vm, &path).unwrap();
+
+    let proto = candle_onnx::read_file(&path).unwrap();
+    let graph = proto.graph.as_ref().unwrap();
+    assert_eq!(graph.input.len(), 1);
+    assert_eq!(graph.output.len(), 1);
+
+    let [channels, height, width] = spec.input_shape;
+    let input = Tensor::rand(0f32, 1f32, (4, channels, height, width), &Device::Cpu).unwrap();
+
+    let expected = model.logits(&input).unwrap();
+    let inputs = HashMap::from([(graph.input[0].name.clone(), input)]);
+    let mut outputs = candle_onnx::simple_eval(&proto, inputs).unwrap();
+    let actual = outputs.remove(&graph.output[0].name).unwrap();
+
+    common::assert_close(&expected, &actual, 1e-4);
+}
+
+#[test]
+fn default_architecture_roundtrips() {
+    assert_roundtrip(&ArchitectureSpec::default());
+}
+
+#[test]
+fn batch_norm_architecture_roundtrips() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("assets/architectures/convnet-batchnorm-silu.json");
+    assert_roundtrip(&ArchitectureSpec::from_json_file(&path).unwrap());
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use candle_core::{Device, Tensor};
+use candle_nn::VarMap;
+use std::path::PathBuf;
+use train_an_ai_core::architecture::{ArchitectureSpec, LayerSpec};
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::ConvNet;
+use train_an_ai_core::prune;
+
+/// A conv with batch norm feeding a hidden linear layer, so pruning has to narrow the batch
+/// norm and the flattened inputs of `fc1` along with `c1`.
+const ARCHITECTURE: &str = r#"{
+    "id": "prunable",
+    "input_shape": [1, 28, 28],
+    "layers": [
+        { "type": "conv", "name": "c1", "out_channels": 8, "kernel": 3 },
+        { "type": "batch_norm", "name": "bn1" },
+        { "type": "activation", "kind": "relu" },
+        { "type": "max_pool", "size": 2 },
+        { "type": "flatten" },
+        { "type": "linear", "name": "fc1", "out_features": 16 },
+        { "type": "activation", "kind": "relu" },
+        { "type": "linear", "name": "fc2" }
+    ]
+}"#;
+
+/// A model of `ARCHITECTURE` with seeded weights.
+fn seeded_model(vm: &mut VarMap) -> ConvNet {
+    let spec = ArchitectureSpec::from_json(ARCHITECTURE).unwrap();
+    common::seeded_model(vm, &spec, &LabelSet::digits())
+}
+
+fn images() -> Tensor {
+    common::seeded_images(4, 1)
+}
+
+fn parameters(vm: &VarMap) -> usize {
+    vm.all_vars().iter().map(|var| var.elem_count()).sum()
+}
+
+#[test]
+fn pruning_nothing_keeps_the_logits() {
+    let mut vm = VarMap::new();
+    let model = seeded_model(&mut vm);
+
+    let (pruned, pruned_vm, layers) = prune::prune(&model, &vm, 0.0).unwrap();
+
+    assert!(layers.iter().all(|layer| layer.before == layer.after));
+    assert_eq!(parameters(&pruned_vm), parameters(&vm));
+
+    let expected = model.logits(&images()).unwrap();
+    let actual = pruned.logits(&images()).unwrap();
+    assert_eq!(
+        expected.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        actual.flatten_all().unwrap().to_vec1::<f32>().unwrap()
+    );
+}
+
+#[test]
+fn pruning_half_narrows_the_architecture() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut vm = VarMap::new();
+    let model = seeded_model(&mut vm);
+
+    let (pruned, pruned_vm, layers) = prune::prune(&model, &vm, 0.5).unwrap();
+
+    let counts = layers
+        .iter()
+        .map(|layer| (layer.name.as_str(), layer.before, layer.after))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, [("c1", 8, 4), ("fc1", 16, 8)]);
+    assert!(parameters(&pruned_vm) < parameters(&vm));
+
+    let widths = pruned
+        .spec()
+        .layers
+        .iter()
+        .filter_map(|layer| match layer {
+            LayerSpec::Conv { out_channels, .. } => Some(*out_channels),
+            LayerSpec::Linear { out_features, .. } => *out_features,
+            _ => None,
+        })
+        .collect::<Vec<usize>>();
+    assert_eq!(widths, [4, 8]);
+    assert_eq!(pruned.spec().id, "prunable-pruned");
+
+    // The narrowed spec saved with the weights must describe their shapes
+    let path = dir.path().join("pruned.safetensors");
+    pruned.save(&pruned_vm, &path, None).unwrap();
+    let mut loaded_vm = VarMap::new();
+    let loaded = ConvNet::new_from_file(&mut loaded_vm, &PathBuf::from(&path)).unwrap();
+
+    assert_eq!(parameters(&loaded_vm), parameters(&pruned_vm));
+    assert_eq!(loaded.logits(&images()).unwrap().dims(), &[4, 10]);
+}
+
+#[test]
+fn report_describes_the_saved_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+    let out = dir.path().join("pruned.safetensors");
+    let data = common::synthetic_dataset();
+    let mut vm = VarMap::new();
+    seeded_model(&mut vm).save(&vm, &path, None).unwrap();
+
+    let report = prune::prune_and_fine_tune(&path, &out, 0.5, 3, &data, &Device::Cpu).unwrap();
+
+    let mut saved_vm = VarMap::new();
+    let saved = ConvNet::new_from_file(&mut saved_vm, &out).unwrap();
+    assert_eq!(report.after.parameters, parameters(&saved_vm));
+    assert_eq!(
+        report.after.file_bytes,
+        std::fs::metadata(&out).unwrap().len()
+    );
+    assert_eq!(
+        report.after.test_accuracy,
+        saved.test(&Device::Cpu, &data, 20).unwrap()
+    );
+    // The best epoch's accuracy, in percent rather than as a fraction
+    assert!((report.after.test_accuracy - 100. * report.fine_tuning.test_accuracy).abs() < 1e-3);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use candle_core::{Device, Tensor};
+use candle_nn::VarMap;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::ConvNet;
+use train_an_ai_core::quantize::{Calibration, QuantizedConvNet};
+
+/// A small net covering every quantized layer: padded and strided convs whose patch sizes (9
+/// and 72) leave a remainder after the 16 wide SIMD loop, batch norm, pooling and two linears.
+const SMALL_ARCHITECTURE: &str = r#"{
+    "id": "small",
+    "input_shape": [1, 28, 28],
+    "layers": [
+        { "type": "conv", "name": "c1", "out_channels": 8, "kernel": 3, "padding": 1 },
+        { "type": "batch_norm", "name": "bn1" },
+        { "type": "activation", "kind": "relu" },
+        { "type": "max_pool", "size": 2 },
+        { "type": "conv", "name": "c2", "out_channels": 8, "kernel": 3, "stride": 2, "padding": 1 },
+        { "type": "activation", "kind": "relu" },
+        { "type": "flatten" },
+        { "type": "linear", "name": "fc1", "out_features": 32 },
+        { "type": "activation", "kind": "relu" },
+        { "type": "linear", "name": "fc2" }
+    ]
+}"#;
+
+/// A model of `SMALL_ARCHITECTURE` with seeded weights.
+fn seeded_model(vm: &mut VarMap) -> ConvNet {
+    let spec = ArchitectureSpec::from_json(SMALL_ARCHITECTURE).unwrap();
+    common::seeded_model(vm, &spec, &LabelSet::digits())
+}
+
+fn calibrated_model(model: &ConvNet, vm: &VarMap) -> QuantizedConvNet {
+    let calibration =
+        Calibration::measure(model, vm, &common::seeded_images(64, 1), 16, &Device::Cpu).unwrap();
+    QuantizedConvNet::new(model, vm, &calibration).unwrap()
+}
+
+#[test]
+fn quantized_logits_match_f32_logits() {
+    let mut vm = VarMap::new();
+    let model = seeded_model(&mut vm);
+    let quantized = calibrated_model(&model, &vm);
+
+    let images = common::seeded_images(16, 2);
+    let expected = model.logits(&images).unwrap();
+    let actual = quantized.logits(&images).unwrap();
+    assert_eq!(actual.dims(), &[16, 10]);
+
+    // Within 2% of the largest logit
+    common::assert_close(&expected, &actual, 0.02 * common::max_abs(&expected));
+}
+
+#[test]
+fn vectorized_and_portable_kernels_are_bit_identical() {
+    let mut vm = VarMap::new();
+    let model = seeded_model(&mut vm);
+    let mut quantized = calibrated_model(&model, &vm);
+
+    let images = common::seeded_images(8, 3);
+    let vectorized = quantized.logits(&images).unwrap();
+    quantized.set_vectorized(false);
+    let portable = quantized.logits(&images).unwrap();
+
+    let bits = |t: &Tensor| {
+        t.flatten_all()
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap()
+            .into_iter()
+            .map(f32::to_bits)
+            .collect::<Vec<u32>>()
+    };
+    assert_eq!(bits(&vectorized), bits(&portable));
+}
+
+#[test]
+fn stale_calibration_is_rejected() {
+    let mut vm = VarMap::new();
+    let model = seeded_model(&mut vm);
+    let calibration =
+        Calibration::measure(&model, &vm, &common::seeded_images(16, 1), 16, &Device::Cpu).unwrap();
+
+    // Changing any weight after calibrating invalidates the calibration
+    let bias = vm.data().lock().unwrap()["fc2.bias"].clone();
+    bias.set(&(bias.as_tensor() + 0.5).unwrap()).unwrap();
+
+    let error = QuantizedConvNet::new(&model, &vm, &calibration).unwrap_err();
+    assert!(
+        error.to_string().contains("different weights"),
+        "unexpected error: {}",
+        error
+    );
+
+    let calibration =
+        Calibration::measure(&model, &vm, &common::seeded_images(16, 1), 16, &Device::Cpu).unwrap();
+    assert!(QuantizedConvNet::new(&model, &vm, &calibration).is_ok());
+}
+
+#[test]
+fn weight_bytes_count_the_values_as_held() {
+    let mut vm = VarMap::new();
+    let model = seeded_model(&mut vm);
+    let quantized = calibrated_model(&model, &vm);
+
+    let count = |name: &str| vm.data().lock().unwrap()[name].elem_count();
+    // Conv and linear values as i16, with an f32 scale and bias per output
+    let quantized_layers = ["c1", "c2", "fc1", "fc2"]
+        .iter()
+        .map(|layer| count(&format!("{layer}.weight")) * 2 + count(&format!("{layer}.bias")) * 8)
+        .sum::<usize>();
+    // Batch norm folded into an f32 scale and shift per channel
+    let batch_norm = count("bn1.weight") * 8;
+
+    assert_eq!(quantized.weight_bytes(), quantized_layers + batch_norm);
+}
@@ -0,0 +1,264 @@
+mod common;
+
+use common::{save_constant_model, saved_value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::history;
+use train_an_ai_core::model::TrainingSummary;
+use train_an_ai_core::quantize;
+use train_an_ai_core::registry::{Hyperparameters, ModelRegistry, PRETRAINED_ID};
+
+/// A registry holding the pretrained model (all weights 0) and the working model path it was
+/// installed at.
+fn pretrained_registry(dir: &TempDir) -> (ModelRegistry, PathBuf) {
+    let bundled = dir.path().join("bundled.safetensors");
+    save_constant_model(&bundled, 0.0);
+    let working = dir.path().join("model.safetensors");
+    save_constant_model(&working, 0.0);
+
+    let mut registry = ModelRegistry::open(&dir.path().join("models")).unwrap();
+    registry.register_pretrained(&bundled).unwrap();
+    (registry, working)
+}
+
+/// Trains (saves over the working model) a model with weights `value` and registers it.
+fn register_trained(registry: &mut ModelRegistry, working: &Path, value: f32) -> String {
+    save_constant_model(working, value);
+    registry
+        .register_trained(
+            working,
+            Hyperparameters::default(),
+            10,
+            TrainingSummary::default(),
+        )
+        .unwrap()
+        .id
+}
+
+#[test]
+fn rollback_activates_the_parent_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let first = register_trained(&mut registry, &working, 1.0);
+    let second = register_trained(&mut registry, &working, 2.0);
+    assert_eq!(registry.get(&second).unwrap().parent, Some(first.clone()));
+
+    let restored = registry.rollback(&working).unwrap().unwrap();
+    assert_eq!(restored.id, first);
+    assert_eq!(registry.active().unwrap().id, first);
+    assert_eq!(saved_value(&working), 1.0);
+
+    // The next model trained descends from the one rolled back to
+    let third = register_trained(&mut registry, &working, 3.0);
+    assert_eq!(registry.get(&third).unwrap().parent, Some(first));
+
+    // The rollback is persisted
+    let reopened = ModelRegistry::open(&dir.path().join("models")).unwrap();
+    assert_eq!(reopened.active().unwrap().id, third);
+}
+
+#[test]
+fn rollback_stops_at_the_pretrained_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    register_trained(&mut registry, &working, 1.0);
+
+    registry.rollback(&working).unwrap();
+    assert_eq!(registry.active().unwrap().id, PRETRAINED_ID);
+    assert_eq!(saved_value(&working), 0.0);
+
+    assert!(registry.rollback(&working).is_err());
+    assert_eq!(registry.active().unwrap().id, PRETRAINED_ID);
+}
+
+#[test]
+fn rollback_without_registry_restores_the_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let working = dir.path().join("model.safetensors");
+    save_constant_model(&working, 1.0);
+    save_constant_model(&working, 2.0);
+
+    let mut registry = ModelRegistry::open(&dir.path().join("models")).unwrap();
+    assert!(registry.rollback(&working).unwrap().is_none());
+    assert_eq!(saved_value(&working), 1.0);
+}
+
+#[test]
+fn derived_model_keeps_the_active_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let trained = register_trained(&mut registry, &working, 1.0);
+
+    let student = dir.path().join("student.safetensors");
+    save_constant_model(&student, 2.0);
+    let derived = registry
+        .register_derived(
+            &student,
+            PRETRAINED_ID,
+            Hyperparameters::default(),
+            10,
+            TrainingSummary::default(),
+        )
+        .unwrap();
+
+    assert_eq!(derived.parent.as_deref(), Some(PRETRAINED_ID));
+    assert_eq!(registry.active().unwrap().id, trained);
+    assert_eq!(saved_value(&working), 1.0);
+    assert_eq!(saved_value(&registry.model_path(&derived.id)), 2.0);
+
+    let reopened = ModelRegistry::open(&dir.path().join("models")).unwrap();
+    assert_eq!(reopened.active().unwrap().id, trained);
+    assert!(reopened.get(&derived.id).is_ok());
+
+    // Only registered models can be parents
+    assert!(registry
+        .register_derived(
+            &student,
+            "missing",
+            Hyperparameters::default(),
+            10,
+            TrainingSummary::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn register_activate_rename_and_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    assert_eq!(registry.active().unwrap().id, PRETRAINED_ID);
+
+    let trained = register_trained(&mut registry, &working, 1.0);
+    let entry = registry.get(&trained).unwrap();
+    assert_eq!(entry.parent.as_deref(), Some(PRETRAINED_ID));
+    assert_eq!(entry.dataset_size, Some(10));
+    assert_eq!(registry.active().unwrap().id, trained);
+    assert_eq!(saved_value(&registry.model_path(&trained)), 1.0);
+
+    registry.activate(PRETRAINED_ID, &working).unwrap();
+    assert_eq!(registry.active().unwrap().id, PRETRAINED_ID);
+    assert_eq!(saved_value(&working), 0.0);
+
+    registry.rename(&trained, "Visitor drawings").unwrap();
+    assert_eq!(registry.get(&trained).unwrap().name, "Visitor drawings");
+    assert!(registry.rename("missing", "Name").is_err());
+
+    // Everything above is persisted in registry.json
+    let reopened = ModelRegistry::open(&dir.path().join("models")).unwrap();
+    assert_eq!(reopened.active().unwrap().id, PRETRAINED_ID);
+    assert_eq!(reopened.get(&trained).unwrap().name, "Visitor drawings");
+
+    registry.delete(&trained).unwrap();
+    assert!(registry.get(&trained).is_err());
+    assert!(!registry.model_path(&trained).exists());
+    assert_eq!(registry.models().len(), 1);
+}
+
+#[test]
+fn active_model_cannot_be_deleted() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let trained = register_trained(&mut registry, &working, 1.0);
+
+    assert!(registry.delete(&trained).is_err());
+    assert!(registry.get(&trained).is_ok());
+    assert!(registry.model_path(&trained).exists());
+
+    assert!(registry.delete("missing").is_err());
+}
+
+#[test]
+fn pretrained_and_parent_models_cannot_be_deleted() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let parent = register_trained(&mut registry, &working, 1.0);
+    let child = register_trained(&mut registry, &working, 2.0);
+
+    assert!(registry.delete(PRETRAINED_ID).is_err());
+    assert!(registry.delete(&parent).is_err());
+    assert!(registry.get(&parent).is_ok());
+    assert!(registry.model_path(&parent).exists());
+
+    // Once nothing descends from it any more, it can go
+    registry.activate(PRETRAINED_ID, &working).unwrap();
+    registry.delete(&child).unwrap();
+    registry.delete(&parent).unwrap();
+    assert_eq!(registry.models().len(), 1);
+}
+
+#[test]
+fn delete_removes_every_file_of_the_model() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let trained = register_trained(&mut registry, &working, 1.0);
+    registry.activate(PRETRAINED_ID, &working).unwrap();
+
+    let path = registry.model_path(&trained);
+    for sibling in [
+        history::jsonl_path(&path),
+        history::csv_path(&path),
+        quantize::calibration_path(&path),
+    ] {
+        fs::write(sibling, "{}").unwrap();
+    }
+
+    registry.delete(&trained).unwrap();
+
+    let mut files = fs::read_dir(dir.path().join("models"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<String>>();
+    files.sort();
+    assert_eq!(files, ["pretrained.safetensors", "registry.json"]);
+}
+
+#[test]
+fn default_names_are_not_reused_after_a_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let first = register_trained(&mut registry, &working, 1.0);
+    assert_eq!(registry.get(&first).unwrap().name, "Model 2");
+
+    registry.activate(PRETRAINED_ID, &working).unwrap();
+    registry.delete(&first).unwrap();
+
+    let mut registry = ModelRegistry::open(&dir.path().join("models")).unwrap();
+    let second = register_trained(&mut registry, &working, 2.0);
+    assert_eq!(registry.get(&second).unwrap().name, "Model 3");
+}
+
+#[test]
+fn diff_compares_weights_of_two_models() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut registry, working) = pretrained_registry(&dir);
+    let trained = register_trained(&mut registry, &working, 0.25);
+
+    let diff = registry.diff(PRETRAINED_ID, &trained).unwrap();
+    assert_eq!(diff.a.id, PRETRAINED_ID);
+    assert_eq!(diff.b.id, trained);
+
+    let mut names = diff
+        .tensors
+        .iter()
+        .map(|t| t.name.as_str())
+        .collect::<Vec<_>>();
+    names.sort();
+    let mut expected = ArchitectureSpec::default()
+        .layer_names()
+        .into_iter()
+        .flat_map(|layer| [format!("{}.bias", layer), format!("{}.weight", layer)])
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(names, expected);
+
+    for tensor in diff.tensors.iter() {
+        assert_eq!(tensor.shape_a, tensor.shape_b);
+        assert_eq!(tensor.mean_abs_diff, Some(0.25));
+        assert_eq!(tensor.max_abs_diff, Some(0.25));
+    }
+
+    let same = registry.diff(&trained, &trained).unwrap();
+    assert!(same.tensors.iter().all(|t| t.max_abs_diff == Some(0.0)));
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use candle_nn::VarMap;
+use std::collections::HashMap;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::ConvNet;
+
+/// Trains a fresh default model for two epochs and returns its weights.
+fn train_fresh_model(seed: u64) -> HashMap<String, Vec<f32>> {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits()).unwrap();
+
+    let args = common::training_args(2, seed);
+    model
+        .train(&common::synthetic_dataset(), &args, &mut vm)
+        .unwrap();
+
+    common::weights(&vm)
+}
+
+#[test]
+fn same_seed_gives_bit_identical_weights() {
+    let first = train_fresh_model(42);
+    let second = train_fresh_model(42);
+
+    assert!(!first.is_empty());
+    for (name, values) in first.iter() {
+        assert_eq!(
+            common::bits(values),
+            common::bits(&second[name]),
+            "'{}' differs between runs",
+            name
+        );
+    }
+}
+
+#[test]
+fn different_seeds_give_different_weights() {
+    assert_ne!(
+        train_fresh_model(1)["fc2.weight"],
+        train_fresh_model(2)["fc2.weight"]
+    );
+}
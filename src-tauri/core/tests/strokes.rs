@@ -0,0 +1,255 @@
+use image::GrayImage;
+use std::fs;
+use std::path::Path;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::strokes::{self, RasterConfig, Stroke, StrokeDrawing, StrokePoint};
+
+fn point(x: f32, y: f32, t: f64) -> StrokePoint {
+    StrokePoint {
+        x,
+        y,
+        t,
+        pressure: 0.5,
+    }
+}
+
+fn drawing(strokes: Vec<Vec<StrokePoint>>) -> StrokeDrawing {
+    StrokeDrawing {
+        canvas_width: 280.0,
+        canvas_height: 280.0,
+        strokes: strokes
+            .into_iter()
+            .map(|points| Stroke { points })
+            .collect(),
+    }
+}
+
+/// A stroke across the middle of the canvas, along the center of pixel row 14 once scaled
+/// to 28x28.
+fn horizontal_line() -> StrokeDrawing {
+    drawing(vec![vec![
+        point(40.0, 145.0, 0.0),
+        point(240.0, 145.0, 100.0),
+    ]])
+}
+
+fn config(line_width: f32, antialias: bool) -> RasterConfig {
+    RasterConfig {
+        size: 28,
+        line_width,
+        antialias,
+        blur: 0.0,
+    }
+}
+
+fn row(image: &GrayImage, y: u32) -> Vec<u8> {
+    (0..image.width())
+        .map(|x| image.get_pixel(x, y)[0])
+        .collect()
+}
+
+#[test]
+fn horizontal_stroke_lights_its_row() {
+    // One pixel wide
+    let image = horizontal_line().rasterize(&config(10.0, false)).unwrap();
+
+    assert_eq!(image.dimensions(), (28, 28));
+    let lit = row(&image, 14);
+    assert!(lit[5..=22].iter().all(|&p| p == 255), "row 14 is {:?}", lit);
+    assert!(lit[..3].iter().chain(&lit[26..]).all(|&p| p == 0));
+    for y in (0..28).filter(|&y| y != 14) {
+        assert!(row(&image, y).iter().all(|&p| p == 0), "row {} is lit", y);
+    }
+}
+
+#[test]
+fn antialiasing_shades_the_edges() {
+    // One and a half pixels wide, so the rows above and below are a quarter covered
+    let sharp = horizontal_line().rasterize(&config(15.0, false)).unwrap();
+    let smooth = horizontal_line().rasterize(&config(15.0, true)).unwrap();
+
+    assert_eq!(sharp.get_pixel(10, 14)[0], 255);
+    assert_eq!(smooth.get_pixel(10, 14)[0], 255);
+    for y in [13, 15] {
+        assert_eq!(sharp.get_pixel(10, y)[0], 0);
+        let edge = smooth.get_pixel(10, y)[0];
+        assert!(edge > 0 && edge < 255, "edge pixel is {}", edge);
+    }
+}
+
+#[test]
+fn invalid_drawings_are_rejected() {
+    assert!(horizontal_line().validate().is_ok());
+
+    let mut no_canvas = horizontal_line();
+    no_canvas.canvas_width = 0.0;
+    let off_canvas = drawing(vec![vec![point(300.0, 10.0, 0.0)]]);
+    let negative = drawing(vec![vec![point(-1.0, 10.0, 0.0)]]);
+    let no_time = drawing(vec![vec![point(10.0, 10.0, f64::NAN)]]);
+    let mut too_hard = drawing(vec![vec![point(10.0, 10.0, 0.0)]]);
+    too_hard.strokes[0].points[0].pressure = 1.5;
+
+    for invalid in [no_canvas, off_canvas, negative, no_time, too_hard] {
+        assert!(invalid.validate().is_err(), "{:?} passed", invalid);
+    }
+
+    // Files are validated as they're read
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("3.strokes.json");
+    drawing(vec![vec![point(300.0, 10.0, 0.0)]])
+        .save(&path)
+        .unwrap();
+    assert!(StrokeDrawing::from_json_file(&path).is_err());
+}
+
+#[test]
+fn up_to_keeps_the_points_drawn_so_far() {
+    let two_strokes = drawing(vec![
+        vec![point(10.0, 10.0, 0.0), point(20.0, 10.0, 50.0)],
+        vec![point(10.0, 20.0, 100.0), point(20.0, 20.0, 150.0)],
+    ]);
+    assert_eq!(two_strokes.duration(), 150.0);
+
+    let partial = two_strokes.up_to(75.0);
+    assert_eq!(partial.strokes, two_strokes.strokes[..1]);
+    assert_eq!(two_strokes.up_to(150.0), two_strokes);
+    assert_eq!(two_strokes.up_to(-1.0).strokes, []);
+}
+
+#[test]
+fn partial_drawings_come_from_drawings_with_strokes() {
+    let dir = tempfile::tempdir().unwrap();
+    let labels = LabelSet::digits();
+
+    let blank = GrayImage::new(28, 28);
+    blank.save(dir.path().join("5.png")).unwrap();
+    assert!(strokes::partial_drawings(dir.path(), &labels)
+        .unwrap()
+        .is_none());
+
+    // Two strokes, so both halfway and three quarters through differ from the whole drawing
+    let seven = drawing(vec![
+        vec![point(40.0, 40.0, 0.0), point(240.0, 40.0, 100.0)],
+        vec![point(240.0, 40.0, 200.0), point(100.0, 260.0, 400.0)],
+    ]);
+    let path = dir.path().join("7.png");
+    seven
+        .rasterize(&RasterConfig::default())
+        .unwrap()
+        .save(&path)
+        .unwrap();
+    seven.save(&strokes::strokes_path(&path)).unwrap();
+
+    let (images, image_labels) = strokes::partial_drawings(dir.path(), &labels)
+        .unwrap()
+        .unwrap();
+    assert_eq!(images.dims(), &[2, 784]);
+    assert_eq!(image_labels.to_vec1::<f32>().unwrap(), [7.0, 7.0]);
+
+    // Halfway through, only the top bar is drawn
+    let halfway = images.get(0).unwrap().to_vec1::<f32>().unwrap();
+    let lit_rows = (0..28)
+        .filter(|y| halfway[y * 28..(y + 1) * 28].iter().any(|&p| p > 0.0))
+        .collect::<Vec<usize>>();
+    assert_eq!(lit_rows, [3, 4]);
+}
+
+/// A drawings store with a `3` saved with strokes and a blank `5` saved as an image only,
+/// both at the default config.
+fn store_with_and_without_strokes(dir: &Path) -> StrokeDrawing {
+    let three = horizontal_line();
+    let path = dir.join("3.png");
+    three
+        .rasterize(&RasterConfig::default())
+        .unwrap()
+        .save(&path)
+        .unwrap();
+    three.save(&strokes::strokes_path(&path)).unwrap();
+
+    GrayImage::new(28, 28).save(dir.join("5.png")).unwrap();
+    three
+}
+
+#[test]
+fn rerender_renders_drawings_with_strokes_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let three = store_with_and_without_strokes(dir.path());
+    let config = RasterConfig {
+        size: 56,
+        line_width: 20.0,
+        antialias: false,
+        blur: 0.5,
+    };
+
+    let report = strokes::rerender_drawings(dir.path(), &config, &LabelSet::digits()).unwrap();
+
+    assert_eq!(report.config, config);
+    assert_eq!(report.rendered, ["3.png"]);
+    assert_eq!(report.without_strokes, ["5.png"]);
+    assert_eq!(report.dataset_size, 2);
+
+    let rendered = image::open(dir.path().join("3.png")).unwrap().to_luma8();
+    assert_eq!(rendered, three.rasterize(&config).unwrap());
+    let untouched = image::open(dir.path().join("5.png")).unwrap().to_luma8();
+    assert_eq!(untouched.dimensions(), (28, 28));
+
+    // The config is stored with the drawings and used for the ones saved from then on
+    assert_eq!(RasterConfig::load(dir.path()).unwrap(), config);
+    let next = three.rasterize_for_store(dir.path(), None).unwrap();
+    assert_eq!(next, three.rasterize(&config).unwrap());
+    let explicit = three
+        .rasterize_for_store(dir.path(), Some(RasterConfig::default()))
+        .unwrap();
+    assert_eq!(explicit.dimensions(), (28, 28));
+}
+
+#[test]
+fn rerender_with_invalid_strokes_leaves_the_store_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    store_with_and_without_strokes(dir.path());
+    let before = fs::read(dir.path().join("3.png")).unwrap();
+
+    GrayImage::new(28, 28)
+        .save(dir.path().join("7.png"))
+        .unwrap();
+    drawing(vec![vec![point(300.0, 10.0, 0.0)]])
+        .save(&dir.path().join("7.strokes.json"))
+        .unwrap();
+    let config = RasterConfig {
+        size: 56,
+        ..RasterConfig::default()
+    };
+
+    assert!(strokes::rerender_drawings(dir.path(), &config, &LabelSet::digits()).is_err());
+    assert_eq!(fs::read(dir.path().join("3.png")).unwrap(), before);
+    assert_eq!(
+        RasterConfig::load(dir.path()).unwrap(),
+        RasterConfig::default()
+    );
+}
+
+#[test]
+fn rerender_that_fails_to_write_leaves_the_store_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    let three = store_with_and_without_strokes(dir.path());
+    let four = dir.path().join("4.png");
+    fs::copy(dir.path().join("3.png"), &four).unwrap();
+    three.save(&strokes::strokes_path(&four)).unwrap();
+    let before = fs::read(dir.path().join("3.png")).unwrap();
+
+    // A directory in the way of the second drawing's temporary file
+    fs::create_dir(dir.path().join("4.png.tmp")).unwrap();
+    let config = RasterConfig {
+        size: 56,
+        ..RasterConfig::default()
+    };
+
+    assert!(strokes::rerender_drawings(dir.path(), &config, &LabelSet::digits()).is_err());
+    assert_eq!(fs::read(dir.path().join("3.png")).unwrap(), before);
+    assert_eq!(fs::read(&four).unwrap(), before);
+    assert!(!dir.path().join("3.png.tmp").exists());
+    assert_eq!(
+        RasterConfig::load(dir.path()).unwrap(),
+        RasterConfig::default()
+    );
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+use train_an_ai_core::tensorboard::{crc32c, masked_crc32c, EventWriter};
+
+/// Splits a TFRecord file into its records' data, checking the framing of each.
+fn read_records(path: &Path) -> Vec<Vec<u8>> {
+    let bytes = fs::read(path).unwrap();
+    let u32_at = |pos: usize| u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let len_bytes = &bytes[pos..pos + 8];
+        let len = u64::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+        assert_eq!(u32_at(pos + 8), masked_crc32c(len_bytes), "length checksum");
+
+        let data = &bytes[pos + 12..pos + 12 + len];
+        assert_eq!(u32_at(pos + 12 + len), masked_crc32c(data), "data checksum");
+
+        records.push(data.to_vec());
+        pos += 12 + len + 4;
+    }
+    assert_eq!(pos, bytes.len());
+    records
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[test]
+fn crc32c_matches_the_check_value() {
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    assert_eq!(crc32c(b""), 0);
+}
+
+#[test]
+fn masked_crc32c_rotates_and_offsets() {
+    // 0xE3069283 rotated right by 15 bits, plus 0xA282EAD8
+    assert_eq!(masked_crc32c(b"123456789"), 0xC78A_B0E5);
+}
+
+#[test]
+fn scalar_events_are_framed_as_tfrecords() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut writer = EventWriter::create(dir.path()).unwrap();
+    writer.add_scalar("loss/train", 0.25, 7).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+
+    let files = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 1);
+    let name = files[0].file_name().unwrap().to_string_lossy().to_string();
+    assert!(name.starts_with("events.out.tfevents."), "{}", name);
+
+    let records = read_records(&files[0]);
+    assert_eq!(records.len(), 2);
+    assert!(contains(&records[0], b"brain.Event:2"));
+
+    // Event.step (field 2, varint) and the summary value's tag and float (field 2, fixed32)
+    let scalar = &records[1];
+    assert!(contains(scalar, &[0x10, 7]));
+    assert!(contains(scalar, b"loss/train"));
+    let mut value = vec![0x15];
+    value.extend_from_slice(&0.25f32.to_le_bytes());
+    assert!(contains(scalar, &value));
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use candle_nn::VarMap;
+use std::path::Path;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::ConvNet;
+
+/// Saves a default model with seeded weights to `path`, for loading as a non-fresh model.
+fn save_initialized_model(path: &Path) {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits()).unwrap();
+    let args = common::training_args(0, 0);
+    model
+        .train(&common::synthetic_dataset(), &args, &mut vm)
+        .unwrap();
+    model.save(&vm, path, None).unwrap();
+}
+
+#[test]
+fn frozen_layers_stay_bit_identical() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+    save_initialized_model(&path);
+
+    let mut vm = VarMap::new();
+    let model = ConvNet::new_from_file(&mut vm, &path).unwrap();
+    let before = common::weights(&vm);
+
+    let mut args = common::training_args(1, 0);
+    args.frozen_layers = ["c1", "c2", "c3", "fc1"].map(String::from).to_vec();
+    model
+        .train(&common::synthetic_dataset(), &args, &mut vm)
+        .unwrap();
+    let after = common::weights(&vm);
+
+    for (name, values) in before.iter() {
+        let changed = common::bits(values) != common::bits(&after[name]);
+        assert_eq!(
+            changed,
+            name.starts_with("fc2."),
+            "'{}' changed: {}",
+            name,
+            changed
+        );
+    }
+}
+
+#[test]
+fn unknown_or_all_frozen_layers_are_rejected() {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits()).unwrap();
+
+    let mut args = common::training_args(1, 0);
+    args.frozen_layers = vec!["c9".to_string()];
+    assert!(model
+        .train(&common::synthetic_dataset(), &args, &mut vm)
+        .is_err());
+
+    args.frozen_layers = ["c1", "c2", "c3", "fc1", "fc2"].map(String::from).to_vec();
+    assert!(model
+        .train(&common::synthetic_dataset(), &args, &mut vm)
+        .is_err());
+}
+
+#[test]
+fn patience_stops_training_and_restores_the_best_epoch() {
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &ArchitectureSpec::default(), &LabelSet::digits()).unwrap();
+    let mut args = common::training_args(30, 0);
+    args.patience = Some(2);
+    let summary = model
+        .train(&common::synthetic_dataset(), &args, &mut vm)
+        .unwrap();
+
+    assert!(
+        summary.epochs_run < 30,
+        "ran all {} epochs",
+        summary.epochs_run
+    );
+    assert_eq!(summary.best_epoch, summary.epochs_run - 2);
+
+    // The kept weights are the ones a run stopping at the best epoch ends with
+    let mut best_vm = VarMap::new();
+    let best_model = ConvNet::new(
+        &mut best_vm,
+        &ArchitectureSpec::default(),
+        &LabelSet::digits(),
+    )
+    .unwrap();
+    args.epochs = summary.best_epoch;
+    args.patience = None;
+    let best_summary = best_model
+        .train(&common::synthetic_dataset(), &args, &mut best_vm)
+        .unwrap();
+    assert_eq!(best_summary.test_accuracy, summary.test_accuracy);
+
+    let best = common::weights(&best_vm);
+    for (name, values) in common::weights(&vm).iter() {
+        assert_eq!(
+            common::bits(values),
+            common::bits(&best[name]),
+            "'{}' isn't the best epoch's",
+            name
+        );
+    }
+}
+
+/// A conv with batch norm, small enough to train quickly.
+const BATCH_NORM_ARCHITECTURE: &str = r#"{
+    "id": "batch-norm",
+    "input_shape": [1, 28, 28],
+    "layers": [
+        { "type": "conv", "name": "c1", "out_channels": 4, "kernel": 3 },
+        { "type": "batch_norm", "name": "bn1" },
+        { "type": "activation", "kind": "leaky_relu" },
+        { "type": "max_pool", "size": 2 },
+        { "type": "flatten" },
+        { "type": "linear", "name": "fc1" }
+    ]
+}"#;
+
+#[test]
+fn batch_norm_statistics_are_trained_saved_and_used_for_eval() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.safetensors");
+    let spec = ArchitectureSpec::from_json(BATCH_NORM_ARCHITECTURE).unwrap();
+    let mut vm = VarMap::new();
+    let model = ConvNet::new(&mut vm, &spec, &LabelSet::digits()).unwrap();
+
+    // Running statistics start at zero mean and unit variance and follow the training batches
+    let data = common::synthetic_dataset();
+    model
+        .train(&data, &common::training_args(1, 0), &mut vm)
+        .unwrap();
+    let trained = common::weights(&vm);
+    assert!(trained["bn1.running_mean"].iter().all(|&mean| mean != 0.0));
+    assert!(trained["bn1.running_var"].iter().all(|&var| var != 1.0));
+
+    model.save(&vm, &path, None).unwrap();
+    let mut loaded_vm = VarMap::new();
+    let loaded = ConvNet::new_from_file(&mut loaded_vm, &path).unwrap();
+    let loaded_weights = common::weights(&loaded_vm);
+    for name in ["bn1.running_mean", "bn1.running_var"] {
+        assert_eq!(
+            common::bits(&loaded_weights[name]),
+            common::bits(&trained[name])
+        );
+    }
+
+    let logits = |model: &ConvNet| {
+        model
+            .logits(&data.test_images)
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap()
+    };
+    let expected = logits(&model);
+    assert_eq!(common::bits(&logits(&loaded)), common::bits(&expected));
+
+    // Evaluation normalizes with the running statistics rather than the batch's own
+    let running_mean = loaded_vm.data().lock().unwrap()["bn1.running_mean"].clone();
+    running_mean
+        .set(&running_mean.as_tensor().zeros_like().unwrap())
+        .unwrap();
+    assert_ne!(logits(&loaded), expected);
+}
@@ -1,2 +0,0 @@
-pub mod model;
-pub mod utils;
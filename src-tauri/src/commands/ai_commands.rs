@@ -1,15 +1,38 @@
-use crate::ai;
-use crate::ai::model::{ConvNet, TrainingArgs};
+use crate::commands::image_commands::rasterize_strokes;
+use crate::commands::model_commands::open_registry;
 use base64;
 use candle_core;
 use candle_core::Device;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::api::path::data_dir;
 use tauri::{command, AppHandle, InvokeError, Manager};
+use train_an_ai_core::labels::LabelSet;
+use train_an_ai_core::model::{ConvNet, TrainingArgs};
+use train_an_ai_core::quantize::{
+    self, Calibration, QuantizationReport, QuantizedConvNet, DEFAULT_CALIBRATION_SAMPLES,
+};
+use train_an_ai_core::registry::{Hyperparameters, ModelEntry};
+use train_an_ai_core::strokes::{RasterConfig, StrokeDrawing};
+use train_an_ai_core::utils::{self, DatasetMode};
+use train_an_ai_core::{metadata, onnx};
 
+/// Fine-tunes the model on the user's drawings. When `mnist_ratio` is set, that share of every
+/// batch is made up of replayed MNIST training samples. Layers named in `frozen_layers`
+/// (e.g. `["c1", "c2", "c3"]`) keep their pretrained weights. With `patience` set, training
+/// stops once test accuracy stalls; the best epoch's weights are the ones saved. Passing a
+/// `seed` makes the run reproducible. Unfinished versions of the drawings saved with strokes
+/// are trained on too unless `partial_drawings` is `false`.
 #[command]
-pub fn train(app_handle: AppHandle) -> Result<(), String> {
+pub fn train(
+    app_handle: AppHandle,
+    mnist_ratio: Option<f32>,
+    partial_drawings: Option<bool>,
+    frozen_layers: Option<Vec<String>>,
+    epochs: Option<usize>,
+    patience: Option<usize>,
+    seed: Option<u64>,
+) -> Result<(), String> {
     let app_handle_clone = app_handle.clone();
     tauri::async_runtime::spawn_blocking(move || {
         // Create the varmap
@@ -60,8 +83,21 @@ pub fn train(app_handle: AppHandle) -> Result<(), String> {
         // Define the drawings directory within data directory
         let drawings_dir = data_directory.join("drawings");
 
+        // Pick how the training set is assembled
+        let mode = match mnist_ratio {
+            Some(mnist_ratio) => DatasetMode::Replay { mnist_ratio },
+            None => DatasetMode::DrawingsOnly,
+        };
+
         // Load the dataset
-        let dataset = match ai::utils::create_dataset(&drawings_dir) {
+        let partial_drawings = partial_drawings.unwrap_or(true);
+        let dataset = match utils::create_dataset(
+            &drawings_dir,
+            mode,
+            partial_drawings,
+            model.labels(),
+            seed,
+        ) {
             Ok(d) => d,
             Err(e) => {
                 eprintln!("Failed to create dataset: {}", e);
@@ -76,18 +112,58 @@ pub fn train(app_handle: AppHandle) -> Result<(), String> {
 
         // Create Training Args
         let args = TrainingArgs {
-            epochs: 1,
+            epochs: epochs.unwrap_or(1),
             learning_rate: 0.01,
             batch_size: 10,
             save: Some(model_path.clone()),
             load: Some(model_path.clone()),
+            frozen_layers: frozen_layers.unwrap_or_default(),
+            patience,
+            log_dir: None,
+            seed,
+            distillation: None,
         };
 
         // Train the model
-        if let Err(e) = model.train(&dataset, &args, &mut vm) {
-            eprintln!("Failed to train model: {}", e);
+        let summary = match model.train(&dataset, &args, &mut vm) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Failed to train model: {}", e);
+                app_handle_clone
+                    .emit_all("training_error", e.to_string())
+                    .unwrap_or_else(|err| {
+                        eprintln!("Failed to emit training_error event: {}", err)
+                    });
+                return;
+            }
+        };
+
+        // Keep a copy of the trained model in the registry
+        let hyperparameters = Hyperparameters {
+            epochs: args.epochs,
+            learning_rate: args.learning_rate,
+            batch_size: args.batch_size,
+            frozen_layers: args.frozen_layers.clone(),
+            patience: args.patience,
+            mnist_ratio,
+            partial_drawings,
+            distillation: None,
+        };
+        let dataset_size = dataset.train_images.dim(0).unwrap_or_default();
+        let registered = open_registry().and_then(|mut registry| {
+            registry
+                .register_trained(
+                    &PathBuf::from(&model_path),
+                    hyperparameters,
+                    dataset_size,
+                    summary,
+                )
+                .map_err(|e| format!("Failed to register trained model: {}", e))
+        });
+        if let Err(e) = registered {
+            eprintln!("{}", e);
             app_handle_clone
-                .emit_all("training_error", e.to_string())
+                .emit_all("training_error", e)
                 .unwrap_or_else(|err| eprintln!("Failed to emit training_error event: {}", err));
             return;
         }
@@ -101,8 +177,17 @@ pub fn train(app_handle: AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// Predicts the label of a base64 encoded drawing, or of `strokes` rasterized with `raster`
+/// or the drawings store's config (see `save_drawing`). With `quantized` set, the prediction
+/// runs on the int8 model calibrated by `compare_quantization`, which fails when there is no
+/// up to date calibration.
 #[command]
-pub fn predict_from_data(image_data: String) -> Result<u32, InvokeError> {
+pub fn predict_from_data(
+    image_data: Option<String>,
+    quantized: Option<bool>,
+    strokes: Option<StrokeDrawing>,
+    raster: Option<RasterConfig>,
+) -> Result<String, InvokeError> {
     // Get the device
     let dev = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
 
@@ -115,23 +200,43 @@ pub fn predict_from_data(image_data: String) -> Result<u32, InvokeError> {
         InvokeError::from(e)
     })?;
 
-    let model =
-        ConvNet::new_from_file(&mut vm, &PathBuf::from(model_path)).expect("Failed to load model");
+    let model = ConvNet::new_from_file(&mut vm, &PathBuf::from(&model_path)).map_err(|e| {
+        eprintln!("Failed to load model: {}", e);
+        InvokeError::from(format!("Failed to load model: {}", e))
+    })?;
 
-    // Decode the base64 string
-    let image_bytes = base64::decode(image_data).expect("Failed to decode base64 string");
+    let quantized_model = if quantized.unwrap_or(false) {
+        let quantized_model = load_quantized(&model, &vm, Path::new(&model_path))
+            .map_err(|e| format!("Failed to load quantized model: {}", e))?;
+        Some(quantized_model)
+    } else {
+        None
+    };
 
-    // Load the image from bytes
-    let img = image::load_from_memory(&image_bytes).expect("Failed to load image from memory");
+    let img = match (strokes, image_data) {
+        (Some(strokes), _) => rasterize_strokes(&strokes, raster)?,
+        (None, Some(image_data)) => {
+            // Decode the base64 string
+            let image_bytes = base64::decode(image_data)
+                .map_err(|e| format!("Failed to decode base64 string: {}", e))?;
+
+            // Load the image from bytes
+            image::load_from_memory(&image_bytes)
+                .map_err(|e| format!("Failed to load image from memory: {}", e))?
+        }
+        (None, None) => return Err(InvokeError::from("Expected image data or strokes")),
+    };
 
     // Get image as tensor
-    let image =
-        ai::utils::image_to_formatted_tensor(img).expect("Failed to convert image to tensor");
+    let image = utils::image_to_formatted_tensor(img)
+        .map_err(|e| format!("Failed to convert image to tensor: {}", e))?;
 
     // Get the prediction
-    let prediction = model
-        .predict(&image, &dev)
-        .expect("Failed to predict image");
+    let prediction = match &quantized_model {
+        Some(quantized_model) => quantized_model.predict(&image),
+        None => model.predict(&image, &dev),
+    }
+    .map_err(|e| format!("Failed to predict image: {}", e))?;
 
     println!("Prediction: {}", prediction);
 
@@ -139,8 +244,199 @@ pub fn predict_from_data(image_data: String) -> Result<u32, InvokeError> {
     Ok(prediction)
 }
 
+/// Returns the labels the active model predicts, in output order.
+#[command]
+pub fn get_labels() -> Result<Vec<String>, String> {
+    let model_path = get_model_path()?;
+
+    let (_, labels) = metadata::read_spec_and_labels(&PathBuf::from(model_path))
+        .map_err(|e| format!("Failed to read model metadata: {}", e))?;
+
+    Ok(labels.labels().to_vec())
+}
+
+/// Grows the active model's output to predict `labels`, keeping everything it already learned,
+/// then fine-tunes only the label-sized layers on the user's drawings for `epochs` (1 by
+/// default) and registers the result as the new active model.
+#[command]
+pub async fn expand_labels(
+    labels: Vec<String>,
+    epochs: Option<usize>,
+) -> Result<ModelEntry, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let labels = LabelSet::new(labels).map_err(|e| e.to_string())?;
+        let model_path = get_model_path()?;
+        let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+
+        let mut vm = candle_nn::VarMap::new();
+        let model = ConvNet::expand_from_file(&mut vm, &PathBuf::from(&model_path), &labels)
+            .map_err(|e| format!("Failed to expand model: {}", e))?;
+
+        let dataset = utils::create_dataset(
+            &data_directory.join("drawings"),
+            DatasetMode::DrawingsOnly,
+            true,
+            &labels,
+            None,
+        )
+        .map_err(|e| format!("Failed to create dataset: {}", e))?;
+
+        // Everything but the new output rows is trained already
+        let label_sized_layers = model.spec().label_sized_layers();
+        let frozen_layers = model
+            .spec()
+            .layer_names()
+            .into_iter()
+            .filter(|layer| !label_sized_layers.contains(layer))
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        let args = TrainingArgs {
+            epochs: epochs.unwrap_or(1),
+            learning_rate: 0.01,
+            batch_size: 10,
+            load: None,
+            save: Some(model_path.clone()),
+            frozen_layers,
+            patience: None,
+            log_dir: None,
+            seed: None,
+            distillation: None,
+        };
+        let summary = model
+            .train(&dataset, &args, &mut vm)
+            .map_err(|e| format!("Failed to fine-tune expanded model: {}", e))?;
+
+        let hyperparameters = Hyperparameters {
+            epochs: args.epochs,
+            learning_rate: args.learning_rate,
+            batch_size: args.batch_size,
+            frozen_layers: args.frozen_layers.clone(),
+            partial_drawings: true,
+            ..Default::default()
+        };
+        let entry = open_registry()?
+            .register_trained(
+                &PathBuf::from(&model_path),
+                hyperparameters,
+                dataset.train_images.dim(0).unwrap_or_default(),
+                summary,
+            )
+            .map_err(|e| format!("Failed to register expanded model: {}", e))?;
+
+        println!(
+            "Expanded model to labels {:?}, registered as {}",
+            labels.labels(),
+            entry.id
+        );
+        Ok(entry)
+    })
+    .await
+    .map_err(|e| format!("Label expansion was interrupted: {}", e))?
+}
+
+/// Restores the model the active one was trained from and makes it the active registry entry.
+#[command]
+pub fn rollback_model() -> Result<(), String> {
+    let model_path = get_model_path()?;
+
+    let restored = open_registry()?
+        .rollback(&PathBuf::from(&model_path))
+        .map_err(|e| format!("Failed to roll back model: {}", e))?;
+
+    match restored {
+        Some(entry) => println!("Rolled back to model {}", entry.id),
+        None => println!("Rolled back model at {}", model_path),
+    }
+    Ok(())
+}
+
+/// Exports the active model as an ONNX graph to `path`, by default `exports/model.onnx` in the
+/// data directory, and returns where it was written.
+#[command]
+pub fn export_onnx(path: Option<String>) -> Result<String, String> {
+    let model_path = PathBuf::from(get_model_path()?);
+
+    let mut vm = candle_nn::VarMap::new();
+    let model = ConvNet::new_from_file(&mut vm, &model_path)
+        .map_err(|e| format!("Failed to load model: {}", e))?;
+
+    let export_path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let data_directory =
+                data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+            let exports_dir = data_directory.join("exports");
+            fs::create_dir_all(&exports_dir).map_err(|e| e.to_string())?;
+            exports_dir.join("model.onnx")
+        }
+    };
+
+    onnx::export(&model, &vm, &export_path)
+        .map_err(|e| format!("Failed to export ONNX model: {}", e))?;
+
+    println!("Exported ONNX model to {:?}", export_path);
+    Ok(export_path.to_string_lossy().to_string())
+}
+
+/// Calibrates int8 inference for the active model on up to `calibration_samples` of the
+/// user's drawings, saves the calibration for `predict_from_data` and reports how accuracy and
+/// latency compare to the f32 model.
+#[command]
+pub fn compare_quantization(
+    calibration_samples: Option<usize>,
+) -> Result<QuantizationReport, String> {
+    let dev = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+    let model_path = PathBuf::from(get_model_path()?);
+
+    let mut vm = candle_nn::VarMap::new();
+    let model = ConvNet::new_from_file(&mut vm, &model_path)
+        .map_err(|e| format!("Failed to load model: {}", e))?;
+
+    let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+    // Calibrate on the drawings as they were saved
+    let dataset = utils::create_dataset(
+        &data_directory.join("drawings"),
+        DatasetMode::DrawingsOnly,
+        false,
+        model.labels(),
+        None,
+    )
+    .map_err(|e| format!("Failed to create dataset: {}", e))?;
+
+    let (calibration, report) = quantize::compare(
+        &model,
+        &vm,
+        &dataset,
+        calibration_samples.unwrap_or(DEFAULT_CALIBRATION_SAMPLES),
+        100,
+        &dev,
+    )
+    .map_err(|e| format!("Failed to compare quantized model: {}", e))?;
+
+    calibration
+        .save(&model_path)
+        .map_err(|e| format!("Failed to save calibration: {}", e))?;
+
+    println!("Quantization report: {:?}", report);
+    Ok(report)
+}
+
+/// Builds the int8 model from the calibration saved next to the model at `model_path`.
+fn load_quantized(
+    model: &ConvNet,
+    vm: &candle_nn::VarMap,
+    model_path: &Path,
+) -> Result<QuantizedConvNet, String> {
+    let calibration = Calibration::load(model_path)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "the model has not been calibrated".to_string())?;
+
+    QuantizedConvNet::new(model, vm, &calibration).map_err(|e| e.to_string())
+}
+
 /// Helper function to get the model path from temp-assets directory
-fn get_model_path() -> Result<String, String> {
+pub(crate) fn get_model_path() -> Result<String, String> {
     // Get the data directory
     let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
 
@@ -1,3 +1,4 @@
+use crate::commands::model_commands::open_registry;
 use std::fs;
 use tauri::api::path::{data_dir, resource_dir};
 use tauri::{command, AppHandle, Manager};
@@ -54,5 +55,10 @@ pub fn reset_temp_assets_directory(app_handle: AppHandle) -> Result<(), String>
     })?;
     println!("Copied from {:?} to {:?}", model_source, model_destination);
 
+    // The working model is the bundled one again, so make it the active registry entry
+    open_registry()?
+        .register_pretrained(&model_source)
+        .map_err(|e| format!("Failed to register pretrained model: {}", e))?;
+
     Ok(())
 }
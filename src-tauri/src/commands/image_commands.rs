@@ -1,8 +1,13 @@
+use crate::commands::ai_commands::get_model_path;
 use base64;
-use image::ImageFormat;
+use image::{DynamicImage, ImageFormat};
 use std::fs;
+use std::path::PathBuf;
 use tauri::api::path::data_dir;
 use tauri::command;
+use train_an_ai_core::drawings::{self, ExportReport, ImportReport};
+use train_an_ai_core::strokes::{self, RasterConfig, RerenderReport, StrokeDrawing};
+use train_an_ai_core::{metadata, utils};
 
 #[command]
 pub fn get_input_image() -> Result<String, String> {
@@ -36,23 +41,37 @@ pub fn get_input_image() -> Result<String, String> {
     Ok(base64_image)
 }
 
+/// Saves a drawing of `symbol` to the drawings store, either as a base64 PNG data URL or as
+/// strokes. Strokes are rasterized with `raster` (or the store's config, see
+/// `rerender_drawings`) and kept next to the PNG so the drawing can be rendered again later.
 #[command]
-pub fn save_drawing(image_data: String, symbol: String) -> Result<(), String> {
-    // Remove the "data:image/png;base64," prefix
-    let base64_data = image_data
-        .split(',')
-        .nth(1)
-        .ok_or_else(|| "Invalid image data format".to_string())?;
-
-    // Decode the base64 string
-    let image_bytes = base64::decode(base64_data).map_err(|e| e.to_string())?;
-
-    // Load the image from bytes
-    let img = image::load_from_memory(&image_bytes)
-        .map_err(|e| format!("Failed to load image from memory: {}", e))?;
-
-    // Resize the image to 28x28 using `resize_exact` with nearest-neighbor filter
-    let resized_img = img.resize_exact(28, 28, image::imageops::FilterType::Nearest);
+pub fn save_drawing(
+    image_data: Option<String>,
+    symbol: String,
+    strokes: Option<StrokeDrawing>,
+    raster: Option<RasterConfig>,
+) -> Result<(), String> {
+    let resized_img = match (&strokes, image_data) {
+        (Some(strokes), _) => rasterize_strokes(strokes, raster)?,
+        (None, Some(image_data)) => {
+            // Remove the "data:image/png;base64," prefix
+            let base64_data = image_data
+                .split(',')
+                .nth(1)
+                .ok_or_else(|| "Invalid image data format".to_string())?;
+
+            // Decode the base64 string
+            let image_bytes = base64::decode(base64_data).map_err(|e| e.to_string())?;
+
+            // Load the image from bytes
+            let img = image::load_from_memory(&image_bytes)
+                .map_err(|e| format!("Failed to load image from memory: {}", e))?;
+
+            // Resize the image to the 28x28 input size
+            utils::resize_drawing(&img)
+        }
+        (None, None) => return Err("Expected image data or strokes".to_string()),
+    };
 
     // Get the data directory
     let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
@@ -71,6 +90,102 @@ pub fn save_drawing(image_data: String, symbol: String) -> Result<(), String> {
         .save_with_format(&filename, ImageFormat::Png)
         .map_err(|e| format!("Failed to save resized image: {}", e))?;
 
+    // Keep the strokes with the image, or drop those of the drawing it replaced
+    let strokes_path = strokes::strokes_path(&filename);
+    match &strokes {
+        Some(strokes) => strokes
+            .save(&strokes_path)
+            .map_err(|e| format!("Failed to save strokes: {}", e))?,
+        None if strokes_path.exists() => fs::remove_file(&strokes_path)
+            .map_err(|e| format!("Failed to remove outdated strokes: {}", e))?,
+        None => {}
+    }
+
     println!("Saved resized drawing: {:?}", filename);
     Ok(())
 }
+
+/// Checks `strokes` and renders them with `raster`, or with the config the drawings store is
+/// rendered with.
+pub(crate) fn rasterize_strokes(
+    strokes: &StrokeDrawing,
+    raster: Option<RasterConfig>,
+) -> Result<DynamicImage, String> {
+    let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+
+    strokes
+        .rasterize_for_store(&data_directory.join("drawings"), raster)
+        .map(DynamicImage::ImageLuma8)
+        .map_err(|e| format!("Failed to rasterize strokes: {}", e))
+}
+
+/// Renders every stored drawing that has strokes again with `raster`, which new drawings are
+/// then rendered with too, and rebuilds the training set from the store. Drawings saved
+/// without strokes are kept as they are and listed in the report.
+#[command]
+pub fn rerender_drawings(raster: RasterConfig) -> Result<RerenderReport, String> {
+    let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+    let model_path = PathBuf::from(get_model_path()?);
+
+    let (_, labels) = metadata::read_spec_and_labels(&model_path)
+        .map_err(|e| format!("Failed to read model metadata: {}", e))?;
+
+    let report = strokes::rerender_drawings(&data_directory.join("drawings"), &raster, &labels)
+        .map_err(|e| format!("Failed to re-render drawings: {}", e))?;
+
+    println!(
+        "Re-rendered {} drawings, {} without strokes",
+        report.rendered.len(),
+        report.without_strokes.len()
+    );
+    Ok(report)
+}
+
+/// Adds the labeled images in the folder or zip archive at `source` (a folder per label,
+/// `<label>/*.png`) to the drawings store, reporting which files were accepted and why the
+/// others were rejected.
+#[command]
+pub fn import_drawings(source: String) -> Result<ImportReport, String> {
+    let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+    let model_path = PathBuf::from(get_model_path()?);
+
+    let (_, labels) = metadata::read_spec_and_labels(&model_path)
+        .map_err(|e| format!("Failed to read model metadata: {}", e))?;
+
+    let report = drawings::import_drawings(
+        &PathBuf::from(source),
+        &data_directory.join("drawings"),
+        &labels,
+    )
+    .map_err(|e| format!("Failed to import drawings: {}", e))?;
+
+    println!(
+        "Imported {} drawings, rejected {}",
+        report.accepted.len(),
+        report.rejected.len()
+    );
+    Ok(report)
+}
+
+/// Exports the drawings store to `destination` as MNIST style IDX files in that folder
+/// (`format` "idx") or as a zip archive of PNGs with a manifest (`format` "zip").
+#[command]
+pub fn export_drawings(format: String, destination: String) -> Result<ExportReport, String> {
+    let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+    let model_path = PathBuf::from(get_model_path()?);
+
+    let (_, labels) = metadata::read_spec_and_labels(&model_path)
+        .map_err(|e| format!("Failed to read model metadata: {}", e))?;
+
+    let drawings_dir = data_directory.join("drawings");
+    let destination = PathBuf::from(destination);
+    let report = match format.as_str() {
+        "idx" => drawings::export_idx(&drawings_dir, &labels, &destination),
+        "zip" => drawings::export_zip(&drawings_dir, &labels, &destination),
+        other => return Err(format!("Unknown export format '{}'", other)),
+    }
+    .map_err(|e| format!("Failed to export drawings: {}", e))?;
+
+    println!("Exported {} drawings to {:?}", report.drawings, destination);
+    Ok(report)
+}
@@ -1,10 +1,10 @@
 use base64;
+use image::GrayImage;
 use image::ImageFormat;
-use image::{GrayImage, ImageBuffer, Luma};
-use imageproc::filter;
 use std::fs;
 use tauri::api::path::data_dir;
 use tauri::command;
+use train_an_ai_core::visualization;
 
 #[command]
 pub fn apply_conv_filter() -> Result<String, String> {
@@ -34,16 +34,8 @@ pub fn apply_conv_filter() -> Result<String, String> {
     // Convert to grayscale
     let gray_img: GrayImage = img.to_luma8();
 
-    // Define a convolution kernel (e.g., edge detection)
-    let kernel: [f32; 9] = [-1.0, -1.0, -1.0, -1.0, 8.0, -1.0, -1.0, -1.0, -1.0];
-
-    // Apply convolution using imageproc's convolution function
-    let conv_img: ImageBuffer<Luma<f32>, Vec<f32>> = filter::filter3x3(&gray_img, &kernel);
-
-    // Convert back to u8
-    let conv_img: GrayImage = ImageBuffer::from_fn(conv_img.width(), conv_img.height(), |x, y| {
-        Luma([conv_img.get_pixel(x, y).0[0].clamp(0.0, 255.0) as u8])
-    });
+    // Apply an edge detection convolution
+    let conv_img = visualization::edge_filter(&gray_img);
 
     // Encode the processed image to PNG format in memory using Cursor
     let mut buffer = std::io::Cursor::new(Vec::new());
@@ -85,7 +77,7 @@ pub fn apply_pooling_filter() -> Result<String, String> {
     let stride = 2;
 
     // Apply max pooling
-    let pooled_img = max_pooling(&gray_img, pool_size, stride)?;
+    let pooled_img = visualization::max_pooling(&gray_img, pool_size, stride);
 
     // Encode the processed image to PNG format in memory
     let mut buffer = std::io::Cursor::new(Vec::new());
@@ -134,33 +126,3 @@ pub fn apply_fully_connected_filter() -> Result<String, String> {
     // Return the Base64 string
     Ok(base64_image)
 }
-
-/// Simple max pooling implementation
-fn max_pooling(img: &GrayImage, pool_size: usize, stride: usize) -> Result<GrayImage, String> {
-    let (width, height) = img.dimensions();
-    let pooled_width = ((width - pool_size as u32) / stride as u32) + 1;
-    let pooled_height = ((height - pool_size as u32) / stride as u32) + 1;
-
-    let mut pooled_img = ImageBuffer::new(pooled_width, pooled_height);
-
-    for y in 0..pooled_height {
-        for x in 0..pooled_width {
-            let mut max_val = 0u8;
-            for dy in 0..pool_size {
-                for dx in 0..pool_size {
-                    let px = x as usize * stride + dx;
-                    let py = y as usize * stride + dy;
-                    if px < width as usize && py < height as usize {
-                        let p = img.get_pixel(px as u32, py as u32).0[0];
-                        if p > max_val {
-                            max_val = p;
-                        }
-                    }
-                }
-            }
-            pooled_img.put_pixel(x as u32, y as u32, Luma([max_val]));
-        }
-    }
-
-    Ok(pooled_img)
-}
@@ -2,8 +2,12 @@ pub mod ai_commands;
 pub mod file_commands;
 pub mod image_commands;
 pub mod image_filter_commands;
+pub mod model_commands;
+pub mod stream_commands;
 
 pub use ai_commands::*;
 pub use file_commands::*;
 pub use image_commands::*;
 pub use image_filter_commands::*;
+pub use model_commands::*;
+pub use stream_commands::*;
@@ -0,0 +1,278 @@
+use crate::commands::ai_commands::get_model_path;
+use candle_core::Device;
+use serde::Serialize;
+use std::path::PathBuf;
+use tauri::api::path::data_dir;
+use tauri::command;
+use train_an_ai_core::architecture::ArchitectureSpec;
+use train_an_ai_core::distill::{self, DistillationReport, DistillationSettings};
+use train_an_ai_core::history::TrainingHistory;
+use train_an_ai_core::metadata;
+use train_an_ai_core::model::TrainingArgs;
+use train_an_ai_core::prune::{self, PruningReport};
+use train_an_ai_core::registry::{
+    Hyperparameters, ModelDiff, ModelEntry, ModelRegistry, PRETRAINED_ID,
+};
+use train_an_ai_core::utils::{self, DatasetMode};
+
+#[derive(Serialize)]
+pub struct ModelList {
+    pub active: Option<String>,
+    pub models: Vec<ModelEntry>,
+}
+
+#[derive(Serialize)]
+pub struct RunHistory {
+    pub model: ModelEntry,
+    pub history: TrainingHistory,
+}
+
+#[derive(Serialize)]
+pub struct PrunedModel {
+    pub model: ModelEntry,
+    pub report: PruningReport,
+}
+
+#[derive(Serialize)]
+pub struct DistilledModel {
+    pub model: ModelEntry,
+    pub report: DistillationReport,
+}
+
+#[command]
+pub fn list_models() -> Result<ModelList, String> {
+    let registry = open_registry()?;
+
+    Ok(ModelList {
+        active: registry.active().map(|m| m.id.clone()),
+        models: registry.models().to_vec(),
+    })
+}
+
+/// Makes the registered model `id` the one used for training and prediction.
+#[command]
+pub fn activate_model(id: String) -> Result<(), String> {
+    let mut registry = open_registry()?;
+    let model_path = get_model_path()?;
+
+    registry
+        .activate(&id, &PathBuf::from(model_path))
+        .map_err(|e| format!("Failed to activate model: {}", e))?;
+
+    println!("Activated model {}", id);
+    Ok(())
+}
+
+#[command]
+pub fn rename_model(id: String, name: String) -> Result<(), String> {
+    let mut registry = open_registry()?;
+
+    registry
+        .rename(&id, &name)
+        .map_err(|e| format!("Failed to rename model: {}", e))
+}
+
+#[command]
+pub fn delete_model(id: String) -> Result<(), String> {
+    let mut registry = open_registry()?;
+
+    registry
+        .delete(&id)
+        .map_err(|e| format!("Failed to delete model: {}", e))?;
+
+    println!("Deleted model {}", id);
+    Ok(())
+}
+
+#[command]
+pub fn diff_models(a: String, b: String) -> Result<ModelDiff, String> {
+    let registry = open_registry()?;
+
+    registry
+        .diff(&a, &b)
+        .map_err(|e| format!("Failed to diff models: {}", e))
+}
+
+/// Returns the metric history of every registered model that was trained in the app, oldest
+/// first.
+#[command]
+pub fn get_training_histories() -> Result<Vec<RunHistory>, String> {
+    let registry = open_registry()?;
+
+    let mut histories = Vec::new();
+    for model in registry.models() {
+        let history = registry
+            .history(&model.id)
+            .map_err(|e| format!("Failed to read training history of {}: {}", model.id, e))?;
+        if let Some(history) = history {
+            histories.push(RunHistory {
+                model: model.clone(),
+                history,
+            });
+        }
+    }
+
+    Ok(histories)
+}
+
+/// Helper function to open the model registry in the data directory
+pub(crate) fn open_registry() -> Result<ModelRegistry, String> {
+    let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+
+    ModelRegistry::open(&data_directory.join("models"))
+        .map_err(|e| format!("Failed to open model registry: {}", e))
+}
+
+/// Removes the `amount` share (0.5 by default) of the weakest conv channels and fc neurons of
+/// the active model, fine-tunes the result on the user's drawings for `epochs` and registers
+/// it as the new active model, reporting size and accuracy before and after.
+#[command]
+pub async fn prune_model(
+    amount: Option<f32>,
+    epochs: Option<usize>,
+) -> Result<PrunedModel, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let dev = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+        let model_path = PathBuf::from(get_model_path()?);
+        let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+
+        let (_, labels) = metadata::read_spec_and_labels(&model_path)
+            .map_err(|e| format!("Failed to read model metadata: {}", e))?;
+        let dataset = utils::create_dataset(
+            &data_directory.join("drawings"),
+            DatasetMode::DrawingsOnly,
+            true,
+            &labels,
+            None,
+        )
+        .map_err(|e| format!("Failed to create dataset: {}", e))?;
+
+        let pruned_path = data_directory
+            .join("temp-assets")
+            .join("pruned.safetensors");
+        let report = prune::prune_and_fine_tune(
+            &model_path,
+            &pruned_path,
+            amount.unwrap_or(0.5),
+            epochs.unwrap_or(1),
+            &dataset,
+            &dev,
+        )
+        .map_err(|e| format!("Failed to prune model: {}", e))?;
+
+        let hyperparameters = Hyperparameters {
+            epochs: report.epochs,
+            learning_rate: report.learning_rate,
+            batch_size: report.batch_size,
+            partial_drawings: true,
+            ..Default::default()
+        };
+        let mut registry = open_registry()?;
+        let entry = registry
+            .register_trained(
+                &pruned_path,
+                hyperparameters,
+                dataset.train_images.dim(0).unwrap_or_default(),
+                report.fine_tuning.clone(),
+            )
+            .map_err(|e| format!("Failed to register pruned model: {}", e))?;
+        registry
+            .activate(&entry.id, &model_path)
+            .map_err(|e| format!("Failed to activate pruned model: {}", e))?;
+
+        println!("Pruned model registered as {}", entry.id);
+        Ok(PrunedModel {
+            model: entry,
+            report,
+        })
+    })
+    .await
+    .map_err(|e| format!("Pruning was interrupted: {}", e))?
+}
+
+/// Trains the compact student architecture for `epochs` on the user's drawings, with the
+/// pretrained model as teacher at `temperature` and soft targets weighted by `alpha` (see
+/// `DistillationSettings` for the defaults). The student is registered next to the pretrained
+/// model, as derived from it, and the active model stays as it was.
+#[command]
+pub async fn distill_model(
+    epochs: Option<usize>,
+    temperature: Option<f64>,
+    alpha: Option<f64>,
+) -> Result<DistilledModel, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let dev = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+        let data_directory = data_dir().ok_or_else(|| "Data directory not found".to_string())?;
+
+        let mut registry = open_registry()?;
+        let teacher_path = registry.model_path(PRETRAINED_ID);
+        let (_, labels) = metadata::read_spec_and_labels(&teacher_path)
+            .map_err(|e| format!("Failed to read teacher metadata: {}", e))?;
+        let dataset = utils::create_dataset(
+            &data_directory.join("drawings"),
+            DatasetMode::DrawingsOnly,
+            true,
+            &labels,
+            None,
+        )
+        .map_err(|e| format!("Failed to create dataset: {}", e))?;
+
+        let defaults = DistillationSettings::default();
+        let settings = DistillationSettings {
+            temperature: temperature.unwrap_or(defaults.temperature),
+            alpha: alpha.unwrap_or(defaults.alpha),
+        };
+        let args = TrainingArgs {
+            epochs: epochs.unwrap_or(1),
+            learning_rate: 0.01,
+            batch_size: 10,
+            load: None,
+            save: None,
+            frozen_layers: Vec::new(),
+            patience: None,
+            log_dir: None,
+            seed: None,
+            distillation: None,
+        };
+        let hyperparameters = Hyperparameters {
+            epochs: args.epochs,
+            learning_rate: args.learning_rate,
+            batch_size: args.batch_size,
+            partial_drawings: true,
+            distillation: Some(settings.clone()),
+            ..Default::default()
+        };
+
+        let student_path = data_directory
+            .join("temp-assets")
+            .join("student.safetensors");
+        let report = distill::distill(
+            &teacher_path,
+            &ArchitectureSpec::student(),
+            &student_path,
+            &settings,
+            args,
+            &dataset,
+            &dev,
+        )
+        .map_err(|e| format!("Failed to distill model: {}", e))?;
+
+        let entry = registry
+            .register_derived(
+                &student_path,
+                PRETRAINED_ID,
+                hyperparameters,
+                dataset.train_images.dim(0).unwrap_or_default(),
+                report.training.clone(),
+            )
+            .map_err(|e| format!("Failed to register student model: {}", e))?;
+
+        println!("Student model registered as {}", entry.id);
+        Ok(DistilledModel {
+            model: entry,
+            report,
+        })
+    })
+    .await
+    .map_err(|e| format!("Distillation was interrupted: {}", e))?
+}
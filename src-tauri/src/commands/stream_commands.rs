@@ -0,0 +1,329 @@
+use crate::commands::ai_commands::get_model_path;
+use candle_core::{Device, D};
+use candle_nn::{ops, VarMap};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{command, AppHandle, Manager, State};
+use train_an_ai_core::model::ConvNet;
+use train_an_ai_core::utils;
+
+/// How long the stream waits for a newer frame before predicting the latest one.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Longest a pushed frame waits for its prediction while frames keep arriving, so the
+/// probabilities follow the drawing instead of waiting for the visitor to pause.
+const MAX_DELAY: Duration = Duration::from_millis(150);
+
+/// Shortest time between two `prediction_update` events.
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The worker thread exits after this long without frames and is restarted by the next one.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Serialize)]
+pub struct LabelProbability {
+    pub label: String,
+    pub probability: f32,
+}
+
+/// Payload of the `prediction_update` event.
+#[derive(Clone, Serialize)]
+pub struct PredictionUpdate {
+    /// Number of the frame predicted, counting every frame pushed. Frames in between were
+    /// superseded before they were predicted.
+    pub frame: u64,
+    pub label: String,
+    /// One entry per label, in the model's output order.
+    pub probabilities: Vec<LabelProbability>,
+}
+
+struct Frame {
+    number: u64,
+    image_data: String,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// The newest frame not predicted yet. Pushing a frame replaces it.
+    latest: Option<Frame>,
+    frames_pushed: u64,
+    /// When the frame in `latest` and the oldest frame it superseded arrived.
+    received_at: Option<Instant>,
+    pending_since: Option<Instant>,
+    worker_running: bool,
+    stopped: bool,
+}
+
+/// Coalesces the canvas frames pushed while a visitor draws and predicts them on a worker
+/// thread, emitting at most one `prediction_update` event per `MIN_UPDATE_INTERVAL`.
+/// Managed by the app, see `main`.
+#[derive(Default)]
+pub struct PredictionStream {
+    state: Mutex<StreamState>,
+    frame_pushed: Condvar,
+    /// Kept here rather than by the worker so a restarted worker doesn't reload the model.
+    model: Mutex<Option<CachedModel>>,
+}
+
+impl PredictionStream {
+    fn lock(&self) -> MutexGuard<'_, StreamState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Waits until the latest frame is due for prediction and takes it. Returns `None` once
+    /// the stream is stopped or has been idle for `IDLE_TIMEOUT`, after which the worker
+    /// should exit.
+    fn next_frame(&self, last_update: Option<Instant>) -> Option<Frame> {
+        let mut state = self.lock();
+
+        loop {
+            if state.stopped {
+                state.worker_running = false;
+                return None;
+            }
+
+            let (Some(received_at), Some(pending_since)) = (state.received_at, state.pending_since)
+            else {
+                let (guard, timeout) = self
+                    .frame_pushed
+                    .wait_timeout(state, IDLE_TIMEOUT)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                state = guard;
+                if timeout.timed_out() && state.latest.is_none() {
+                    state.worker_running = false;
+                    return None;
+                }
+                continue;
+            };
+
+            let due = due_at(received_at, pending_since, last_update);
+            let now = Instant::now();
+            if now >= due {
+                state.received_at = None;
+                state.pending_since = None;
+                if let Some(frame) = state.latest.take() {
+                    return Some(frame);
+                }
+                continue;
+            }
+
+            state = self
+                .frame_pushed
+                .wait_timeout(state, due - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+}
+
+/// When a frame received at `received_at`, superseding frames pending since `pending_since`,
+/// is predicted: after `DEBOUNCE` without newer frames but no later than `MAX_DELAY` after the
+/// first of them, and never sooner than `MIN_UPDATE_INTERVAL` after the `last_update`.
+fn due_at(received_at: Instant, pending_since: Instant, last_update: Option<Instant>) -> Instant {
+    let due = (received_at + DEBOUNCE).min(pending_since + MAX_DELAY);
+    match last_update {
+        Some(last_update) => due.max(last_update + MIN_UPDATE_INTERVAL),
+        None => due,
+    }
+}
+
+/// The active model, reloaded when the file at its path changes (e.g. after training).
+struct CachedModel {
+    path: PathBuf,
+    modified: SystemTime,
+    model: ConvNet,
+    _varmap: VarMap,
+}
+
+impl CachedModel {
+    fn load(path: PathBuf) -> Result<Self, String> {
+        let modified = modified_time(&path)?;
+        let mut varmap = VarMap::new();
+        let model = ConvNet::new_from_file(&mut varmap, &path)
+            .map_err(|e| format!("Failed to load model: {}", e))?;
+
+        Ok(Self {
+            path,
+            modified,
+            model,
+            _varmap: varmap,
+        })
+    }
+
+    fn is_current(&self, path: &Path) -> Result<bool, String> {
+        Ok(self.path == path && self.modified == modified_time(path)?)
+    }
+}
+
+/// Queues a base64 encoded canvas frame for live prediction, replacing any frame that hasn't
+/// been predicted yet. Results arrive as `prediction_update` events, failures as
+/// `prediction_error` events.
+#[command]
+pub fn push_prediction_frame(
+    app_handle: AppHandle,
+    stream: State<'_, PredictionStream>,
+    image_data: String,
+) -> Result<u64, String> {
+    let mut state = stream.lock();
+
+    let now = Instant::now();
+    state.frames_pushed += 1;
+    let number = state.frames_pushed;
+    state.latest = Some(Frame { number, image_data });
+    state.received_at = Some(now);
+    state.pending_since.get_or_insert(now);
+    state.stopped = false;
+
+    if !state.worker_running {
+        state.worker_running = true;
+        std::thread::spawn(move || predict_frames(app_handle));
+    }
+
+    stream.frame_pushed.notify_one();
+    Ok(number)
+}
+
+/// Drops the pending frame, if any, and stops the worker once it has finished the frame it
+/// may be predicting.
+#[command]
+pub fn stop_prediction_stream(stream: State<'_, PredictionStream>) -> Result<(), String> {
+    let mut state = stream.lock();
+
+    state.latest = None;
+    state.received_at = None;
+    state.pending_since = None;
+    state.stopped = true;
+
+    stream.frame_pushed.notify_one();
+    Ok(())
+}
+
+/// Worker loop of the prediction stream, running until `next_frame` tells it to exit.
+fn predict_frames(app_handle: AppHandle) {
+    let stream = app_handle.state::<PredictionStream>();
+    let dev = Device::cuda_if_available(0).unwrap_or(Device::Cpu);
+
+    let mut last_update = None;
+
+    while let Some(frame) = stream.next_frame(last_update) {
+        let mut cached = stream
+            .model
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match predict_frame(&mut cached, &frame, &dev) {
+            Ok(update) => app_handle
+                .emit_all("prediction_update", update)
+                .unwrap_or_else(|err| eprintln!("Failed to emit prediction_update event: {}", err)),
+            Err(e) => {
+                eprintln!("Failed to predict frame {}: {}", frame.number, e);
+                app_handle
+                    .emit_all("prediction_error", e)
+                    .unwrap_or_else(|err| {
+                        eprintln!("Failed to emit prediction_error event: {}", err)
+                    });
+            }
+        }
+        last_update = Some(Instant::now());
+    }
+}
+
+fn predict_frame(
+    cached: &mut Option<CachedModel>,
+    frame: &Frame,
+    dev: &Device,
+) -> Result<PredictionUpdate, String> {
+    let model_path = PathBuf::from(get_model_path()?);
+    let current = match cached.take() {
+        Some(model) if model.is_current(&model_path)? => model,
+        _ => CachedModel::load(model_path)?,
+    };
+    let model = &cached.insert(current).model;
+
+    let image_bytes = base64::decode(&frame.image_data)
+        .map_err(|e| format!("Failed to decode base64 string: {}", e))?;
+    let img = image::load_from_memory(&image_bytes)
+        .map_err(|e| format!("Failed to load image from memory: {}", e))?;
+
+    let probabilities = utils::image_to_formatted_tensor(img)
+        .and_then(|image| model.logits(&image.unsqueeze(0)?.to_device(dev)?))
+        .and_then(|logits| {
+            ops::softmax(&logits, D::Minus1)?
+                .squeeze(0)?
+                .to_vec1::<f32>()
+        })
+        .map_err(|e| format!("Failed to predict image: {}", e))?;
+
+    let labels = model.labels().labels();
+    let best = probabilities
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i)
+        .unwrap_or_default();
+
+    Ok(PredictionUpdate {
+        frame: frame.number,
+        label: labels[best].clone(),
+        probabilities: labels
+            .iter()
+            .zip(probabilities)
+            .map(|(label, probability)| LabelProbability {
+                label: label.clone(),
+                probability,
+            })
+            .collect(),
+    })
+}
+
+fn modified_time(path: &Path) -> Result<SystemTime, String> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| format!("Failed to read {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_is_due_after_the_debounce() {
+        let start = Instant::now();
+
+        assert_eq!(due_at(start, start, None), start + DEBOUNCE);
+    }
+
+    #[test]
+    fn newer_frames_delay_the_prediction_up_to_the_max_delay() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        // Each frame pushes the debounce back...
+        assert_eq!(
+            due_at(start + ms(40), start, None),
+            start + ms(40) + DEBOUNCE
+        );
+        // ...until the first pending frame has waited MAX_DELAY
+        assert_eq!(due_at(start + ms(140), start, None), start + MAX_DELAY);
+    }
+
+    #[test]
+    fn updates_are_rate_limited() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        let last_update = start + ms(20);
+        assert_eq!(
+            due_at(start, start, Some(last_update)),
+            last_update + MIN_UPDATE_INTERVAL
+        );
+
+        // An update long enough ago doesn't hold the frame back
+        let last_update = start.checked_sub(ms(500)).unwrap_or(start);
+        assert_eq!(due_at(start, start, Some(last_update)), start + DEBOUNCE);
+    }
+}
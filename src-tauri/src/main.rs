@@ -1,24 +1,44 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod ai;
 mod commands;
 
 use commands::*;
 
 fn main() {
     tauri::Builder::default()
+        .manage(PredictionStream::default())
         .invoke_handler(tauri::generate_handler![
             // AI Commands
             train,
             predict_from_data,
+            get_labels,
+            expand_labels,
+            rollback_model,
+            export_onnx,
+            compare_quantization,
             // Image Commands
             save_drawing,
             apply_conv_filter,
             apply_pooling_filter,
             apply_fully_connected_filter,
             get_input_image,
+            rerender_drawings,
+            import_drawings,
+            export_drawings,
             // File Commands
             reset_temp_assets_directory,
+            // Model Commands
+            list_models,
+            activate_model,
+            rename_model,
+            delete_model,
+            diff_models,
+            get_training_histories,
+            prune_model,
+            distill_model,
+            // Stream Commands
+            push_prediction_frame,
+            stop_prediction_stream,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
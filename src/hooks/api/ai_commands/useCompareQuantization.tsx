@@ -0,0 +1,47 @@
+// src/hooks/api/ai_commands/useCompareQuantization.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export interface QuantizationReport {
+  calibration_samples: number;
+  f32_accuracy: number;
+  int8_accuracy: number;
+  f32_latency_ms: number;
+  int8_latency_ms: number;
+  f32_weight_bytes: number;
+  int8_weight_bytes: number;
+}
+
+export function useCompareQuantization(): UseMutationResult<
+  QuantizationReport,
+  Error,
+  { calibrationSamples?: number } | void,
+  unknown
+> {
+  return useMutation<
+    QuantizationReport,
+    Error,
+    { calibrationSamples?: number } | void,
+    unknown
+  >({
+    mutationFn: async (payload) => {
+      try {
+        // Calibrates the int8 model and compares it against the f32 one
+        return await invoke<QuantizationReport>("compare_quantization", {
+          calibrationSamples: payload?.calibrationSamples,
+        });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error comparing quantized model:", error);
+    },
+  });
+}
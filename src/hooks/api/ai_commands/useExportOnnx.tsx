@@ -0,0 +1,30 @@
+// src/hooks/api/ai_commands/useExportOnnx.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export function useExportOnnx(): UseMutationResult<
+  string,
+  Error,
+  { path?: string } | void,
+  unknown
+> {
+  return useMutation<string, Error, { path?: string } | void, unknown>({
+    mutationFn: async (payload) => {
+      try {
+        // Resolves to the path the ONNX model was written to
+        return await invoke<string>("export_onnx", { path: payload?.path });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error exporting ONNX model:", error);
+    },
+  });
+}
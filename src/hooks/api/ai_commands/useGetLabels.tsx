@@ -0,0 +1,15 @@
+// src/hooks/api/ai_commands/useGetLabels.ts
+import { useQuery, UseQueryResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export function useGetLabels(): UseQueryResult<string[], string> {
+  return useQuery<string[], string>({
+    queryKey: ["get_labels"],
+    queryFn: async () => {
+      // Labels the active model predicts, in output order
+      const labels: string[] = await invoke("get_labels");
+      return labels;
+    },
+    retry: 1,
+  });
+}
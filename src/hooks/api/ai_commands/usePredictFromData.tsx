@@ -1,17 +1,29 @@
 // usePredictFromData.ts
 import { useMutation } from "@tanstack/react-query";
 import { invoke } from "@tauri-apps/api/tauri";
+import {
+  RasterConfig,
+  StrokeDrawing,
+} from "@/hooks/api/image_commands/useSaveDrawing";
 
 interface PredictFromDataPayload {
-  imageData: string;
+  imageData?: string;
+  // Runs the int8 model calibrated by compare_quantization
+  quantized?: boolean;
+  // Predicted instead of imageData when set
+  strokes?: StrokeDrawing;
+  raster?: RasterConfig;
 }
 
 export function usePredictFromData() {
-  return useMutation<number, Error, PredictFromDataPayload>({
-    mutationFn: async ({ imageData }) => {
+  return useMutation<string, Error, PredictFromDataPayload>({
+    mutationFn: async ({ imageData, quantized, strokes, raster }) => {
       // Invoke the backend command with the provided imageData
-      const prediction: number = await invoke("predict_from_data", {
+      const prediction: string = await invoke("predict_from_data", {
         imageData,
+        quantized,
+        strokes,
+        raster,
       });
       return prediction;
     },
@@ -0,0 +1,30 @@
+// src/hooks/api/ai_commands/useRollbackModel.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export function useRollbackModel(): UseMutationResult<
+  void,
+  Error,
+  void,
+  unknown
+> {
+  return useMutation<void, Error, void, unknown>({
+    mutationFn: async () => {
+      try {
+        // Restore the model the active one was trained from
+        await invoke<void>("rollback_model");
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error rolling back model:", error);
+    },
+  });
+}
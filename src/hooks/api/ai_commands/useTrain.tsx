@@ -3,13 +3,34 @@ import { listen } from "@tauri-apps/api/event";
 import { useMutation } from "@tanstack/react-query";
 import { useState, useEffect } from "react";
 
+interface TrainPayload {
+  // Share of each batch made up of replayed MNIST samples, in [0, 1)
+  mnistRatio?: number;
+  // Also train on unfinished versions of drawings saved with strokes, true by default
+  partialDrawings?: boolean;
+  // Layers to leave untouched, e.g. ["c1", "c2", "c3"] to train only the classifier
+  frozenLayers?: string[];
+  epochs?: number;
+  // Stop once test accuracy hasn't improved for this many epochs
+  patience?: number;
+  // Makes the run reproducible
+  seed?: number;
+}
+
 export function useTrain() {
   const [isComplete, setIsComplete] = useState(false);
   const [error, setError] = useState<string | null>(null);
 
-  const mutation = useMutation<void, string, void>({
-    mutationFn: async () => {
-      await invoke("train");
+  const mutation = useMutation<void, string, TrainPayload | void>({
+    mutationFn: async (payload) => {
+      await invoke("train", {
+        mnistRatio: payload?.mnistRatio,
+        partialDrawings: payload?.partialDrawings,
+        frozenLayers: payload?.frozenLayers,
+        epochs: payload?.epochs,
+        patience: payload?.patience,
+        seed: payload?.seed,
+      });
     },
   });
 
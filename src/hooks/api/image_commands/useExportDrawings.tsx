@@ -0,0 +1,47 @@
+// src/hooks/api/image_commands/useExportDrawings.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export type ExportFormat = "idx" | "zip";
+
+export interface ExportReport {
+  files: string[];
+  drawings: number;
+  // number of drawings per label
+  per_label: Record<string, number>;
+}
+
+export function useExportDrawings(): UseMutationResult<
+  ExportReport,
+  Error,
+  { format: ExportFormat; destination: string },
+  unknown
+> {
+  return useMutation<
+    ExportReport,
+    Error,
+    { format: ExportFormat; destination: string },
+    unknown
+  >({
+    mutationFn: async ({ format, destination }) => {
+      try {
+        // destination is a folder for "idx" and the archive's path for "zip"
+        return await invoke<ExportReport>("export_drawings", {
+          format,
+          destination,
+        });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error exporting drawings:", error);
+    },
+  });
+}
@@ -0,0 +1,36 @@
+// src/hooks/api/image_commands/useImportDrawings.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export interface ImportReport {
+  // stored is relative to the drawings store, e.g. "3/<hash>.png"
+  accepted: { source: string; label: string; stored: string }[];
+  rejected: { source: string; reason: string }[];
+}
+
+export function useImportDrawings(): UseMutationResult<
+  ImportReport,
+  Error,
+  { source: string },
+  unknown
+> {
+  return useMutation<ImportReport, Error, { source: string }, unknown>({
+    mutationFn: async ({ source }) => {
+      try {
+        // source is a folder or zip archive with a folder per label
+        return await invoke<ImportReport>("import_drawings", { source });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error importing drawings:", error);
+    },
+  });
+}
@@ -0,0 +1,41 @@
+// src/hooks/api/image_commands/useRerenderDrawings.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+import { RasterConfig } from "./useSaveDrawing";
+
+export interface RerenderReport {
+  config: RasterConfig;
+  // Drawings rendered again from their strokes
+  rendered: string[];
+  // Drawings saved as images only, which were left as they are
+  without_strokes: string[];
+  // Number of drawings in the rebuilt training set
+  dataset_size: number;
+}
+
+export function useRerenderDrawings(): UseMutationResult<
+  RerenderReport,
+  Error,
+  RasterConfig,
+  unknown
+> {
+  return useMutation<RerenderReport, Error, RasterConfig, unknown>({
+    mutationFn: async (raster) => {
+      try {
+        // New drawings are rendered with this config from now on too
+        return await invoke<RerenderReport>("rerender_drawings", { raster });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error re-rendering drawings:", error);
+    },
+  });
+}
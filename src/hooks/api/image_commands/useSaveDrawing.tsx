@@ -1,15 +1,44 @@
 import { useMutation } from "@tanstack/react-query";
 import { invoke } from "@tauri-apps/api/tauri";
 
+export interface StrokePoint {
+  x: number;
+  y: number;
+  // Milliseconds since the drawing started
+  t: number;
+  // In [0, 1], 0.5 for mice
+  pressure: number;
+}
+
+// A drawing as polylines in the coordinates of the canvas it was drawn on
+export interface StrokeDrawing {
+  canvas_width: number;
+  canvas_height: number;
+  strokes: { points: StrokePoint[] }[];
+}
+
+export interface RasterConfig {
+  // Width and height of the rendered image in pixels
+  size: number;
+  // In canvas units, at the default pressure
+  line_width: number;
+  antialias: boolean;
+  // Standard deviation in pixels of a Gaussian blur, 0 for none
+  blur?: number;
+}
+
 interface SaveDrawingPayload {
-  imageData: string;
+  imageData?: string;
   symbol: string;
+  // Rendered by the backend, and stored so the drawing can be rendered again later
+  strokes?: StrokeDrawing;
+  raster?: RasterConfig;
 }
 
 export function useSaveDrawing() {
   return useMutation<void, Error, SaveDrawingPayload>({
-    mutationFn: async ({ imageData, symbol }) => {
-      await invoke("save_drawing", { imageData, symbol });
+    mutationFn: async ({ imageData, symbol, strokes, raster }) => {
+      await invoke("save_drawing", { imageData, symbol, strokes, raster });
     },
   });
 }
@@ -0,0 +1,46 @@
+// src/hooks/api/model_commands/useDistillModel.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+import { ModelStats } from "./usePruneModel";
+
+export interface DistilledModel {
+  model: { id: string; name: string; created_at: number };
+  report: {
+    settings: { temperature: number; alpha: number };
+    teacher: ModelStats;
+    student: ModelStats;
+  };
+}
+
+type DistillPayload = { epochs?: number; temperature?: number; alpha?: number };
+
+export function useDistillModel(): UseMutationResult<
+  DistilledModel,
+  Error,
+  DistillPayload | void,
+  unknown
+> {
+  return useMutation<DistilledModel, Error, DistillPayload | void, unknown>({
+    mutationFn: async (payload) => {
+      try {
+        // Trains a compact student from the pretrained model and registers it without activating it
+        return await invoke<DistilledModel>("distill_model", {
+          epochs: payload?.epochs,
+          temperature: payload?.temperature,
+          alpha: payload?.alpha,
+        });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error distilling model:", error);
+    },
+  });
+}
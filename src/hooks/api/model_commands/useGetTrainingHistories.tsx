@@ -0,0 +1,34 @@
+// src/hooks/api/model_commands/useGetTrainingHistories.ts
+import { useQuery, UseQueryResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export interface BatchMetrics {
+  epoch: number;
+  // Position of the batch within its epoch
+  batch: number;
+  loss: number;
+}
+
+export interface EpochMetrics {
+  epoch: number;
+  train_loss: number;
+  // Share of the test set predicted correctly, in [0, 1]
+  test_accuracy: number;
+}
+
+export interface RunHistory {
+  model: { id: string; name: string; created_at: number };
+  history: { batches: BatchMetrics[]; epochs: EpochMetrics[] };
+}
+
+export function useGetTrainingHistories(): UseQueryResult<RunHistory[], string> {
+  return useQuery<RunHistory[], string>({
+    queryKey: ["get_training_histories"],
+    queryFn: async () => {
+      // Every run trained in the app, oldest first
+      const histories: RunHistory[] = await invoke("get_training_histories");
+      return histories;
+    },
+    retry: 1,
+  });
+}
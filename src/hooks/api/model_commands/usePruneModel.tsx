@@ -0,0 +1,55 @@
+// src/hooks/api/model_commands/usePruneModel.ts
+import { useMutation, UseMutationResult } from "@tanstack/react-query";
+import { invoke } from "@tauri-apps/api/tauri";
+
+export interface ModelStats {
+  parameters: number;
+  file_bytes: number;
+  // Test accuracy in percent
+  test_accuracy: number;
+}
+
+export interface PrunedModel {
+  model: { id: string; name: string; created_at: number };
+  report: {
+    amount: number;
+    layers: { name: string; before: number; after: number }[];
+    before: ModelStats;
+    after: ModelStats;
+  };
+}
+
+export function usePruneModel(): UseMutationResult<
+  PrunedModel,
+  Error,
+  { amount?: number; epochs?: number } | void,
+  unknown
+> {
+  return useMutation<
+    PrunedModel,
+    Error,
+    { amount?: number; epochs?: number } | void,
+    unknown
+  >({
+    mutationFn: async (payload) => {
+      try {
+        // Prunes and fine-tunes the active model, which the pruned one then replaces
+        return await invoke<PrunedModel>("prune_model", {
+          amount: payload?.amount,
+          epochs: payload?.epochs,
+        });
+      } catch (error) {
+        if (typeof error === "string") {
+          throw new Error(error);
+        } else if (error instanceof Error) {
+          throw new Error(error.message);
+        } else {
+          throw new Error("An unknown error occurred.");
+        }
+      }
+    },
+    onError: (error) => {
+      console.error("Error pruning model:", error);
+    },
+  });
+}
@@ -0,0 +1,59 @@
+// src/hooks/api/stream_commands/usePredictionStream.ts
+import { invoke } from "@tauri-apps/api/tauri";
+import { listen } from "@tauri-apps/api/event";
+import { useCallback, useEffect, useState } from "react";
+
+export interface PredictionUpdate {
+  // Number of the pushed frame this prediction is for; frames pushed in between were skipped
+  frame: number;
+  label: string;
+  probabilities: { label: string; probability: number }[];
+}
+
+// Pushes canvas frames while the visitor draws. The backend only predicts the latest frame and
+// sends at most about ten updates a second, so every stroke can be pushed as is.
+export function usePredictionStream() {
+  const [update, setUpdate] = useState<PredictionUpdate | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  const pushFrame = useCallback(async (imageData: string) => {
+    try {
+      await invoke<number>("push_prediction_frame", { imageData });
+    } catch (error) {
+      console.error("Error pushing prediction frame:", error);
+    }
+  }, []);
+
+  const stop = useCallback(async () => {
+    try {
+      await invoke("stop_prediction_stream");
+    } catch (error) {
+      console.error("Error stopping prediction stream:", error);
+    }
+  }, []);
+
+  useEffect(() => {
+    const updateUnlisten = listen(
+      "prediction_update",
+      (event: { payload: PredictionUpdate }) => {
+        setUpdate(event.payload);
+        setError(null);
+      }
+    );
+
+    const errorUnlisten = listen(
+      "prediction_error",
+      (event: { payload: string }) => {
+        setError(event.payload);
+      }
+    );
+
+    return () => {
+      updateUnlisten.then((fn) => fn());
+      errorUnlisten.then((fn) => fn());
+      invoke("stop_prediction_stream").catch(() => {});
+    };
+  }, []);
+
+  return { pushFrame, stop, update, error };
+}
@@ -1,6 +1,10 @@
 import { useState, useRef, useEffect } from "react";
 import { useNavigate } from "react-router-dom";
-import { useSaveDrawing } from "@/hooks/api/image_commands/useSaveDrawing";
+import {
+  StrokePoint,
+  useSaveDrawing,
+} from "@/hooks/api/image_commands/useSaveDrawing";
+import { useGetLabels } from "@/hooks/api/ai_commands/useGetLabels";
 import { Button } from "@/components/ui/button";
 import {
   Card,
@@ -12,12 +16,17 @@ import {
 import { Progress } from "@/components/ui/progress";
 import { ArrowRight, Pencil } from "lucide-react";
 
-const symbols = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+const defaultSymbols = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
 
 export default function CollectData() {
+  const { data: labels } = useGetLabels();
+  const symbols = labels ?? defaultSymbols;
   const [currentSymbol, setCurrentSymbol] = useState(0);
   const canvasRef = useRef<HTMLCanvasElement>(null);
   const [isDrawing, setIsDrawing] = useState(false);
+  // Strokes of the current drawing, so the backend can render it again at any resolution
+  const strokesRef = useRef<{ points: StrokePoint[] }[]>([]);
+  const drawingStartRef = useRef<number | null>(null);
   const navigate = useNavigate();
 
   useEffect(() => {
@@ -42,6 +51,7 @@ export default function CollectData() {
 
   const startDrawing = (e: React.MouseEvent<HTMLCanvasElement>) => {
     setIsDrawing(true);
+    strokesRef.current.push({ points: [] });
     draw(e);
   };
 
@@ -62,6 +72,14 @@ export default function CollectData() {
       const rect = canvas.getBoundingClientRect();
       const x = e.clientX - rect.left;
       const y = e.clientY - rect.top;
+      const now = performance.now();
+      drawingStartRef.current ??= now;
+      strokesRef.current[strokesRef.current.length - 1]?.points.push({
+        x: Math.min(Math.max(x, 0), canvas.width),
+        y: Math.min(Math.max(y, 0), canvas.height),
+        t: now - drawingStartRef.current,
+        pressure: 0.5,
+      });
       ctx.lineTo(x, y);
       ctx.stroke();
       ctx.beginPath();
@@ -76,7 +94,16 @@ export default function CollectData() {
       const symbol = symbols[currentSymbol];
 
       try {
-        await saveDrawingMutation.mutateAsync({ imageData, symbol });
+        const strokes = {
+          canvas_width: canvas.width,
+          canvas_height: canvas.height,
+          strokes: strokesRef.current.filter(
+            (stroke) => stroke.points.length > 0
+          ),
+        };
+        await saveDrawingMutation.mutateAsync({ imageData, symbol, strokes });
+        strokesRef.current = [];
+        drawingStartRef.current = null;
 
         const ctx = canvas.getContext("2d");
         if (ctx) {